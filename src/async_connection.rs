@@ -0,0 +1,147 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use std::convert::TryFrom;
+use crate::codec::{self, KdbRequest, Payload, SynchronisationType};
+use crate::intern::SymbolTable;
+use crate::{decode_message, message_size, uncompressed_size, RequestHandler};
+
+/// Non-blocking counterpart of `KdbConnection` for use inside a tokio runtime.
+pub struct AsyncKdbConnection<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
+    tcp_connection_read: R,
+    tcp_connection_write: W,
+    request_handler: Option<RequestHandler>,
+    symbols: Option<SymbolTable>,
+    max_message_size: u32,
+}
+
+impl AsyncKdbConnection<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn new<T: ToSocketAddrs>(address: T) -> std::io::Result<AsyncKdbConnection<OwnedReadHalf, OwnedWriteHalf>> {
+        let (tcp_connection_read, tcp_connection_write) = TcpStream::connect(address).await?.into_split();
+        Ok(AsyncKdbConnection::from_streams(tcp_connection_read, tcp_connection_write))
+    }
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> AsyncKdbConnection<R, W> {
+    pub fn from_streams(tcp_connection_read: R, tcp_connection_write: W) -> AsyncKdbConnection<R, W> {
+        AsyncKdbConnection { tcp_connection_read, tcp_connection_write, request_handler: None, symbols: None, max_message_size: u32::MAX }
+    }
+
+    /// See `KdbConnection::set_max_message_size`
+    pub fn set_max_message_size(&mut self, size: u32) {
+        self.max_message_size = size;
+    }
+
+    /// See `KdbConnection::intern_symbols`
+    pub fn intern_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    /// See `KdbConnection::set_request_handler`
+    pub fn set_request_handler<F: FnMut(SynchronisationType, Payload) -> Result<Payload, String> + Send + 'static>(&mut self, handler: F) {
+        self.request_handler = Some(Box::new(handler));
+    }
+
+    /// Sends handshake byte
+    pub async fn connect(&mut self, user: &str, pwd: &str) -> std::io::Result<()> {
+        let mut user_pass = format!("{}:{}", user, pwd).into_bytes();
+        user_pass.push(3);
+        user_pass.push(0);
+        self.tcp_connection_write.write_all(&user_pass).await?;
+        let mut buf = [0u8; 1];
+        self.tcp_connection_read.read_exact(&mut buf).await?;
+        Ok(())
+    }
+
+    pub async fn query(&mut self, msg: KdbRequest<'_>) -> Result<Payload, String> {
+        let vec: Vec<u8> = msg.to_bytes();
+        self.tcp_connection_write.write_all(vec.as_slice()).await.map_err(|x| x.to_string())?;
+        self.receive().await
+    }
+
+    /// See `KdbConnection::call`
+    pub async fn call(&mut self, payload: &Payload) -> Result<Payload, String> {
+        self.write_message(SynchronisationType::Sync, payload).await?;
+        self.receive().await
+    }
+
+    /// Sends an async message, q does not reply to these
+    pub async fn send_async(&mut self, payload: &Payload) -> Result<(), String> {
+        self.write_message(SynchronisationType::Async, payload).await
+    }
+
+    pub(crate) async fn write_message(&mut self, message_type: SynchronisationType, payload: &Payload) -> Result<(), String> {
+        payload.check()?;
+        self.tcp_connection_write.write_all(&codec::encode_message(message_type, payload)).await.map_err(|x| x.to_string())
+    }
+
+    /// Writes every request before reading any reply, see `KdbConnection::pipeline`
+    pub async fn pipeline(&mut self, msgs: &[KdbRequest<'_>]) -> Result<Vec<Payload>, String> {
+        let vec: Vec<u8> = msgs.iter().flat_map(|x| x.to_bytes()).collect();
+        self.tcp_connection_write.write_all(vec.as_slice()).await.map_err(|x| x.to_string())?;
+        let mut payloads = Vec::with_capacity(msgs.len());
+        for _ in msgs {
+            payloads.push(self.receive().await?);
+        }
+        Ok(payloads)
+    }
+
+    /// Reads the next message of any type without dispatching it
+    pub async fn receive_message(&mut self) -> Result<(SynchronisationType, Payload), String> {
+        let mut header = [0u8; 8];
+        self.tcp_connection_read.read_exact(&mut header).await.map_err(|x| x.to_string())?;
+        let mut buf = vec![0; message_size(&header, self.max_message_size)?];
+        buf[0..8].copy_from_slice(&header);
+        self.tcp_connection_read.read_exact(&mut buf[8..]).await.map_err(|x| x.to_string())?;
+        uncompressed_size(&buf, self.max_message_size)?;
+        Ok((SynchronisationType::try_from(header[1])?, decode_message(buf, self.symbols.as_ref())?))
+    }
+
+    async fn receive(&mut self) -> Result<Payload, String> {
+        loop {
+            match self.receive_message().await? {
+                (SynchronisationType::Response, payload) => return Ok(payload),
+                (message_type, payload) => self.handle_request(message_type, payload).await?,
+            }
+        }
+    }
+
+    pub(crate) async fn handle_request(&mut self, message_type: SynchronisationType, payload: Payload) -> Result<(), String> {
+        let result = match self.request_handler.as_mut() {
+            Some(handler) => handler(message_type, payload),
+            None => Err(String::from("nyi")),
+        };
+        if message_type == SynchronisationType::Sync {
+            let response = result.and_then(|x| x.check().map(|_| x)).unwrap_or_else(|x| Payload::error(&x));
+            self.tcp_connection_write.write_all(&codec::encode_message(SynchronisationType::Response, &response)).await.map_err(|x| x.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::async_connection::AsyncKdbConnection;
+    use crate::codec::{KdbRequest, KdbString, Payload, VectorAttribute};
+
+    #[tokio::test]
+    pub async fn test_connect() {
+        let (client_read, mut server_write) = tokio::io::duplex(64);
+        let (mut server_read, client_write) = tokio::io::duplex(64);
+        let mut kdb_connection = AsyncKdbConnection::from_streams(client_read, client_write);
+
+        tokio::io::AsyncWriteExt::write_all(&mut server_write, &[3]).await.unwrap();
+        kdb_connection.connect("MOCK_USER", "MOCK_PASS").await.unwrap();
+        let mut written = vec![0u8; 21];
+        tokio::io::AsyncReadExt::read_exact(&mut server_read, &mut written).await.unwrap();
+        assert_eq!(written, b"MOCK_USER:MOCK_PASS\x03\x00");
+
+        tokio::io::AsyncWriteExt::write_all(&mut server_write, &hex::decode("010200001a0000000a000c00000069276d736f6d657175657279").unwrap()).await.unwrap();
+        let payload = kdb_connection.query(KdbRequest::new("somequery")).await.unwrap();
+        assert_eq!(payload, Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("i'msomequery")));
+
+        let mut written = vec![0u8; 23];
+        tokio::io::AsyncReadExt::read_exact(&mut server_read, &mut written).await.unwrap();
+        assert_eq!(written, hex::decode("01010000170000000a0009000000736f6d657175657279").unwrap());
+    }
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use iron_kdb::KdbConnection;
+use iron_kdb::export::{query_pages, CsvWriter, NdjsonWriter, TableWriter};
+
+const USAGE: &str = "usage: iron-export host:port[:user[:password]] query output [--format csv|ndjson|parquet] [--page-size rows]
+  output is a file, or - for standard output. The format defaults to the output's extension, else csv.";
+
+struct Options {
+    target: String,
+    query: String,
+    output: String,
+    format: String,
+    page_size: usize,
+}
+
+fn main() {
+    let options = match parse(std::env::args().skip(1).collect()) {
+        Ok(x) => x,
+        Err(x) => {
+            eprintln!("{}\n{}", x, USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(x) = run(&options) {
+        eprintln!("{}", x);
+        std::process::exit(1);
+    }
+}
+
+fn parse(arguments: Vec<String>) -> Result<Options, String> {
+    let mut positional = Vec::new();
+    let (mut format, mut page_size) = (None, 100_000);
+    let mut arguments = arguments.into_iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--format" => format = Some(arguments.next().ok_or("--format needs a value")?),
+            "--page-size" => page_size = arguments.next().and_then(|x| x.parse().ok()).filter(|x| *x > 0).ok_or("--page-size needs a positive number")?,
+            "-h" | "--help" => return Err(String::new()),
+            _ => positional.push(argument),
+        }
+    }
+    if positional.len() != 3 {
+        return Err(String::from("Expected a target, a query and an output"));
+    }
+    let output = positional.pop().unwrap_or_default();
+    let format = format.unwrap_or_else(|| match output.rsplit_once('.').map(|x| x.1) {
+        Some("ndjson") | Some("jsonl") => String::from("ndjson"),
+        Some("parquet") => String::from("parquet"),
+        _ => String::from("csv"),
+    });
+    let query = positional.pop().unwrap_or_default();
+    let target = positional.pop().unwrap_or_default();
+    Ok(Options { target, query, output, format, page_size })
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    // The password is last so it may contain colons
+    let mut parts = options.target.splitn(4, ':');
+    let host = parts.next().filter(|x| !x.is_empty()).unwrap_or("localhost");
+    let port: u16 = parts.next().and_then(|x| x.parse().ok()).ok_or("Expected host:port")?;
+    let (user, password) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut connection = KdbConnection::new((host, port)).map_err(|x| format!("{}:{}: {}", host, port, x))?;
+    connection.connect(user, password).map_err(|x| format!("{}:{}: {}", host, port, x))?;
+
+    let output: Box<dyn Write + Send> = match options.output.as_str() {
+        "-" => Box::new(std::io::stdout()),
+        path => Box::new(File::create(path).map_err(|x| format!("{}: {}", path, x))?),
+    };
+    let output = BufWriter::new(output);
+    let mut writer: Box<dyn TableWriter> = match options.format.as_str() {
+        "csv" => Box::new(CsvWriter::new(output)),
+        "ndjson" => Box::new(NdjsonWriter::new(output)),
+        #[cfg(feature = "parquet")]
+        "parquet" => Box::new(iron_kdb::export::ParquetWriter::new(output)),
+        #[cfg(not(feature = "parquet"))]
+        "parquet" => return Err(String::from("Writing parquet needs iron-export built with the parquet feature")),
+        x => return Err(format!("Unknown format {}", x)),
+    };
+    query_pages(&mut connection, &options.query, options.page_size, |x| writer.write_table(&x))?;
+    writer.finish()
+}
@@ -0,0 +1,135 @@
+use std::path::PathBuf;
+use iron_kdb::KdbConnection;
+use iron_kdb::codec::KdbRequest;
+use iron_kdb::console::ConsoleSize;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+const USAGE: &str = "usage: iron-q host:port[:user[:password]]";
+
+fn main() {
+    let target = match std::env::args().nth(1) {
+        Some(x) if x != "-h" && x != "--help" => x,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    if let Err(x) = run(&target) {
+        eprintln!("{}", x);
+        std::process::exit(1);
+    }
+}
+
+fn run(target: &str) -> Result<(), String> {
+    // The password is last so it may contain colons
+    let mut parts = target.splitn(4, ':');
+    let host = parts.next().filter(|x| !x.is_empty()).unwrap_or("localhost");
+    let port: u16 = parts.next().and_then(|x| x.parse().ok()).ok_or(USAGE)?;
+    let (user, password) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut connection = KdbConnection::new((host, port)).map_err(|x| format!("{}:{}: {}", host, port, x))?;
+    connection.connect(user, password).map_err(|x| format!("{}:{}: {}", host, port, x))?;
+
+    let mut editor = DefaultEditor::new().map_err(|x| x.to_string())?;
+    let history = std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".iron_q_history"));
+    if let Some(history) = &history {
+        // There's no history the first time
+        let _ = editor.load_history(history);
+    }
+    let mut size = ConsoleSize::default();
+    while let Some(input) = read_input(&mut editor)? {
+        let query = match input.trim() {
+            "" => continue,
+            "\\\\" => break,
+            // The console size is ours, not the server's
+            x if x == "\\c" || x.starts_with("\\c ") => {
+                match console_size(&x[2..]) {
+                    Some(x) => size = x,
+                    None => println!("{} {}", size.lines, size.columns),
+                }
+                continue;
+            }
+            // Everything else goes to the server, `\t` included so it times only the evaluation
+            x => x,
+        };
+        match connection.query(KdbRequest::new(query)) {
+            Ok(payload) => {
+                let text = payload.console(size).to_string();
+                if !text.is_empty() {
+                    println!("{}", text);
+                }
+            }
+            Err(x) => println!("'{}", x),
+        }
+    }
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(|x| x.to_string())?;
+    }
+    Ok(())
+}
+
+/// Reads one expression, taking more lines while a bracket or string is left open. `None` at the end of input.
+fn read_input(editor: &mut DefaultEditor) -> Result<Option<String>, String> {
+    let mut input = String::new();
+    loop {
+        match editor.readline(if input.is_empty() { "q)" } else { "  " }) {
+            Ok(line) => {
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                input.push_str(&line);
+            }
+            // Ctrl-C abandons the expression, like q
+            Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(x) => return Err(x.to_string()),
+        }
+        if is_complete(&input) {
+            let _ = editor.add_history_entry(input.as_str());
+            return Ok(Some(input));
+        }
+    }
+}
+
+/// Lines and columns of `\c lines columns`
+fn console_size(arguments: &str) -> Option<ConsoleSize> {
+    let mut arguments = arguments.split_whitespace().map(str::parse);
+    match (arguments.next(), arguments.next()) {
+        (Some(Ok(lines)), Some(Ok(columns))) => Some(ConsoleSize { lines, columns }),
+        _ => None,
+    }
+}
+
+fn is_complete(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in input.chars() {
+        match (in_string, c) {
+            (true, _) if escaped => escaped = false,
+            (true, '\\') => escaped = true,
+            (true, '"') => in_string = false,
+            (true, _) => {}
+            (false, '"') => in_string = true,
+            (false, '(' | '[' | '{') => depth += 1,
+            (false, ')' | ']' | '}') => depth -= 1,
+            _ => {}
+        }
+    }
+    // Too many closing brackets is a syntax error for q to report
+    !in_string && depth <= 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::is_complete;
+
+    #[test]
+    pub fn test_is_complete() {
+        assert!(is_complete("1+1"));
+        assert!(!is_complete("f:{[x]"));
+        assert!(is_complete("f:{[x]\n x+1}"));
+        assert!(!is_complete("\"a{\\\""));
+        assert!(is_complete("\"a{\""));
+    }
+}
@@ -0,0 +1,297 @@
+use std::convert::TryInto;
+use std::fmt::Display;
+use crate::codec::{KdbString, Payload, VectorAttribute};
+
+/// A general list, `list![1i64, 2.5, "sym"]`. Each item is anything convertible to a `Payload`,
+/// the first that isn't is the error.
+#[macro_export]
+macro_rules! list {
+    ($($item:expr),* $(,)?) => {
+        $crate::builder::list(vec![$($crate::builder::item($item)),*])
+    };
+}
+
+/// A dictionary with symbol keys and a general list of values, `dict!{"a" => 1i64, "b" => 2.5}`
+#[macro_export]
+macro_rules! dict {
+    ($($key:expr => $value:expr),* $(,)?) => {
+        $crate::builder::dictionary(vec![$(($crate::builder::symbol($key), $crate::builder::item($value))),*])
+    };
+}
+
+/// Builds a table a column at a time, `TableBuilder::new().col("sym", vec!["a", "b"]).col("px", vec![1.5, 2.5]).build()`.
+/// Columns added with `key` make it a keyed table.
+#[derive(Debug, Default)]
+pub struct TableBuilder {
+    keys: Vec<(String, Result<Payload, String>)>,
+    columns: Vec<(String, Result<Payload, String>)>,
+}
+
+impl TableBuilder {
+    pub fn new() -> TableBuilder {
+        TableBuilder::default()
+    }
+
+    /// Adds a column, a vector or anything convertible to one such as `vec![1.5, 2.5]`
+    pub fn col<S: Into<String>, C: TryInto<Payload>>(mut self, name: S, column: C) -> TableBuilder where C::Error: Display {
+        self.columns.push((name.into(), item(column)));
+        self
+    }
+
+    /// Adds a key column
+    pub fn key<S: Into<String>, C: TryInto<Payload>>(mut self, name: S, column: C) -> TableBuilder where C::Error: Display {
+        self.keys.push((name.into(), item(column)));
+        self
+    }
+
+    /// The table, unless a column isn't a vector, names aren't unique or the columns differ in
+    /// length
+    pub fn build(self) -> Result<Payload, String> {
+        let columns = flip(self.columns)?;
+        match self.keys.is_empty() {
+            true => Ok(columns),
+            false => keyed_table(flip(self.keys)?, columns),
+        }
+    }
+}
+
+fn flip(columns: Vec<(String, Result<Payload, String>)>) -> Result<Payload, String> {
+    if columns.is_empty() {
+        return Err(String::from("A table needs at least one column"));
+    }
+    let (mut names, mut values): (Vec<String>, Vec<Payload>) = (Vec::new(), Vec::new());
+    for (name, column) in columns {
+        let column = column.map_err(|x| format!("Column {}: {}", name, x))?;
+        if !(0..98).contains(&column.type_byte()) {
+            return Err(format!("Column {} is type {}, not a vector", name, column.type_byte()));
+        }
+        if names.contains(&name) {
+            return Err(format!("Column {} appears twice", name));
+        }
+        if let Some(first) = values.first() {
+            if first.count() != column.count() {
+                return Err(format!("Column {} has {} rows but {} has {}", name, column.count(), names[0], first.count()));
+            }
+        }
+        names.push(name);
+        values.push(column);
+    }
+    Ok(table(&names, values))
+}
+
+/// A keyed table, `keys!values`, from tables of key and value columns. They must have the same
+/// rows and no column in common.
+pub fn keyed_table(keys: Payload, values: Payload) -> Result<Payload, String> {
+    let (key_names, _) = table_parts(&keys)?;
+    let (names, _) = table_parts(&values)?;
+    if let Some(name) = key_names.iter().find(|x| names.contains(x)) {
+        return Err(format!("Column {} is both a key and a value", name));
+    }
+    if keys.count() != values.count() {
+        return Err(format!("{} keys for {} rows", keys.count(), values.count()));
+    }
+    Ok(Payload::Dictionary(Box::new(keys), Box::new(values)))
+}
+
+/// Column names and values of a `Payload::Table`
+pub(crate) fn table_parts(table: &Payload) -> Result<(&[KdbString], &[Payload]), String> {
+    match table {
+        Payload::Table(_, dictionary) => match dictionary.as_ref() {
+            Payload::Dictionary(names, values) => match (names.as_ref(), values.as_ref()) {
+                (Payload::SymbolVector(_, names), Payload::List(_, values)) if names.len() == values.len() => Ok((names, values)),
+                _ => Err(String::from("Table columns aren't a list of symbols and a list of vectors")),
+            },
+            x => Err(format!("Table holds type {} instead of a dictionary", x.type_byte())),
+        },
+        x => Err(format!("Expected a table, got type {}", x.type_byte())),
+    }
+}
+
+/// A table (`flip columns!values`) from its column names and vectors
+pub(crate) fn table<S: AsRef<[u8]>>(columns: &[S], values: Vec<Payload>) -> Payload {
+    let names = columns.iter().map(|x| KdbString::from(x.as_ref())).collect();
+    Payload::Table(VectorAttribute::NoAttribute, Box::new(Payload::Dictionary(
+        Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, names)),
+        Box::new(Payload::List(VectorAttribute::NoAttribute, values)))))
+}
+
+#[doc(hidden)]
+pub fn item<T: TryInto<Payload>>(value: T) -> Result<Payload, String> where T::Error: Display {
+    value.try_into().map_err(|x| x.to_string())
+}
+
+#[doc(hidden)]
+pub fn symbol<S: AsRef<str>>(name: S) -> KdbString {
+    KdbString::from(name.as_ref())
+}
+
+#[doc(hidden)]
+pub fn list(items: Vec<Result<Payload, String>>) -> Result<Payload, String> {
+    Ok(Payload::List(VectorAttribute::NoAttribute, items.into_iter().collect::<Result<_, _>>()?))
+}
+
+#[doc(hidden)]
+pub fn dictionary(entries: Vec<(KdbString, Result<Payload, String>)>) -> Result<Payload, String> {
+    let (mut keys, mut values) = (Vec::with_capacity(entries.len()), Vec::with_capacity(entries.len()));
+    for (key, value) in entries {
+        values.push(value.map_err(|x| format!("{}: {}", key, x))?);
+        keys.push(key);
+    }
+    Ok(Payload::Dictionary(
+        Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, keys)),
+        Box::new(Payload::List(VectorAttribute::NoAttribute, values))))
+}
+
+impl From<bool> for Payload {
+    fn from(value: bool) -> Self {
+        Payload::Bool(value)
+    }
+}
+
+impl From<u8> for Payload {
+    fn from(value: u8) -> Self {
+        Payload::Byte(value)
+    }
+}
+
+impl From<i16> for Payload {
+    fn from(value: i16) -> Self {
+        Payload::Short(value as u16)
+    }
+}
+
+impl From<i32> for Payload {
+    fn from(value: i32) -> Self {
+        Payload::Int(value as u32)
+    }
+}
+
+impl From<i64> for Payload {
+    fn from(value: i64) -> Self {
+        Payload::Long(value as u64)
+    }
+}
+
+impl From<f32> for Payload {
+    fn from(value: f32) -> Self {
+        Payload::Real(value)
+    }
+}
+
+impl From<f64> for Payload {
+    fn from(value: f64) -> Self {
+        Payload::Float(value)
+    }
+}
+
+impl From<Vec<bool>> for Payload {
+    fn from(value: Vec<bool>) -> Self {
+        Payload::BoolVector(VectorAttribute::NoAttribute, value)
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(value: Vec<u8>) -> Self {
+        Payload::ByteVector(VectorAttribute::NoAttribute, value)
+    }
+}
+
+impl From<Vec<i16>> for Payload {
+    fn from(value: Vec<i16>) -> Self {
+        Payload::ShortVector(VectorAttribute::NoAttribute, value.into_iter().map(|x| x as u16).collect())
+    }
+}
+
+impl From<Vec<i32>> for Payload {
+    fn from(value: Vec<i32>) -> Self {
+        Payload::IntVector(VectorAttribute::NoAttribute, value.into_iter().map(|x| x as u32).collect())
+    }
+}
+
+impl From<Vec<i64>> for Payload {
+    fn from(value: Vec<i64>) -> Self {
+        Payload::LongVector(VectorAttribute::NoAttribute, value.into_iter().map(|x| x as u64).collect())
+    }
+}
+
+impl From<Vec<f32>> for Payload {
+    fn from(value: Vec<f32>) -> Self {
+        Payload::RealVector(VectorAttribute::NoAttribute, value)
+    }
+}
+
+impl From<Vec<f64>> for Payload {
+    fn from(value: Vec<f64>) -> Self {
+        Payload::FloatVector(VectorAttribute::NoAttribute, value)
+    }
+}
+
+/// Text is a symbol
+impl From<&str> for Payload {
+    fn from(value: &str) -> Self {
+        Payload::Symbol(KdbString::from(value))
+    }
+}
+
+impl From<String> for Payload {
+    fn from(value: String) -> Self {
+        Payload::Symbol(KdbString::from(value))
+    }
+}
+
+impl From<Vec<&str>> for Payload {
+    fn from(value: Vec<&str>) -> Self {
+        Payload::SymbolVector(VectorAttribute::NoAttribute, value.into_iter().map(KdbString::from).collect())
+    }
+}
+
+impl From<Vec<String>> for Payload {
+    fn from(value: Vec<String>) -> Self {
+        Payload::SymbolVector(VectorAttribute::NoAttribute, value.into_iter().map(KdbString::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::{keyed_table, TableBuilder};
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::builder::table;
+    use crate::fixtures::symbol_vector;
+
+    #[test]
+    pub fn test_table_builder() {
+        let trades = TableBuilder::new().col("sym", vec!["a", "b"]).col("px", vec![1.5, 2.5]).build().unwrap();
+        assert_eq!(trades, table(&["sym", "px"], vec![symbol_vector(&["a", "b"]), Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, 2.5])]));
+
+        let keyed = TableBuilder::new().key("sym", vec!["a"]).col("size", vec![10i64]).build().unwrap();
+        assert_eq!(keyed, Payload::Dictionary(
+            Box::new(table(&["sym"], vec![symbol_vector(&["a"])])),
+            Box::new(table(&["size"], vec![Payload::LongVector(VectorAttribute::NoAttribute, vec![10])]))));
+
+        assert_eq!(TableBuilder::new().col("a", vec![1i64]).col("b", vec![1i64, 2]).build(), Err(String::from("Column b has 2 rows but a has 1")));
+        assert!(TableBuilder::new().col("a", vec![1i64]).col("a", vec![2i64]).build().is_err());
+        assert!(TableBuilder::new().col("a", 1i64).build().is_err());
+        assert!(TableBuilder::new().col("é", vec!["é"]).build().is_ok());
+        assert!(TableBuilder::new().key("a", vec![1i64, 2]).col("b", vec![1i64]).build().is_err());
+        assert!(TableBuilder::new().build().is_err());
+        let values = TableBuilder::new().col("a", vec![1i64]).build().unwrap();
+        assert!(keyed_table(values.clone(), values).is_err());
+    }
+
+    #[test]
+    pub fn test_macros() {
+        assert_eq!(crate::list![1i64, "a", vec![true]].unwrap(), Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Long(1),
+            Payload::Symbol(KdbString::from("a")),
+            Payload::BoolVector(VectorAttribute::NoAttribute, vec![true])]));
+        assert_eq!(crate::list![].unwrap(), Payload::List(VectorAttribute::NoAttribute, vec![]));
+        assert_eq!(crate::dict!{"a" => 1.5, "b" => crate::list![2i32].unwrap()}.unwrap(), Payload::Dictionary(
+            Box::new(symbol_vector(&["a", "b"])),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![
+                Payload::Float(1.5),
+                Payload::List(VectorAttribute::NoAttribute, vec![Payload::Int(2)])]))));
+        assert_eq!(crate::dict!{"é" => "ü"}.unwrap(), Payload::Dictionary(
+            Box::new(symbol_vector(&["é"])),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![Payload::Symbol(KdbString::from("ü"))]))));
+    }
+}
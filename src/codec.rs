@@ -1,7 +1,10 @@
+use std::borrow::{Borrow, Cow};
 use std::convert::{TryInto, TryFrom};
-use ascii::{AsciiStr, AsAsciiStrError, AsciiString};
-use std::array::TryFromSliceError;
-use crate::codec::VectorAttribute::{Sorted, Unique, Grouped, NoAttribute};
+use std::fmt::{Debug, Display, Formatter};
+use std::iter::FromIterator;
+use std::str::Utf8Error;
+use crate::codec::VectorAttribute::{Sorted, Unique, Parted, Grouped, NoAttribute};
+use crate::intern::{Interner, SymbolId, SymbolTable};
 
 const HEADER_LEN: u32 = 8;
 const TYPE_LEN: u32 = 1;
@@ -9,12 +12,14 @@ const ATTRIBUTE_LEN: u32 = 1;
 const VECTOR_LEN: u32 = 4;
 const PADDING_BYTES: [u8; 2] = [0, 0];
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum VectorAttribute {
     NoAttribute = 0,
     Sorted = 1,
     Unique = 2,
-    Grouped = 3,
+    /// `p#`, equal items are contiguous
+    Parted = 3,
+    Grouped = 4,
 }
 
 impl TryFrom<u8> for VectorAttribute {
@@ -25,7 +30,8 @@ impl TryFrom<u8> for VectorAttribute {
             0 => Ok(NoAttribute),
             1 => Ok(Sorted),
             2 => Ok(Unique),
-            3 => Ok(Grouped),
+            3 => Ok(Parted),
+            4 => Ok(Grouped),
             _ => Err(format!("Value {} out of range.", value))
         }
     }
@@ -37,11 +43,38 @@ enum Architecture {
     LittleEndian = 1,
 }
 
-#[derive(Copy, Clone)]
-enum SynchronisationType {
-    //TODO: Async = 0,
+/// Message type carried in byte 1 of the IPC header
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SynchronisationType {
+    Async = 0,
     Sync = 1,
-    //Response = 2,
+    Response = 2,
+}
+
+impl TryFrom<u8> for SynchronisationType {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(SynchronisationType::Async),
+            1 => Ok(SynchronisationType::Sync),
+            2 => Ok(SynchronisationType::Response),
+            _ => Err(format!("Unknown message type {}.", value))
+        }
+    }
+}
+
+/// Frames an encoded payload as an uncompressed little endian IPC message
+pub fn encode_message(synchronisation_type: SynchronisationType, payload: &Payload) -> Vec<u8> {
+    let mut ret_val = Vec::with_capacity(HEADER_LEN as usize + payload.encoded_len());
+    ret_val.push(Architecture::LittleEndian as u8);
+    ret_val.push(synchronisation_type as u8);
+    ret_val.extend_from_slice(&PADDING_BYTES);
+    ret_val.extend_from_slice(&[0; 4]);
+    payload.encode_into(&mut ret_val);
+    let len = ret_val.len() as u32;
+    ret_val[4..8].copy_from_slice(&len.to_le_bytes());
+    ret_val
 }
 
 pub struct KdbRequest<'a> {
@@ -50,17 +83,18 @@ pub struct KdbRequest<'a> {
     /// Byte 1
     synchronisation_type: SynchronisationType,
 
-    request: &'a AsciiStr,
+    request: &'a [u8],
 }
 
 
 impl<'a> KdbRequest<'a> {
-    pub fn new(request: &'a str) -> Result<KdbRequest, AsAsciiStrError> {
-        Ok(KdbRequest {
+    /// A query sent as a char vector, any text or bytes
+    pub fn new<S: AsRef<[u8]> + ?Sized>(request: &'a S) -> KdbRequest<'a> {
+        KdbRequest {
             architecture: Architecture::LittleEndian,
             synchronisation_type: SynchronisationType::Sync,
-            request: AsciiStr::from_ascii(request)?,
-        })
+            request: request.as_ref(),
+        }
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -71,13 +105,135 @@ impl<'a> KdbRequest<'a> {
         ret_val[4..8].copy_from_slice(&(HEADER_LEN + TYPE_LEN + ATTRIBUTE_LEN + VECTOR_LEN + self.request.len() as u32).to_le_bytes());
         ret_val[8] = 10;
         ret_val[9] = 0;
-        ret_val[10..14].copy_from_slice(&(self.request.as_bytes().len() as u32).to_le_bytes());
-        ret_val[14..].copy_from_slice(self.request.as_bytes());
+        ret_val[10..14].copy_from_slice(&(self.request.len() as u32).to_le_bytes());
+        ret_val[14..].copy_from_slice(self.request);
         ret_val
     }
 }
 
-#[derive(Debug, PartialEq)]
+/// The text of a char vector, symbol or error. q strings are bytes, usually UTF-8, so they're kept
+/// as sent: `to_str` gives the text when it's valid UTF-8 and `as_bytes` always gives the bytes.
+#[derive(Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct KdbString(Vec<u8>);
+
+impl KdbString {
+    pub fn new() -> KdbString {
+        KdbString::default()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// The text, unless the bytes aren't UTF-8
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        std::str::from_utf8(&self.0)
+    }
+
+    /// The text with bytes that aren't UTF-8 replaced by U+FFFD
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.0)
+    }
+
+    /// The text, or the string back when the bytes aren't UTF-8
+    pub fn into_string(self) -> Result<String, KdbString> {
+        String::from_utf8(self.0).map_err(|x| KdbString(x.into_bytes()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+}
+
+impl Display for KdbString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_string_lossy())
+    }
+}
+
+/// Quoted text, or `b"..."` with escapes when the bytes aren't UTF-8
+impl Debug for KdbString {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.to_str() {
+            Ok(x) => Debug::fmt(x, f),
+            Err(_) => write!(f, "b\"{}\"", self.0.escape_ascii()),
+        }
+    }
+}
+
+impl From<&str> for KdbString {
+    fn from(value: &str) -> Self {
+        KdbString(value.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for KdbString {
+    fn from(value: String) -> Self {
+        KdbString(value.into_bytes())
+    }
+}
+
+impl From<&[u8]> for KdbString {
+    fn from(value: &[u8]) -> Self {
+        KdbString(value.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for KdbString {
+    fn from(value: Vec<u8>) -> Self {
+        KdbString(value)
+    }
+}
+
+impl FromIterator<u8> for KdbString {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        KdbString(iter.into_iter().collect())
+    }
+}
+
+impl Extend<u8> for KdbString {
+    fn extend<I: IntoIterator<Item = u8>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl AsRef<[u8]> for KdbString {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Borrow<[u8]> for KdbString {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for KdbString {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+impl PartialEq<&str> for KdbString {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == other.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Payload {
     List(VectorAttribute, Vec<Payload>),
     Bool(bool),
@@ -96,11 +252,14 @@ pub enum Payload {
     RealVector(VectorAttribute, Vec<f32>),
     Float(f64),
     FloatVector(VectorAttribute, Vec<f64>),
+    /// One byte, as the char with that code point, so only U+0000 to U+00FF can be sent
     Char(char),
-    CharVector(VectorAttribute, AsciiString),
-    Symbol(AsciiString),
-    SymbolVector(VectorAttribute, Vec<AsciiString>),
-    Error(AsciiString),
+    CharVector(VectorAttribute, KdbString),
+    Symbol(KdbString),
+    SymbolVector(VectorAttribute, Vec<KdbString>),
+    /// A symbol vector as ids into a `SymbolTable`, see `SymbolTable::decode`
+    InternedSymbolVector(VectorAttribute, SymbolTable, Vec<SymbolId>),
+    Error(KdbString),
     Timestamp(u64),
     TimestampVector(VectorAttribute, Vec<u64>),
     Month(u32),
@@ -124,101 +283,461 @@ pub enum Payload {
 }
 
 impl Payload {
-    #[inline]
+    /// Decodes a whole serialised object, bytes left over are an error
     pub fn from_bytes(bytes: &[u8]) -> Result<Payload, String> {
-        let type_byte = bytes[0] as i8;
+        Payload::from_bytes_with(bytes, None)
+    }
 
+    /// `from_bytes`, interning symbol vectors into `symbols` if given
+    pub(crate) fn from_bytes_with(bytes: &[u8], symbols: Option<&mut Interner>) -> Result<Payload, String> {
+        let (payload, len) = Payload::decode_with(bytes, symbols)?;
+        match len == bytes.len() {
+            true => Ok(payload),
+            false => Err(format!("{} bytes left after the payload", bytes.len() - len)),
+        }
+    }
+
+    /// Decodes the object at the start of `bytes`, returning it with the number of bytes it took
+    pub fn decode(bytes: &[u8]) -> Result<(Payload, usize), String> {
+        Payload::decode_with(bytes, None)
+    }
+
+    /// `decode`, interning symbol vectors into `symbols` if given
+    pub(crate) fn decode_with(bytes: &[u8], mut symbols: Option<&mut Interner>) -> Result<(Payload, usize), String> {
+        let type_byte = *bytes.first().ok_or_else(truncated)? as i8;
         match type_byte {
             0 => {
-                let list_len = Self::get_vec_size(&bytes[2..6])?;
-                let mut list_contents = Vec::with_capacity(Self::get_vec_size(&bytes[2..6])?);
+                let (attribute, count) = vector_header(bytes)?;
+                // The count can't be trusted to size the list before the items are read
+                let mut items = Vec::with_capacity(count.min(bytes.len()));
                 let mut index = 6;
-                for _ in 0..list_len {
-                    let sub_payload = Payload::from_bytes(&bytes[index..]).unwrap();
-                    index += sub_payload.get_size() + 1;
-                    list_contents.push(sub_payload);
+                for _ in 0..count {
+                    let (item, len) = Payload::decode_with(&bytes[index..], symbols.as_deref_mut())?;
+                    items.push(item);
+                    index += len;
                 }
-                Ok(Payload::List(bytes[1].try_into()?, list_contents))
+                Ok((Payload::List(attribute, items), index))
+            }
+            -1 => atom(bytes, |x: [u8; 1]| x[0] != 0, Payload::Bool),
+            1 => vector(bytes, |x: [u8; 1]| x[0] != 0, Payload::BoolVector),
+            -2 => atom(bytes, u128::from_le_bytes, Payload::GUID),
+            2 => vector(bytes, u128::from_le_bytes, Payload::GUIDVector),
+            -4 => atom(bytes, |x: [u8; 1]| x[0], Payload::Byte),
+            4 => vector(bytes, |x: [u8; 1]| x[0], Payload::ByteVector),
+            -5 => atom(bytes, u16::from_le_bytes, Payload::Short),
+            5 => vector(bytes, u16::from_le_bytes, Payload::ShortVector),
+            -6 => atom(bytes, u32::from_le_bytes, Payload::Int),
+            6 => vector(bytes, u32::from_le_bytes, Payload::IntVector),
+            -7 => atom(bytes, u64::from_le_bytes, Payload::Long),
+            7 => vector(bytes, u64::from_le_bytes, Payload::LongVector),
+            -8 => atom(bytes, f32::from_le_bytes, Payload::Real),
+            8 => vector(bytes, f32::from_le_bytes, Payload::RealVector),
+            -9 => atom(bytes, f64::from_le_bytes, Payload::Float),
+            9 => vector(bytes, f64::from_le_bytes, Payload::FloatVector),
+            -10 => atom(bytes, |x: [u8; 1]| char::from(x[0]), Payload::Char),
+            10 => {
+                let (attribute, count) = vector_header(bytes)?;
+                let text = bytes.get(6..6 + count).ok_or_else(truncated)?;
+                Ok((Payload::CharVector(attribute, KdbString::from(text)), 6 + count))
             }
-            -1 => if bytes[1] < 2 && bytes.len() == 2 { Ok(Payload::Bool(bytes[1] != 0)) } else { Err(String::from("Failed to parse type")) },
-            1 => Ok(Payload::BoolVector(bytes[1].try_into()?, bytes[6..6 + Self::get_vec_size(&bytes[2..6])?].iter().map(|x| *x != 0).collect())),
-            -2 => Ok(Payload::GUID(u128::from_le_bytes(bytes[1..17].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            2 => Ok(Payload::GUIDVector(bytes[1].try_into()?, bytes[6..6 + 16 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(2)
-                .map(|x| x.try_into().map(u128::from_le_bytes)).collect::<Result<Vec<u128>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -4 => Ok(Payload::Byte(bytes[1])),
-            4 => Ok(Payload::ByteVector(bytes[1].try_into()?, bytes[6..6 + Self::get_vec_size(&bytes[2..6])?].iter().copied().collect())),
-            -5 => Ok(Payload::Short(u16::from_le_bytes(bytes[1..3].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            5 => Ok(Payload::ShortVector(bytes[1].try_into()?, bytes[6..6 + 2 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(2)
-                .map(|x| x.try_into().map(u16::from_le_bytes)).collect::<Result<Vec<u16>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -6 => Ok(Payload::Int(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            6 => Ok(Payload::IntVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -7 => Ok(Payload::Long(u64::from_le_bytes(bytes[1..9].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            7 => Ok(Payload::LongVector(bytes[1].try_into()?, bytes[6..6 + 8 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(8)
-                .map(|x| x.try_into().map(u64::from_le_bytes)).collect::<Result<Vec<u64>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -8 => Ok(Payload::Real(f32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            8 => Ok(Payload::RealVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(f32::from_le_bytes)).collect::<Result<Vec<f32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -9 => Ok(Payload::Float(f64::from_le_bytes(bytes[1..9].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            9 => Ok(Payload::FloatVector(bytes[1].try_into()?, bytes[6..6 + 8 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(8)
-                .map(|x| x.try_into().map(f64::from_le_bytes)).collect::<Result<Vec<f64>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -10 => Ok(Payload::Char(char::from(bytes[1]))),
-            10 => Ok(Payload::CharVector(bytes[1].try_into()?, AsciiString::from_ascii::<&[u8]>(
-                &bytes[6..6 + Self::get_vec_size(&bytes[2..6])?]).map_err(|x| x.to_string())?)),
-            -11 => Ok(Payload::Symbol(AsciiString::from_ascii::<&[u8]>(&bytes[1..].iter().copied().take_while(|x| *x != 0).collect::<Vec<u8>>().as_slice()).map_err(|x| x.to_string())?)),
+            -11 => symbol(&bytes[1..]).map(|(x, len)| (Payload::Symbol(x), 1 + len)),
             11 => {
-                let vec_size = Self::get_vec_size(&bytes[2..6])?;
-                let mut vec = Vec::new();
+                let (attribute, count) = vector_header(bytes)?;
                 let mut index = 6;
-                for _ in 0..vec_size {
-                    let string = AsciiString::from_ascii::<&[u8]>(&bytes[index..].iter().copied().take_while(|x| *x != 0).collect::<Vec<u8>>().as_slice()).map_err(|x| x.to_string())?;
-                    let len = string.len();
-                    vec.push(string);
-                    index += len + 1;
+                if let Some(symbols) = symbols {
+                    let mut ids = Vec::with_capacity(count.min(bytes.len()));
+                    for _ in 0..count {
+                        let end = bytes[index..].iter().position(|x| *x == 0).ok_or_else(truncated)?;
+                        ids.push(symbols.intern(&bytes[index..index + end]));
+                        index += end + 1;
+                    }
+                    return Ok((Payload::InternedSymbolVector(attribute, symbols.table(), ids), index));
+                }
+                let mut symbols = Vec::with_capacity(count.min(bytes.len()));
+                for _ in 0..count {
+                    let (x, len) = symbol(&bytes[index..])?;
+                    symbols.push(x);
+                    index += len;
                 }
-                Ok(Payload::SymbolVector(bytes[1].try_into()?, vec))
+                Ok((Payload::SymbolVector(attribute, symbols), index))
+            }
+            -12 => atom(bytes, u64::from_le_bytes, Payload::Timestamp),
+            12 => vector(bytes, u64::from_le_bytes, Payload::TimestampVector),
+            -13 => atom(bytes, u32::from_le_bytes, Payload::Month),
+            13 => vector(bytes, u32::from_le_bytes, Payload::MonthVector),
+            -14 => atom(bytes, u32::from_le_bytes, Payload::Date),
+            14 => vector(bytes, u32::from_le_bytes, Payload::DateVector),
+            -15 => atom(bytes, u64::from_le_bytes, Payload::DateTime),
+            15 => vector(bytes, u64::from_le_bytes, Payload::DateTimeVector),
+            -16 => atom(bytes, u64::from_le_bytes, Payload::TimeSpan),
+            16 => vector(bytes, u64::from_le_bytes, Payload::TimeSpanVector),
+            -17 => atom(bytes, u32::from_le_bytes, Payload::Minute),
+            17 => vector(bytes, u32::from_le_bytes, Payload::MinuteVector),
+            -18 => atom(bytes, u32::from_le_bytes, Payload::Second),
+            18 => vector(bytes, u32::from_le_bytes, Payload::SecondVector),
+            -19 => atom(bytes, u32::from_le_bytes, Payload::Time),
+            19 => vector(bytes, u32::from_le_bytes, Payload::TimeVector),
+            98 => {
+                let attribute = (*bytes.get(1).ok_or_else(truncated)?).try_into()?;
+                // Column names stay plain so the table keeps its shape
+                let (dictionary, len) = match bytes.get(2) {
+                    Some(99) => {
+                        let (names, names_len) = Payload::decode_with(&bytes[3..], None)?;
+                        let (columns, columns_len) = Payload::decode_with(&bytes[3 + names_len..], symbols)?;
+                        (Payload::Dictionary(Box::new(names), Box::new(columns)), 1 + names_len + columns_len)
+                    }
+                    _ => Payload::decode_with(&bytes[2..], symbols)?,
+                };
+                Ok((Payload::Table(attribute, Box::new(dictionary)), 2 + len))
             }
-            -12 => Ok(Payload::Timestamp(u64::from_le_bytes(bytes[1..9].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            12 => Ok(Payload::TimestampVector(bytes[1].try_into()?, bytes[6..6 + 8 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(8)
-                .map(|x| x.try_into().map(u64::from_le_bytes)).collect::<Result<Vec<u64>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -13 => Ok(Payload::Month(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            13 => Ok(Payload::MonthVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -14 => Ok(Payload::Date(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            14 => Ok(Payload::DateVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -15 => Ok(Payload::DateTime(u64::from_le_bytes(bytes[1..9].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            15 => Ok(Payload::DateTimeVector(bytes[1].try_into()?, bytes[6..6 + 8 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(8)
-                .map(|x| x.try_into().map(u64::from_le_bytes)).collect::<Result<Vec<u64>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -16 => Ok(Payload::TimeSpan(u64::from_le_bytes(bytes[1..9].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            16 => Ok(Payload::TimeSpanVector(bytes[1].try_into()?, bytes[6..6 + 8 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(8)
-                .map(|x| x.try_into().map(u64::from_le_bytes)).collect::<Result<Vec<u64>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -17 => Ok(Payload::Minute(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            17 => Ok(Payload::MinuteVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -18 => Ok(Payload::Second(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            18 => Ok(Payload::SecondVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            -19 => Ok(Payload::Time(u32::from_le_bytes(bytes[1..5].try_into().map_err(|_| String::from("Failed to parse type"))?))),
-            19 => Ok(Payload::TimeVector(bytes[1].try_into()?, bytes[6..6 + 4 * Self::get_vec_size(&bytes[2..6])?].chunks_exact(4)
-                .map(|x| x.try_into().map(u32::from_le_bytes)).collect::<Result<Vec<u32>, TryFromSliceError>>().map_err(|x| x.to_string())?)),
-            98 => Ok(Payload::Table(bytes[1].try_into()?, Box::new(Payload::from_bytes(&bytes[2..])?))),
             99 => {
-                let key_payload = Payload::from_bytes(&bytes[1..])?;
-                let value_payload = Payload::from_bytes(&bytes[key_payload.get_size() + 2..])?;
-                Ok(Payload::Dictionary(Box::from(key_payload), Box::new(value_payload)))
+                let (keys, keys_len) = Payload::decode_with(&bytes[1..], symbols.as_deref_mut())?;
+                let (values, values_len) = Payload::decode_with(&bytes[1 + keys_len..], symbols)?;
+                Ok((Payload::Dictionary(Box::new(keys), Box::new(values)), 1 + keys_len + values_len))
             }
-            -101 => Ok(Payload::Nil),
-            101 => Ok(Payload::Nil),
-            -128 => Ok(Payload::Error(AsciiString::from_ascii::<&[u8]>(&bytes[1..].iter().copied().take_while(|x| *x != 0).collect::<Vec<u8>>().as_slice()).map_err(|x| x.to_string())?)),
+            -101 | 101 => atom(bytes, |_: [u8; 1]| (), |_| Payload::Nil),
+            -128 => symbol(&bytes[1..]).map(|(x, len)| (Payload::Error(x), 1 + len)),
             _ => Err(format!("Failed to find type, {}", type_byte))
         }
     }
 
+    /// Error payload for sending back to q
+    pub fn error(message: &str) -> Payload {
+        Payload::Error(KdbString::from(message))
+    }
+
+    /// Empty vector able to hold `atom` via `push`, a general list for non atoms
+    pub fn empty_vector_for(atom: &Payload) -> Payload {
+        let a = NoAttribute;
+        match atom {
+            Payload::Bool(_) => Payload::BoolVector(a, Vec::new()),
+            Payload::GUID(_) => Payload::GUIDVector(a, Vec::new()),
+            Payload::Byte(_) => Payload::ByteVector(a, Vec::new()),
+            Payload::Short(_) => Payload::ShortVector(a, Vec::new()),
+            Payload::Int(_) => Payload::IntVector(a, Vec::new()),
+            Payload::Long(_) => Payload::LongVector(a, Vec::new()),
+            Payload::Real(_) => Payload::RealVector(a, Vec::new()),
+            Payload::Float(_) => Payload::FloatVector(a, Vec::new()),
+            Payload::Char(_) => Payload::CharVector(a, KdbString::new()),
+            Payload::Symbol(_) => Payload::SymbolVector(a, Vec::new()),
+            Payload::Timestamp(_) => Payload::TimestampVector(a, Vec::new()),
+            Payload::Month(_) => Payload::MonthVector(a, Vec::new()),
+            Payload::Date(_) => Payload::DateVector(a, Vec::new()),
+            Payload::DateTime(_) => Payload::DateTimeVector(a, Vec::new()),
+            Payload::TimeSpan(_) => Payload::TimeSpanVector(a, Vec::new()),
+            Payload::Minute(_) => Payload::MinuteVector(a, Vec::new()),
+            Payload::Second(_) => Payload::SecondVector(a, Vec::new()),
+            Payload::Time(_) => Payload::TimeVector(a, Vec::new()),
+            _ => Payload::List(a, Vec::new()),
+        }
+    }
+
+    /// Whether `push` would append `atom`
+    pub(crate) fn accepts(&self, atom: &Payload) -> bool {
+        match (self, atom) {
+            (Payload::List(_, _), _) => true,
+            (_, Payload::Char(x)) if *x > '\u{ff}' => false,
+            (vector, x) => (-19..0).contains(&x.type_byte()) && vector.type_byte() == -x.type_byte(),
+        }
+    }
+
+    /// Appends an atom to a vector of the same type, anything can be appended to a general list
+    pub fn push(&mut self, atom: Payload) -> Result<(), String> {
+        match (self, atom) {
+            (Payload::List(_, v), x) => v.push(x),
+            (Payload::BoolVector(_, v), Payload::Bool(x)) => v.push(x),
+            (Payload::GUIDVector(_, v), Payload::GUID(x)) => v.push(x),
+            (Payload::ByteVector(_, v), Payload::Byte(x)) => v.push(x),
+            (Payload::ShortVector(_, v), Payload::Short(x)) => v.push(x),
+            (Payload::IntVector(_, v), Payload::Int(x)) => v.push(x),
+            (Payload::LongVector(_, v), Payload::Long(x)) => v.push(x),
+            (Payload::RealVector(_, v), Payload::Real(x)) => v.push(x),
+            (Payload::FloatVector(_, v), Payload::Float(x)) => v.push(x),
+            (Payload::CharVector(_, v), Payload::Char(x)) if x <= '\u{ff}' => v.push(x as u8),
+            (Payload::SymbolVector(_, v), Payload::Symbol(x)) => v.push(x),
+            (Payload::InternedSymbolVector(_, t, v), Payload::Symbol(x)) => v.push(t.intern(x.as_bytes())),
+            (Payload::TimestampVector(_, v), Payload::Timestamp(x)) => v.push(x),
+            (Payload::MonthVector(_, v), Payload::Month(x)) => v.push(x),
+            (Payload::DateVector(_, v), Payload::Date(x)) => v.push(x),
+            (Payload::DateTimeVector(_, v), Payload::DateTime(x)) => v.push(x),
+            (Payload::TimeSpanVector(_, v), Payload::TimeSpan(x)) => v.push(x),
+            (Payload::MinuteVector(_, v), Payload::Minute(x)) => v.push(x),
+            (Payload::SecondVector(_, v), Payload::Second(x)) => v.push(x),
+            (Payload::TimeVector(_, v), Payload::Time(x)) => v.push(x),
+            (vector, x) => return Err(format!("Can't append type {} to type {}", x.type_byte(), vector.type_byte())),
+        }
+        Ok(())
+    }
+
+    /// Appends the items of another vector of the same type, the attribute is dropped
+    pub(crate) fn append(&mut self, other: Payload) -> Result<(), String> {
+        fn extend<T>(attribute: &mut VectorAttribute, values: &mut Vec<T>, other: Vec<T>) {
+            *attribute = NoAttribute;
+            values.extend(other);
+        }
+
+        match (self, other) {
+            (Payload::List(a, v), Payload::List(_, x)) => extend(a, v, x),
+            (Payload::BoolVector(a, v), Payload::BoolVector(_, x)) => extend(a, v, x),
+            (Payload::GUIDVector(a, v), Payload::GUIDVector(_, x)) => extend(a, v, x),
+            (Payload::ByteVector(a, v), Payload::ByteVector(_, x)) => extend(a, v, x),
+            (Payload::ShortVector(a, v), Payload::ShortVector(_, x)) => extend(a, v, x),
+            (Payload::IntVector(a, v), Payload::IntVector(_, x)) => extend(a, v, x),
+            (Payload::LongVector(a, v), Payload::LongVector(_, x)) => extend(a, v, x),
+            (Payload::RealVector(a, v), Payload::RealVector(_, x)) => extend(a, v, x),
+            (Payload::FloatVector(a, v), Payload::FloatVector(_, x)) => extend(a, v, x),
+            (Payload::CharVector(a, v), Payload::CharVector(_, x)) => {
+                *a = NoAttribute;
+                v.extend(x.into_bytes());
+            }
+            (Payload::SymbolVector(a, v), Payload::SymbolVector(_, x)) => extend(a, v, x),
+            (Payload::InternedSymbolVector(a, t, v), Payload::InternedSymbolVector(_, u, x)) if *t == u => extend(a, v, x),
+            (Payload::TimestampVector(a, v), Payload::TimestampVector(_, x)) => extend(a, v, x),
+            (Payload::MonthVector(a, v), Payload::MonthVector(_, x)) => extend(a, v, x),
+            (Payload::DateVector(a, v), Payload::DateVector(_, x)) => extend(a, v, x),
+            (Payload::DateTimeVector(a, v), Payload::DateTimeVector(_, x)) => extend(a, v, x),
+            (Payload::TimeSpanVector(a, v), Payload::TimeSpanVector(_, x)) => extend(a, v, x),
+            (Payload::MinuteVector(a, v), Payload::MinuteVector(_, x)) => extend(a, v, x),
+            (Payload::SecondVector(a, v), Payload::SecondVector(_, x)) => extend(a, v, x),
+            (Payload::TimeVector(a, v), Payload::TimeVector(_, x)) => extend(a, v, x),
+            (vector, x) => return Err(format!("Can't append type {} to type {}", x.type_byte(), vector.type_byte())),
+        }
+        Ok(())
+    }
+
+    /// Keeps the items of a vector whose flag in `keep` is set, only `s#` survives
+    pub(crate) fn filter_rows(self, keep: &[bool]) -> Result<Payload, String> {
+        fn filter<T>(values: Vec<T>, keep: &[bool]) -> Vec<T> {
+            values.into_iter().zip(keep).filter(|(_, keep)| **keep).map(|(x, _)| x).collect()
+        }
+        fn kept(attribute: VectorAttribute) -> VectorAttribute {
+            if attribute == Sorted { Sorted } else { NoAttribute }
+        }
+
+        Ok(match self {
+            Payload::List(a, v) => Payload::List(kept(a), filter(v, keep)),
+            Payload::BoolVector(a, v) => Payload::BoolVector(kept(a), filter(v, keep)),
+            Payload::GUIDVector(a, v) => Payload::GUIDVector(kept(a), filter(v, keep)),
+            Payload::ByteVector(a, v) => Payload::ByteVector(kept(a), filter(v, keep)),
+            Payload::ShortVector(a, v) => Payload::ShortVector(kept(a), filter(v, keep)),
+            Payload::IntVector(a, v) => Payload::IntVector(kept(a), filter(v, keep)),
+            Payload::LongVector(a, v) => Payload::LongVector(kept(a), filter(v, keep)),
+            Payload::RealVector(a, v) => Payload::RealVector(kept(a), filter(v, keep)),
+            Payload::FloatVector(a, v) => Payload::FloatVector(kept(a), filter(v, keep)),
+            Payload::CharVector(a, v) => Payload::CharVector(kept(a), filter(v.into_bytes(), keep).into()),
+            Payload::SymbolVector(a, v) => Payload::SymbolVector(kept(a), filter(v, keep)),
+            Payload::InternedSymbolVector(a, t, v) => Payload::InternedSymbolVector(kept(a), t, filter(v, keep)),
+            Payload::TimestampVector(a, v) => Payload::TimestampVector(kept(a), filter(v, keep)),
+            Payload::MonthVector(a, v) => Payload::MonthVector(kept(a), filter(v, keep)),
+            Payload::DateVector(a, v) => Payload::DateVector(kept(a), filter(v, keep)),
+            Payload::DateTimeVector(a, v) => Payload::DateTimeVector(kept(a), filter(v, keep)),
+            Payload::TimeSpanVector(a, v) => Payload::TimeSpanVector(kept(a), filter(v, keep)),
+            Payload::MinuteVector(a, v) => Payload::MinuteVector(kept(a), filter(v, keep)),
+            Payload::SecondVector(a, v) => Payload::SecondVector(kept(a), filter(v, keep)),
+            Payload::TimeVector(a, v) => Payload::TimeVector(kept(a), filter(v, keep)),
+            x => return Err(format!("Can't filter type {}", x.type_byte())),
+        })
+    }
+
+    /// The items of a vector at `order`, which must be in range, with the attribute given
+    pub(crate) fn take_rows(self, order: &[usize], attribute: VectorAttribute) -> Result<Payload, String> {
+        fn take<T: Clone>(values: Vec<T>, order: &[usize]) -> Vec<T> {
+            order.iter().map(|x| values[*x].clone()).collect()
+        }
+
+        Ok(match self {
+            Payload::List(_, v) => Payload::List(attribute, take(v, order)),
+            Payload::BoolVector(_, v) => Payload::BoolVector(attribute, take(v, order)),
+            Payload::GUIDVector(_, v) => Payload::GUIDVector(attribute, take(v, order)),
+            Payload::ByteVector(_, v) => Payload::ByteVector(attribute, take(v, order)),
+            Payload::ShortVector(_, v) => Payload::ShortVector(attribute, take(v, order)),
+            Payload::IntVector(_, v) => Payload::IntVector(attribute, take(v, order)),
+            Payload::LongVector(_, v) => Payload::LongVector(attribute, take(v, order)),
+            Payload::RealVector(_, v) => Payload::RealVector(attribute, take(v, order)),
+            Payload::FloatVector(_, v) => Payload::FloatVector(attribute, take(v, order)),
+            Payload::CharVector(_, v) => Payload::CharVector(attribute, order.iter().map(|x| v.as_bytes()[*x]).collect()),
+            Payload::SymbolVector(_, v) => Payload::SymbolVector(attribute, take(v, order)),
+            Payload::InternedSymbolVector(_, t, v) => Payload::InternedSymbolVector(attribute, t, take(v, order)),
+            Payload::TimestampVector(_, v) => Payload::TimestampVector(attribute, take(v, order)),
+            Payload::MonthVector(_, v) => Payload::MonthVector(attribute, take(v, order)),
+            Payload::DateVector(_, v) => Payload::DateVector(attribute, take(v, order)),
+            Payload::DateTimeVector(_, v) => Payload::DateTimeVector(attribute, take(v, order)),
+            Payload::TimeSpanVector(_, v) => Payload::TimeSpanVector(attribute, take(v, order)),
+            Payload::MinuteVector(_, v) => Payload::MinuteVector(attribute, take(v, order)),
+            Payload::SecondVector(_, v) => Payload::SecondVector(attribute, take(v, order)),
+            Payload::TimeVector(_, v) => Payload::TimeVector(attribute, take(v, order)),
+            x => return Err(format!("Can't reorder type {}", x.type_byte())),
+        })
+    }
+
+    /// Serialises the payload (type byte included) without any message header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret_val = Vec::with_capacity(self.encoded_len());
+        self.encode_into(&mut ret_val);
+        ret_val
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        fn vector<T, F: Fn(&T, &mut Vec<u8>)>(buf: &mut Vec<u8>, attribute: &VectorAttribute, values: &[T], write: F) {
+            buf.push(*attribute as u8);
+            buf.extend_from_slice(&(values.len() as u32).to_le_bytes());
+            values.iter().for_each(|x| write(x, buf));
+        }
+
+        match self {
+            // :: has no vector form, send a general list of them
+            Payload::NilVector(attribute, x) => {
+                buf.push(0);
+                vector(buf, attribute, x, |_, buf| buf.extend_from_slice(&[101, 0]));
+                return;
+            }
+            Payload::Nil => {
+                buf.extend_from_slice(&[101, 0]);
+                return;
+            }
+            _ => buf.push(self.type_byte() as u8),
+        }
+        match self {
+            Payload::List(a, x) => vector(buf, a, x, |x, buf| x.encode_into(buf)),
+            Payload::Bool(x) => buf.push(*x as u8),
+            Payload::BoolVector(a, x) => vector(buf, a, x, |x, buf| buf.push(*x as u8)),
+            Payload::GUID(x) => buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::GUIDVector(a, x) => vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Byte(x) => buf.push(*x),
+            Payload::ByteVector(a, x) => vector(buf, a, x, |x, buf| buf.push(*x)),
+            Payload::Short(x) => buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::ShortVector(a, x) => vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Int(x) | Payload::Month(x) | Payload::Date(x) | Payload::Minute(x) | Payload::Second(x) | Payload::Time(x) =>
+                buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::IntVector(a, x) | Payload::MonthVector(a, x) | Payload::DateVector(a, x) | Payload::MinuteVector(a, x)
+            | Payload::SecondVector(a, x) | Payload::TimeVector(a, x) => vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Long(x) | Payload::Timestamp(x) | Payload::DateTime(x) | Payload::TimeSpan(x) => buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::LongVector(a, x) | Payload::TimestampVector(a, x) | Payload::DateTimeVector(a, x) | Payload::TimeSpanVector(a, x) =>
+                vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Real(x) => buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::RealVector(a, x) => vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Float(x) => buf.extend_from_slice(&x.to_le_bytes()),
+            Payload::FloatVector(a, x) => vector(buf, a, x, |x, buf| buf.extend_from_slice(&x.to_le_bytes())),
+            Payload::Char(x) => buf.push(*x as u8),
+            Payload::CharVector(a, x) => vector(buf, a, x.as_bytes(), |x, buf| buf.push(*x)),
+            Payload::Symbol(x) | Payload::Error(x) => {
+                buf.extend_from_slice(x.as_bytes());
+                buf.push(0);
+            }
+            Payload::SymbolVector(a, x) => vector(buf, a, x, |x, buf| {
+                buf.extend_from_slice(x.as_bytes());
+                buf.push(0);
+            }),
+            Payload::InternedSymbolVector(a, t, x) => t.read(|symbols| vector(buf, a, x, |x, buf| {
+                buf.extend_from_slice(symbols.name(*x));
+                buf.push(0);
+            })),
+            Payload::Table(a, x) => {
+                buf.push(*a as u8);
+                x.encode_into(buf);
+            }
+            Payload::Dictionary(x, y) => {
+                x.encode_into(buf);
+                y.encode_into(buf);
+            }
+            Payload::Nil | Payload::NilVector(_, _) => {}
+        }
+    }
+
     fn get_vec_size(bytes: &[u8]) -> Result<usize, String> {
         bytes.try_into().map(|x| u32::from_le_bytes(x) as usize).map_err(|_| String::from("Failed to find vector size"))
     }
 
+    /// Length of the serialised object at the start of `bytes` without decoding it, `None` if
+    /// `bytes` ends before the object does
+    pub(crate) fn serialized_len(bytes: &[u8]) -> Result<Option<usize>, String> {
+        let fixed = |len: usize| Ok(Some(len).filter(|x| *x <= bytes.len()));
+        let type_byte = match bytes.first() {
+            Some(x) => *x as i8,
+            None => return Ok(None),
+        };
+        match type_byte {
+            -1 | -4 | -10 | -101 | 101 => fixed(2),
+            -2 => fixed(17),
+            -5 => fixed(3),
+            -6 | -8 | -13 | -14 | -17 | -18 | -19 => fixed(5),
+            -7 | -9 | -12 | -15 | -16 => fixed(9),
+            -11 | -128 => Ok(bytes[1..].iter().position(|x| *x == 0).map(|x| x + 2)),
+            98 => Ok(match bytes.len() {
+                0..=1 => None,
+                _ => Self::serialized_len(&bytes[2..])?.map(|x| x + 2),
+            }),
+            99 => {
+                let key_len = match Self::serialized_len(&bytes[1..])? {
+                    Some(x) => x,
+                    None => return Ok(None),
+                };
+                Ok(Self::serialized_len(&bytes[1 + key_len..])?.map(|x| 1 + key_len + x))
+            }
+            0..=19 if type_byte != 3 => {
+                if bytes.len() < 6 {
+                    return Ok(None);
+                }
+                let count = Self::get_vec_size(&bytes[2..6])?;
+                if let Some(width) = Self::vector_width(type_byte) {
+                    return fixed(6 + width * count);
+                }
+                let mut index = 6;
+                for _ in 0..count {
+                    index += match type_byte {
+                        11 => match bytes[index..].iter().position(|x| *x == 0) {
+                            Some(x) => x + 1,
+                            None => return Ok(None),
+                        },
+                        _ => match Self::serialized_len(&bytes[index..])? {
+                            Some(x) => x,
+                            None => return Ok(None),
+                        },
+                    };
+                }
+                Ok(Some(index))
+            }
+            _ => Err(format!("Failed to find type, {}", type_byte))
+        }
+    }
+
+    /// A simple vector from its items' bytes, laid out the same in IPC and in vector files, without
+    /// copying them anywhere first
+    pub(crate) fn from_vector_data(type_byte: i8, attribute: VectorAttribute, data: &[u8]) -> Result<Payload, String> {
+        match type_byte {
+            1 => Ok(Payload::BoolVector(attribute, items(data, |x: [u8; 1]| x[0] != 0))),
+            2 => Ok(Payload::GUIDVector(attribute, items(data, u128::from_le_bytes))),
+            4 => Ok(Payload::ByteVector(attribute, data.to_vec())),
+            5 => Ok(Payload::ShortVector(attribute, items(data, u16::from_le_bytes))),
+            6 => Ok(Payload::IntVector(attribute, items(data, u32::from_le_bytes))),
+            7 => Ok(Payload::LongVector(attribute, items(data, u64::from_le_bytes))),
+            8 => Ok(Payload::RealVector(attribute, items(data, f32::from_le_bytes))),
+            9 => Ok(Payload::FloatVector(attribute, items(data, f64::from_le_bytes))),
+            10 => Ok(Payload::CharVector(attribute, KdbString::from(data))),
+            12 => Ok(Payload::TimestampVector(attribute, items(data, u64::from_le_bytes))),
+            13 => Ok(Payload::MonthVector(attribute, items(data, u32::from_le_bytes))),
+            14 => Ok(Payload::DateVector(attribute, items(data, u32::from_le_bytes))),
+            15 => Ok(Payload::DateTimeVector(attribute, items(data, u64::from_le_bytes))),
+            16 => Ok(Payload::TimeSpanVector(attribute, items(data, u64::from_le_bytes))),
+            17 => Ok(Payload::MinuteVector(attribute, items(data, u32::from_le_bytes))),
+            18 => Ok(Payload::SecondVector(attribute, items(data, u32::from_le_bytes))),
+            19 => Ok(Payload::TimeVector(attribute, items(data, u32::from_le_bytes))),
+            x => Err(format!("Type {} isn't a simple vector", x)),
+        }
+    }
+
+    /// Bytes per element of vectors of a fixed width type, `None` for symbols and general lists
+    pub(crate) const fn vector_width(type_byte: i8) -> Option<usize> {
+        match type_byte {
+            1 | 4 | 10 => Some(1),
+            5 => Some(2),
+            6 | 8 | 13 | 14 | 17 | 18 | 19 => Some(4),
+            7 | 9 | 12 | 15 | 16 => Some(8),
+            2 => Some(16),
+            _ => None,
+        }
+    }
+
     pub const fn type_byte(&self) -> i8 {
         match self {
             Payload::List(_, _) => 0,
@@ -241,7 +760,7 @@ impl Payload {
             Payload::Char(_) => -10,
             Payload::CharVector(_, _) => 10,
             Payload::Symbol(_) => -11,
-            Payload::SymbolVector(_, _) => 11,
+            Payload::SymbolVector(_, _) | Payload::InternedSymbolVector(_, _, _) => 11,
             Payload::Timestamp(_) => -12,
             Payload::TimestampVector(_, _) => 12,
             Payload::Month(_) => -13,
@@ -266,60 +785,157 @@ impl Payload {
         }
     }
 
-    pub fn get_size(&self) -> usize {
+    /// Items in the payload as q's `count` gives them: a vector's length, a table's rows, a
+    /// dictionary's keys and 1 for an atom
+    pub fn count(&self) -> usize {
+        match self {
+            Payload::List(_, x) => x.len(),
+            Payload::BoolVector(_, x) => x.len(),
+            Payload::GUIDVector(_, x) => x.len(),
+            Payload::ByteVector(_, x) => x.len(),
+            Payload::ShortVector(_, x) => x.len(),
+            Payload::IntVector(_, x) => x.len(),
+            Payload::LongVector(_, x) => x.len(),
+            Payload::RealVector(_, x) => x.len(),
+            Payload::FloatVector(_, x) => x.len(),
+            Payload::CharVector(_, x) => x.len(),
+            Payload::SymbolVector(_, x) => x.len(),
+            Payload::InternedSymbolVector(_, _, x) => x.len(),
+            Payload::TimestampVector(_, x) => x.len(),
+            Payload::MonthVector(_, x) => x.len(),
+            Payload::DateVector(_, x) => x.len(),
+            Payload::DateTimeVector(_, x) => x.len(),
+            Payload::TimeSpanVector(_, x) => x.len(),
+            Payload::MinuteVector(_, x) => x.len(),
+            Payload::SecondVector(_, x) => x.len(),
+            Payload::TimeVector(_, x) => x.len(),
+            Payload::NilVector(_, x) => x.len(),
+            // The rows of the first column
+            Payload::Table(_, x) => match x.as_ref() {
+                Payload::Dictionary(_, values) => match values.as_ref() {
+                    Payload::List(_, columns) => columns.first().map(Payload::count).unwrap_or(0),
+                    x => x.count(),
+                },
+                x => x.count(),
+            },
+            Payload::Dictionary(keys, _) => keys.count(),
+            _ => 1,
+        }
+    }
+
+    /// Attribute of a vector or table, atoms and dictionaries have none
+    pub const fn attribute(&self) -> VectorAttribute {
+        match self {
+            Payload::List(x, _) => *x,
+            Payload::BoolVector(x, _) => *x,
+            Payload::GUIDVector(x, _) => *x,
+            Payload::ByteVector(x, _) => *x,
+            Payload::ShortVector(x, _) => *x,
+            Payload::IntVector(x, _) => *x,
+            Payload::LongVector(x, _) => *x,
+            Payload::RealVector(x, _) => *x,
+            Payload::FloatVector(x, _) => *x,
+            Payload::CharVector(x, _) => *x,
+            Payload::SymbolVector(x, _) => *x,
+            Payload::InternedSymbolVector(x, _, _) => *x,
+            Payload::TimestampVector(x, _) => *x,
+            Payload::MonthVector(x, _) => *x,
+            Payload::DateVector(x, _) => *x,
+            Payload::DateTimeVector(x, _) => *x,
+            Payload::TimeSpanVector(x, _) => *x,
+            Payload::MinuteVector(x, _) => *x,
+            Payload::SecondVector(x, _) => *x,
+            Payload::TimeVector(x, _) => *x,
+            Payload::Table(x, _) => *x,
+            Payload::NilVector(x, _) => *x,
+            _ => NoAttribute,
+        }
+    }
+
+    /// Exact length of the object as `to_bytes` writes it, type byte included
+    pub fn encoded_len(&self) -> usize {
+        fn fixed<T>(values: &[T]) -> usize {
+            VECTOR_HEADER_LEN + std::mem::size_of_val(values)
+        }
+        const VECTOR_HEADER_LEN: usize = (TYPE_LEN + ATTRIBUTE_LEN + VECTOR_LEN) as usize;
         match self {
-            Payload::List(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + x.len() + x.iter().fold(0, |acc, val| acc + val.get_size()),
-            Payload::Bool(_) => 1,
-            Payload::BoolVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + x.len(),
-            Payload::GUID(_) => 16,
-            Payload::GUIDVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 16 * x.len(),
-            Payload::Byte(_) => 1,
-            Payload::ByteVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + x.len(),
-            Payload::Short(_) => 2,
-            Payload::ShortVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 2 * x.len(),
-            Payload::Int(_) => 4,
-            Payload::IntVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Long(_) => 8,
-            Payload::LongVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 8 * x.len(),
-            Payload::Real(_) => 4,
-            Payload::RealVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Float(_) => 8,
-            Payload::FloatVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 8 * x.len(),
-            Payload::Char(_) => 1,
-            Payload::CharVector(_, x) => 1 + 4 + x.len(),
-            Payload::Symbol(x) => 1 + x.len(),
-            Payload::SymbolVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + x.len() + x.iter().fold(0, |acc, val| acc + val.len()),
-            Payload::Timestamp(_) => 8,
-            Payload::TimestampVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 8 * x.len(),
-            Payload::Month(_) => 4,
-            Payload::MonthVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Date(_) => 4,
-            Payload::DateVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::DateTime(_) => 8,
-            Payload::DateTimeVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 8 * x.len(),
-            Payload::TimeSpan(_) => 8,
-            Payload::TimeSpanVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 8 * x.len(),
-            Payload::Minute(_) => 4,
-            Payload::MinuteVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Second(_) => 4,
-            Payload::SecondVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Time(_) => 4,
-            Payload::TimeVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + 4 * x.len(),
-            Payload::Table(_, y) => ATTRIBUTE_LEN as usize + y.get_size(),
-            Payload::Dictionary(x, y) => x.get_size() + y.get_size(),
-            Payload::Nil => 1,
-            Payload::NilVector(_, x) => ATTRIBUTE_LEN as usize + VECTOR_LEN as usize + x.len(),
-            Payload::Error(x) => x.len(),
+            Payload::List(_, x) => VECTOR_HEADER_LEN + x.iter().map(Payload::encoded_len).sum::<usize>(),
+            Payload::Bool(_) | Payload::Byte(_) | Payload::Char(_) | Payload::Nil => 2,
+            Payload::GUID(_) => 17,
+            Payload::Short(_) => 3,
+            Payload::Int(_) | Payload::Real(_) | Payload::Month(_) | Payload::Date(_) | Payload::Minute(_) | Payload::Second(_) | Payload::Time(_) => 5,
+            Payload::Long(_) | Payload::Float(_) | Payload::Timestamp(_) | Payload::DateTime(_) | Payload::TimeSpan(_) => 9,
+            Payload::BoolVector(_, x) => fixed(x),
+            Payload::GUIDVector(_, x) => fixed(x),
+            Payload::ByteVector(_, x) => fixed(x),
+            Payload::ShortVector(_, x) => fixed(x),
+            Payload::IntVector(_, x) | Payload::MonthVector(_, x) | Payload::DateVector(_, x) | Payload::MinuteVector(_, x)
+            | Payload::SecondVector(_, x) | Payload::TimeVector(_, x) => fixed(x),
+            Payload::LongVector(_, x) | Payload::TimestampVector(_, x) | Payload::DateTimeVector(_, x) | Payload::TimeSpanVector(_, x) => fixed(x),
+            Payload::RealVector(_, x) => fixed(x),
+            Payload::FloatVector(_, x) => fixed(x),
+            Payload::CharVector(_, x) => VECTOR_HEADER_LEN + x.len(),
+            // Null terminated
+            Payload::Symbol(x) | Payload::Error(x) => 2 + x.len(),
+            Payload::SymbolVector(_, x) => VECTOR_HEADER_LEN + x.iter().map(|x| x.len() + 1).sum::<usize>(),
+            Payload::InternedSymbolVector(_, t, x) => VECTOR_HEADER_LEN + t.read(|symbols| x.iter().map(|x| symbols.name(*x).len() + 1).sum::<usize>()),
+            Payload::Table(_, x) => 2 + x.encoded_len(),
+            Payload::Dictionary(x, y) => 1 + x.encoded_len() + y.encoded_len(),
+            // Sent as a general list of `::`
+            Payload::NilVector(_, x) => VECTOR_HEADER_LEN + 2 * x.len(),
         }
     }
+
+    /// Encoded length without the type byte, see `encoded_len`
+    pub fn get_size(&self) -> usize {
+        self.encoded_len() - TYPE_LEN as usize
+    }
+}
+
+
+fn truncated() -> String {
+    String::from("Payload ends early")
 }
 
+/// Attribute and count of the vector at the start of `bytes`
+fn vector_header(bytes: &[u8]) -> Result<(VectorAttribute, usize), String> {
+    let header = bytes.get(1..6).ok_or_else(truncated)?;
+    Ok((header[0].try_into()?, u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize))
+}
+
+/// An atom of `N` bytes after the type byte
+fn atom<T, const N: usize>(bytes: &[u8], read: fn([u8; N]) -> T, make: fn(T) -> Payload) -> Result<(Payload, usize), String> {
+    let mut value = [0; N];
+    value.copy_from_slice(bytes.get(1..1 + N).ok_or_else(truncated)?);
+    Ok((make(read(value)), 1 + N))
+}
+
+/// A vector of items `N` bytes wide
+fn vector<T, const N: usize>(bytes: &[u8], read: fn([u8; N]) -> T, make: fn(VectorAttribute, Vec<T>) -> Payload) -> Result<(Payload, usize), String> {
+    let (attribute, count) = vector_header(bytes)?;
+    let data = bytes.get(6..6 + N * count).ok_or_else(truncated)?;
+    Ok((make(attribute, items(data, read)), 6 + N * count))
+}
+
+/// Every whole item of `N` bytes in `data`
+fn items<T, const N: usize>(data: &[u8], read: fn([u8; N]) -> T) -> Vec<T> {
+    data.chunks_exact(N).map(|x| {
+        let mut value = [0; N];
+        value.copy_from_slice(x);
+        read(value)
+    }).collect()
+}
+
+/// A null terminated symbol and the bytes it took, terminator included
+fn symbol(bytes: &[u8]) -> Result<(KdbString, usize), String> {
+    let end = bytes.iter().position(|x| *x == 0).ok_or_else(truncated)?;
+    Ok((KdbString::from(&bytes[..end]), end + 1))
+}
 
 #[cfg(test)]
 mod tests {
-    use ascii::{AsciiStr, AsciiString};
-    use crate::codec::Payload;
-    use crate::codec::VectorAttribute::NoAttribute;
+    use crate::codec::{KdbRequest, KdbString, Payload, SynchronisationType, encode_message};
+    use crate::codec::VectorAttribute::{Grouped, NoAttribute, Parted, Sorted, Unique};
 
     #[test]
     pub fn test_list_marshalling() {
@@ -327,7 +943,7 @@ mod tests {
         if let Payload::List(x, vals) = Payload::from_bytes(&char_vec_hex_str[8..]).unwrap() {
             assert_eq!(x, NoAttribute);
             assert_eq!(vals[0], Payload::Char('a'));
-            assert_eq!(vals[1], Payload::CharVector(NoAttribute, AsciiString::from_ascii("ab").unwrap()));
+            assert_eq!(vals[1], Payload::CharVector(NoAttribute, KdbString::from("ab")));
         } else {
             panic!("Failed to get the right type");
         }
@@ -356,7 +972,7 @@ mod tests {
         let char_vec_hex_str = hex::decode("01000000180000000a000a00000074686174736372617a79").unwrap();
         if let Payload::CharVector(x, string) = Payload::from_bytes(&char_vec_hex_str[8..]).unwrap() {
             assert_eq!(x, NoAttribute);
-            assert_eq!(string, AsciiStr::from_ascii("thatscrazy").unwrap());
+            assert_eq!(string, "thatscrazy");
         }
     }
 
@@ -374,13 +990,13 @@ mod tests {
         let true_bool_hex_str = hex::decode("010000000a000000ff01").unwrap();
         let false_bool_hex_str = hex::decode("010000000a000000ff00").unwrap();
         if let Payload::Bool(x) = Payload::from_bytes(&true_bool_hex_str[8..]).unwrap() {
-            assert!(x, true);
+            assert!(x);
             Ok(())
         } else {
             Err(())
         }?;
         if let Payload::Bool(x) = Payload::from_bytes(&false_bool_hex_str[8..]).unwrap() {
-            assert_eq!(x, false);
+            assert!(!x);
             Ok(())
         } else {
             Err(())
@@ -474,4 +1090,118 @@ mod tests {
             panic!()
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn test_encoding_round_trip() {
+        let payload = Payload::List(NoAttribute, vec![
+            Payload::Symbol(KdbString::from("upd")),
+            Payload::Long(7),
+            Payload::SymbolVector(NoAttribute, vec![KdbString::from("a"), KdbString::from("bc")]),
+            Payload::FloatVector(NoAttribute, vec![1.5, -2.0]),
+            Payload::CharVector(NoAttribute, KdbString::from("xyz")),
+        ]);
+        assert_eq!(Payload::from_bytes(&payload.to_bytes()).unwrap(), payload);
+    }
+
+    /// One of every type, alone, in lists, dictionaries and tables
+    fn every_type() -> Vec<Payload> {
+        let symbol = |x: &str| KdbString::from(x);
+        let atoms = vec![
+            Payload::Bool(true), Payload::GUID(0x0123456789abcdef0123456789abcdef), Payload::Byte(7), Payload::Short(5),
+            Payload::Int(6), Payload::Long(7), Payload::Real(1.5), Payload::Float(-2.5), Payload::Char('c'),
+            Payload::Symbol(symbol("sym")), Payload::Symbol(symbol("")), Payload::Timestamp(12), Payload::Month(13),
+            Payload::Date(14), Payload::DateTime(0.5f64.to_bits()), Payload::TimeSpan(16), Payload::Minute(17),
+            Payload::Second(18), Payload::Time(19), Payload::Nil, Payload::Error(symbol("type")),
+        ];
+        let vectors = vec![
+            Payload::BoolVector(Sorted, vec![false, true]), Payload::GUIDVector(NoAttribute, vec![1, u128::MAX, 3]),
+            Payload::ByteVector(NoAttribute, vec![1, 2]), Payload::ShortVector(NoAttribute, vec![1, 2]),
+            Payload::IntVector(Unique, vec![1, 2]), Payload::LongVector(Parted, vec![1, 1, 2]),
+            Payload::RealVector(NoAttribute, vec![1.5]), Payload::FloatVector(NoAttribute, vec![]),
+            Payload::CharVector(NoAttribute, symbol("text")), Payload::SymbolVector(Grouped, vec![symbol("a"), symbol(""), symbol("bc")]),
+            Payload::TimestampVector(NoAttribute, vec![1]), Payload::MonthVector(NoAttribute, vec![1]),
+            Payload::DateVector(NoAttribute, vec![1]), Payload::DateTimeVector(NoAttribute, vec![1]),
+            Payload::TimeSpanVector(NoAttribute, vec![1]), Payload::MinuteVector(NoAttribute, vec![1]),
+            Payload::SecondVector(NoAttribute, vec![1]), Payload::TimeVector(NoAttribute, vec![1]),
+        ];
+        let table = |attribute, names: Vec<&str>, columns: Vec<Payload>| Payload::Table(attribute, Box::new(Payload::Dictionary(
+            Box::new(Payload::SymbolVector(NoAttribute, names.into_iter().map(symbol).collect())),
+            Box::new(Payload::List(NoAttribute, columns)))));
+        let trades = table(Sorted, vec!["sym", "px", "notes"], vec![
+            Payload::SymbolVector(NoAttribute, vec![symbol("a"), symbol("b")]),
+            Payload::FloatVector(NoAttribute, vec![1.0, 2.0]),
+            Payload::List(NoAttribute, vec![Payload::CharVector(NoAttribute, symbol("x")), Payload::List(NoAttribute, vec![])])]);
+        let keyed = Payload::Dictionary(
+            Box::new(table(NoAttribute, vec!["k"], vec![Payload::LongVector(NoAttribute, vec![1, 2])])),
+            Box::new(trades.clone()));
+        let dictionary = Payload::Dictionary(
+            Box::new(Payload::SymbolVector(NoAttribute, vec![symbol("a"), symbol("b")])),
+            Box::new(Payload::List(NoAttribute, vec![Payload::Bool(false), keyed.clone()])));
+        let nested = Payload::List(NoAttribute, vec![
+            Payload::List(NoAttribute, atoms.clone()),
+            Payload::List(NoAttribute, vectors.clone()),
+            Payload::List(NoAttribute, vec![Payload::List(NoAttribute, vec![dictionary.clone()]), Payload::Error(symbol("x"))]),
+        ]);
+        atoms.into_iter().chain(vectors).chain(vec![trades, keyed, dictionary, nested]).collect()
+    }
+
+    #[test]
+    pub fn test_round_trip_every_type() {
+        for payload in every_type() {
+            let bytes = payload.to_bytes();
+            assert_eq!(payload.encoded_len(), bytes.len(), "{:?}", payload);
+            assert_eq!(payload.get_size() + 1, bytes.len());
+            assert_eq!(Payload::serialized_len(&bytes), Ok(Some(bytes.len())));
+            assert_eq!(Payload::decode(&bytes), Ok((payload.clone(), bytes.len())));
+            // Trailing bytes are left for the caller
+            let mut padded = bytes.clone();
+            padded.push(0xff);
+            assert_eq!(Payload::decode(&padded), Ok((payload.clone(), bytes.len())));
+            assert!(Payload::from_bytes(&padded).is_err());
+            for len in 0..bytes.len() {
+                assert!(Payload::decode(&bytes[..len]).is_err(), "{:?} cut to {}", payload, len);
+            }
+        }
+        let nils = Payload::NilVector(NoAttribute, vec![(), ()]);
+        assert_eq!(nils.encoded_len(), nils.to_bytes().len());
+        assert_eq!(Payload::from_bytes(&nils.to_bytes()), Ok(Payload::List(NoAttribute, vec![Payload::Nil, Payload::Nil])));
+    }
+
+    #[test]
+    pub fn test_encode_message() {
+        let char_vec_hex_str = hex::decode("01020000180000000a000a00000074686174736372617a79").unwrap();
+        let payload = Payload::CharVector(NoAttribute, KdbString::from("thatscrazy"));
+        assert_eq!(encode_message(SynchronisationType::Response, &payload), char_vec_hex_str);
+        assert_eq!(Payload::error("café").to_bytes(), hex::decode("80636166c3a900").unwrap());
+    }
+
+    #[test]
+    pub fn test_utf8_strings() {
+        let bytes = hex::decode("0b00020000005ac3bc7269636800e1ff00").unwrap();
+        let zurich = KdbString::from("Zürich");
+        if let Ok(Payload::SymbolVector(_, x)) = Payload::from_bytes(&bytes) {
+            assert_eq!(x[0], zurich);
+            assert!(x[1].to_str().is_err());
+            assert_eq!(x[1].clone().into_string(), Err(KdbString::from(vec![0xe1, 0xff])));
+            assert_eq!(x[1].to_string(), "\u{fffd}\u{fffd}");
+            assert_eq!(format!("{:?}", x[1]), r#"b"\xe1\xff""#);
+        } else {
+            panic!("Failed to decode symbols");
+        }
+        let payload = Payload::SymbolVector(NoAttribute, vec![zurich.clone(), KdbString::from(vec![0xe1, 0xff])]);
+        assert_eq!(Payload::from_bytes(&payload.to_bytes()), Ok(payload));
+        assert_eq!(zurich.to_str(), Ok("Zürich"));
+        assert_eq!(format!("{:?}", zurich), "\"Zürich\"");
+        assert_eq!(&KdbRequest::new("`Zürich").to_bytes()[8..], Payload::CharVector(NoAttribute, KdbString::from("`Zürich")).to_bytes().as_slice());
+
+        // A char is one byte whatever its value
+        let mut text = Payload::from_bytes(&hex::decode("0a000100000061").unwrap()).unwrap();
+        let byte = Payload::from_bytes(&[0xf6, 0xe9]).unwrap();
+        assert_eq!(byte, Payload::Char('\u{e9}'));
+        assert_eq!(byte.validate(), Ok(()));
+        assert_eq!(byte.to_bytes(), vec![0xf6, 0xe9]);
+        text.push(byte).unwrap();
+        assert_eq!(text, Payload::CharVector(NoAttribute, KdbString::from(vec![b'a', 0xe9])));
+        assert!(text.push(Payload::Char('€')).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::ops::Range;
+use std::path::Path;
+use memmap2::Mmap;
+use crate::splayed::map_file;
+
+/// Start of every file q writes with compression enabled by `.z.zd` or `-19!`
+pub(crate) const COMPRESSED_HEADER: [u8; 8] = *b"kxzipped";
+/// Uncompressed length, algorithm, level and log2 of the block size at the end of the file
+const TRAILER_LEN: usize = 16;
+
+/// Compression algorithms of `.z.zd`, by their id
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum Algorithm {
+    /// kx's own algorithm, as used for IPC
+    Kx = 1,
+    Gzip = 2,
+    Snappy = 3,
+    Lz4Hc = 4,
+    Zstd = 5,
+}
+
+impl TryFrom<u8> for Algorithm {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Algorithm::Kx),
+            2 => Ok(Algorithm::Gzip),
+            3 => Ok(Algorithm::Snappy),
+            4 => Ok(Algorithm::Lz4Hc),
+            5 => Ok(Algorithm::Zstd),
+            x => Err(format!("Unknown compression algorithm {}", x)),
+        }
+    }
+}
+
+/// What `-21!` reports for a compressed file
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CompressionInfo {
+    pub compressed_len: usize,
+    pub uncompressed_len: usize,
+    pub algorithm: Algorithm,
+    pub block_size: usize,
+    pub level: u8,
+}
+
+/// A compressed q file, read a block at a time so random access only decompresses what it needs.
+///
+/// The file is `kxzipped`, the compressed blocks back to back, the compressed length of each block
+/// as a u32, then a 16 byte trailer: the u64 uncompressed length, the algorithm, the level and log2
+/// of the logical block size, padded with zeros.
+pub struct CompressedFile {
+    map: Mmap,
+    info: CompressionInfo,
+    blocks: Vec<Range<usize>>,
+    /// Last block decompressed, sequential reads mostly stay within one
+    cache: RefCell<Option<(usize, Vec<u8>)>>,
+}
+
+impl CompressedFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CompressedFile, String> {
+        CompressedFile::from_map(map_file(path.as_ref())?).map_err(|x| format!("{}: {}", path.as_ref().display(), x))
+    }
+
+    pub(crate) fn from_map(map: Mmap) -> Result<CompressedFile, String> {
+        let (info, blocks) = parse(&map)?;
+        Ok(CompressedFile { map, info, blocks, cache: RefCell::new(None) })
+    }
+
+    pub fn info(&self) -> &CompressionInfo {
+        &self.info
+    }
+
+    /// Length of the uncompressed contents
+    pub fn len(&self) -> usize {
+        self.info.uncompressed_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.info.uncompressed_len == 0
+    }
+
+    /// Uncompressed bytes in `range`, decompressing only the blocks it overlaps
+    pub fn read(&self, range: Range<usize>) -> Result<Vec<u8>, String> {
+        if range.start > range.end || range.end > self.len() {
+            return Err(format!("{:?} out of range of {} bytes", range, self.len()));
+        }
+        let block_size = self.info.block_size;
+        let mut ret_val = Vec::with_capacity(range.len().min(block_size));
+        let mut position = range.start;
+        while position < range.end {
+            let block = position / block_size;
+            let start = position - block * block_size;
+            let end = (range.end - block * block_size).min(block_size);
+            let mut cache = self.cache.borrow_mut();
+            if cache.as_ref().map(|x| x.0) != Some(block) {
+                *cache = Some((block, self.block(block)?));
+            }
+            let data = &cache.as_ref().map(|x| &x.1).ok_or("Block cache is empty")?;
+            ret_val.extend_from_slice(data.get(start..end).ok_or_else(|| format!("Block {} is too short", block))?);
+            position += end - start;
+        }
+        Ok(ret_val)
+    }
+
+    /// The whole uncompressed contents
+    pub fn read_all(&self) -> Result<Vec<u8>, String> {
+        decompress_blocks(&self.map, &self.info, &self.blocks)
+    }
+
+    fn block(&self, index: usize) -> Result<Vec<u8>, String> {
+        decompress_block(&self.map, &self.info, &self.blocks, index)
+    }
+}
+
+/// Whether `bytes` start like a compressed q file
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&COMPRESSED_HEADER)
+}
+
+/// Uncompressed contents of a whole compressed q file
+pub fn decompress(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (info, blocks) = parse(bytes)?;
+    decompress_blocks(bytes, &info, &blocks)
+}
+
+fn parse(bytes: &[u8]) -> Result<(CompressionInfo, Vec<Range<usize>>), String> {
+    if !is_compressed(bytes) {
+        return Err(String::from("Not a compressed q file"));
+    }
+    let trailer = bytes.len().checked_sub(TRAILER_LEN).filter(|x| *x >= COMPRESSED_HEADER.len())
+        .map(|x| &bytes[x..]).ok_or("Incomplete compressed q file")?;
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&trailer[0..8]);
+    let uncompressed_len = usize::try_from(u64::from_le_bytes(len)).map_err(|x| x.to_string())?;
+    let algorithm = Algorithm::try_from(trailer[8])?;
+    if !(12..=20).contains(&trailer[10]) {
+        return Err(format!("Logical block size 2^{} is out of range", trailer[10]));
+    }
+    let block_size = 1usize << trailer[10];
+    let count = uncompressed_len.div_ceil(block_size);
+
+    let index_start = count.checked_mul(4).and_then(|x| (bytes.len() - TRAILER_LEN).checked_sub(x))
+        .filter(|x| *x >= COMPRESSED_HEADER.len()).ok_or("Incomplete compressed q file")?;
+    let mut blocks = Vec::with_capacity(count);
+    let mut start = COMPRESSED_HEADER.len();
+    for len in bytes[index_start..bytes.len() - TRAILER_LEN].chunks_exact(4) {
+        let end = start + u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        if end > index_start {
+            return Err(String::from("Compressed block runs past the block index"));
+        }
+        blocks.push(start..end);
+        start = end;
+    }
+    let info = CompressionInfo { compressed_len: bytes.len(), uncompressed_len, algorithm, block_size, level: trailer[9] };
+    Ok((info, blocks))
+}
+
+fn decompress_blocks(bytes: &[u8], info: &CompressionInfo, blocks: &[Range<usize>]) -> Result<Vec<u8>, String> {
+    // Grown as blocks decompress, a malformed trailer can claim far more than the file holds
+    let mut ret_val = Vec::new();
+    for index in 0..blocks.len() {
+        ret_val.extend_from_slice(&decompress_block(bytes, info, blocks, index)?);
+    }
+    Ok(ret_val)
+}
+
+fn decompress_block(bytes: &[u8], info: &CompressionInfo, blocks: &[Range<usize>], index: usize) -> Result<Vec<u8>, String> {
+    let block = &bytes[blocks[index].clone()];
+    // Every block is full except the last
+    let len = (info.uncompressed_len - index * info.block_size).min(info.block_size);
+    let data = match info.algorithm {
+        // The IPC format without the message header, its length still counts the header
+        Algorithm::Kx => crate::uncompress(block)?.split_off(8),
+        Algorithm::Gzip => gzip(block, len)?,
+        Algorithm::Snappy => snappy(block)?,
+        Algorithm::Lz4Hc => lz4(block, len)?,
+        Algorithm::Zstd => zstd(block, len)?,
+    };
+    match data.len() == len {
+        true => Ok(data),
+        false => Err(format!("Block {} decompressed to {} bytes instead of {}", index, data.len(), len)),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(block: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    let mut ret_val = Vec::with_capacity(len);
+    flate2::read::ZlibDecoder::new(block).read_to_end(&mut ret_val).map_err(|x| x.to_string())?;
+    Ok(ret_val)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip(_: &[u8], _: usize) -> Result<Vec<u8>, String> {
+    Err(String::from("Reading gzip compressed files needs the gzip feature"))
+}
+
+#[cfg(feature = "snappy")]
+fn snappy(block: &[u8]) -> Result<Vec<u8>, String> {
+    snap::raw::Decoder::new().decompress_vec(block).map_err(|x| x.to_string())
+}
+
+#[cfg(not(feature = "snappy"))]
+fn snappy(_: &[u8]) -> Result<Vec<u8>, String> {
+    Err(String::from("Reading snappy compressed files needs the snappy feature"))
+}
+
+#[cfg(feature = "lz4")]
+fn lz4(block: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    lz4_flex::block::decompress(block, len).map_err(|x| x.to_string())
+}
+
+#[cfg(not(feature = "lz4"))]
+fn lz4(_: &[u8], _: usize) -> Result<Vec<u8>, String> {
+    Err(String::from("Reading lz4 compressed files needs the lz4 feature"))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd(block: &[u8], len: usize) -> Result<Vec<u8>, String> {
+    zstd::bulk::decompress(block, len).map_err(|x| x.to_string())
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd(_: &[u8], _: usize) -> Result<Vec<u8>, String> {
+    Err(String::from("Reading zstd compressed files needs the zstd feature"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{Payload, VectorAttribute};
+    use crate::compressed::{decompress, Algorithm, CompressedFile};
+    use crate::fixtures::{compressed_file, kx};
+
+    #[test]
+    pub fn test_read_blocks() {
+        let vector = Payload::LongVector(VectorAttribute::NoAttribute, (0..2000).map(|x| x / 3).collect()).to_file_bytes();
+        let bytes = compressed_file(Algorithm::Kx, 12, &vector, kx);
+        assert_eq!(decompress(&bytes).unwrap(), vector);
+        assert_eq!(Payload::from_file_bytes(&bytes).unwrap(), Payload::from_file_bytes(&vector).unwrap());
+
+        let path = std::env::temp_dir().join(format!("iron_kdb_compressed_{}", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let file = CompressedFile::open(&path).unwrap();
+        assert_eq!((file.info().algorithm, file.info().block_size, file.len()), (Algorithm::Kx, 4096, vector.len()));
+        assert_eq!(file.read(4000..9000).unwrap(), vector[4000..9000]);
+        assert_eq!(file.read(100..200).unwrap(), vector[100..200]);
+        assert!(file.read(0..vector.len() + 1).is_err());
+        assert!(decompress(&bytes[..bytes.len() - 1]).is_err());
+
+        // 256 empty blocks claiming a MiB each
+        let mut lying = b"kxzipped".to_vec();
+        lying.extend_from_slice(&[0; 4 * 256]);
+        lying.extend_from_slice(&(256u64 << 20).to_le_bytes());
+        lying.extend_from_slice(&[Algorithm::Kx as u8, 6, 20, 0, 0, 0, 0, 0]);
+        assert!(decompress(&lying).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_algorithms() {
+        let data: Vec<u8> = (0..10000u32).flat_map(|x| (x % 7).to_le_bytes()).collect();
+        #[cfg(feature = "gzip")]
+        {
+            let gzip = |block: &[u8]| {
+                use std::io::Write;
+                let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(6));
+                encoder.write_all(block).unwrap();
+                encoder.finish().unwrap()
+            };
+            assert_eq!(decompress(&compressed_file(Algorithm::Gzip, 13, &data, gzip)).unwrap(), data);
+        }
+        #[cfg(feature = "snappy")]
+        {
+            let snappy = |block: &[u8]| snap::raw::Encoder::new().compress_vec(block).unwrap();
+            assert_eq!(decompress(&compressed_file(Algorithm::Snappy, 13, &data, snappy)).unwrap(), data);
+        }
+        #[cfg(feature = "lz4")]
+        assert_eq!(decompress(&compressed_file(Algorithm::Lz4Hc, 13, &data, lz4_flex::block::compress)).unwrap(), data);
+        #[cfg(feature = "zstd")]
+        {
+            let zstd = |block: &[u8]| zstd::bulk::compress(block, 3).unwrap();
+            assert_eq!(decompress(&compressed_file(Algorithm::Zstd, 13, &data, zstd)).unwrap(), data);
+        }
+        #[cfg(not(feature = "gzip"))]
+        assert!(decompress(&compressed_file(Algorithm::Gzip, 13, &data, |x| x.to_vec())).is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use std::fmt::{Display, Formatter};
+use crate::codec::{KdbString, Payload};
+use crate::temporal::{format_date, format_datetime, format_minute, format_month, format_second, format_time, format_timespan, format_timestamp};
+
+/// Console size set with `\c`, output is cut to fit as the q console does
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConsoleSize {
+    pub lines: usize,
+    pub columns: usize,
+}
+
+impl Default for ConsoleSize {
+    /// q's default of `\c 25 80`
+    fn default() -> Self {
+        ConsoleSize { lines: 25, columns: 80 }
+    }
+}
+
+/// A payload shown as the q console would at a given size, see `Payload::console`
+pub struct Console<'a> {
+    payload: &'a Payload,
+    size: Option<ConsoleSize>,
+}
+
+impl Payload {
+    /// Shows the payload cut to `size`, lines too long end in `..` and a last line of `..` stands
+    /// for those that don't fit
+    pub fn console(&self, size: ConsoleSize) -> Console<'_> {
+        Console { payload: self, size: Some(size) }
+    }
+}
+
+impl Display for Console<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let size = match self.size {
+            Some(size) => size,
+            None => return f.write_str(&lines(self.payload, usize::MAX, usize::MAX).join("\n")),
+        };
+        // q won't go below 10 by 10, and keeps a line for the prompt
+        let (height, width) = (size.lines.max(10) - 1, size.columns.max(10));
+        // Items past the width can't be seen, each takes at least one character
+        let lines = lines(self.payload, height, width);
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            match line.char_indices().nth(width) {
+                Some(_) => write!(f, "{}..", line.chars().take(width - 2).collect::<String>())?,
+                None => f.write_str(line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shows the payload as the q console does, `{:#}` lifts the default `\c 25 80` limits.
+/// `::` shows as nothing.
+impl Display for Payload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let size = if f.alternate() { None } else { Some(ConsoleSize::default()) };
+        Console { payload: self, size }.fmt(f)
+    }
+}
+
+/// Lines of the console output. Lists, tables and dictionaries longer than `rows` lines end in a
+/// line of `..`, and vectors show at most `items` items, so large results are never formatted whole.
+fn lines(payload: &Payload, rows: usize, items: usize) -> Vec<String> {
+    match payload {
+        Payload::Nil => Vec::new(),
+        // Each item of a general list gets its own line
+        Payload::List(_, values) if values.len() > 1 => {
+            let shown = if values.len() > rows { rows - 1 } else { values.len() };
+            let mut ret_val: Vec<String> = values[..shown].iter().map(|x| inline_items(x, items)).collect();
+            if shown < values.len() {
+                ret_val.push(String::from(".."));
+            }
+            ret_val
+        }
+        // Keyed tables show the key columns, then the others
+        Payload::Dictionary(keys, values) if is_table(keys) && is_table(values) => {
+            let keys = lines(keys, rows, items);
+            let width = keys.first().map(String::len).unwrap_or(0);
+            lines(values, rows, items).iter().zip(&keys).map(|(value, key)| match key.as_str() {
+                ".." => key.clone(),
+                _ => format!("{:width$}| {}", key, value, width = width),
+            }).collect()
+        }
+        Payload::Dictionary(keys, values) => {
+            let mut keys = cells(keys, rows.saturating_add(1));
+            let cut = keys.len() > rows;
+            keys.truncate(if cut { rows - 1 } else { keys.len() });
+            let width = keys.iter().map(String::len).max().unwrap_or(0);
+            let mut ret_val: Vec<String> = keys.iter().zip(cells(values, keys.len()))
+                .map(|(key, value)| format!("{:width$}| {}", key, value, width = width)).collect();
+            if cut {
+                ret_val.push(String::from(".."));
+            }
+            ret_val
+        }
+        Payload::Table(_, dictionary) => match dictionary.as_ref() {
+            Payload::Dictionary(names, values) => table(&cells(names, usize::MAX), values, rows),
+            x => vec![inline_items(x, items)],
+        },
+        x => vec![inline_items(x, items)],
+    }
+}
+
+fn is_table(payload: &Payload) -> bool {
+    matches!(payload, Payload::Table(_, _))
+}
+
+/// Header, dashes and rows of a table in at most `rows` lines, every column as wide as the widest
+/// entry shown
+fn table(names: &[String], values: &Payload, rows: usize) -> Vec<String> {
+    let available = rows.saturating_sub(2);
+    let mut columns: Vec<Vec<String>> = match values {
+        Payload::List(_, columns) => columns.iter().map(|x| cells(x, available.saturating_add(1))).collect(),
+        x => vec![cells(x, available.saturating_add(1))],
+    };
+    let count = columns.iter().map(Vec::len).max().unwrap_or(0);
+    let cut = count > available;
+    let shown = if cut { available.saturating_sub(1) } else { count };
+    columns.iter_mut().for_each(|x| x.truncate(shown));
+    let widths: Vec<usize> = names.iter().zip(&columns)
+        .map(|(name, column)| column.iter().map(String::len).chain(Some(name.len())).max().unwrap_or(0)).collect();
+    let row = |cells: Vec<&str>| cells.iter().zip(&widths).map(|(x, width)| format!("{:width$}", x, width = width)).collect::<Vec<_>>().join(" ");
+    let mut ret_val = vec![row(names.iter().map(String::as_str).collect())];
+    ret_val.push("-".repeat(ret_val[0].len()));
+    for index in 0..shown {
+        ret_val.push(row(columns.iter().map(|x| x.get(index).map(String::as_str).unwrap_or("")).collect()));
+    }
+    if cut {
+        ret_val.push(String::from(".."));
+    }
+    ret_val
+}
+
+/// The first `limit` items of a vector as they appear in a table column, without decoration and
+/// with nulls blank
+fn cells(payload: &Payload, limit: usize) -> Vec<String> {
+    fn each<T, F: Fn(&T) -> Option<String>>(values: &[T], limit: usize, f: F) -> Vec<String> {
+        values.iter().take(limit).map(|x| f(x).unwrap_or_default()).collect()
+    }
+
+    match payload {
+        Payload::List(_, items) => items.iter().take(limit).map(inline).collect(),
+        Payload::BoolVector(_, x) => each(x, limit, |x| Some(String::from(if *x { "1" } else { "0" }))),
+        Payload::GUIDVector(_, x) => each(x, limit, |x| Some(guid(*x))),
+        Payload::ByteVector(_, x) => each(x, limit, |x| Some(format!("{:02x}", x))),
+        Payload::ShortVector(_, x) => each(x, limit, |x| integer(*x as i16 as i64, i16::MIN as i64, i16::MAX as i64)),
+        Payload::IntVector(_, x) => each(x, limit, |x| integer(*x as i32 as i64, i32::MIN as i64, i32::MAX as i64)),
+        Payload::LongVector(_, x) => each(x, limit, |x| integer(*x as i64, i64::MIN, i64::MAX)),
+        Payload::RealVector(_, x) => each(x, limit, |x| float(*x as f64)),
+        Payload::FloatVector(_, x) => each(x, limit, |x| float(*x)),
+        Payload::CharVector(_, x) => x.as_bytes().iter().take(limit).map(|item| String::from_utf8_lossy(&[*item]).into_owned()).collect(),
+        Payload::SymbolVector(_, x) => x.iter().take(limit).map(KdbString::to_string).collect(),
+        Payload::InternedSymbolVector(_, t, x) => t.symbols(&x[..x.len().min(limit)]).iter().map(KdbString::to_string).collect(),
+        Payload::TimestampVector(_, x) => each(x, limit, |x| temporal(*x as i64, i64::MIN, |x| format_timestamp(x, false))),
+        Payload::MonthVector(_, x) => each(x, limit, |x| temporal(*x as i32 as i64, i32::MIN as i64, |x| format_month(x as i32))),
+        Payload::DateVector(_, x) => each(x, limit, |x| temporal(*x as i32 as i64, i32::MIN as i64, |x| format_date(x as i32))),
+        Payload::DateTimeVector(_, x) => each(x, limit, |x| datetime(f64::from_bits(*x))),
+        Payload::TimeSpanVector(_, x) => each(x, limit, |x| temporal(*x as i64, i64::MIN, format_timespan)),
+        Payload::MinuteVector(_, x) => each(x, limit, |x| temporal(*x as i32 as i64, i32::MIN as i64, format_minute)),
+        Payload::SecondVector(_, x) => each(x, limit, |x| temporal(*x as i32 as i64, i32::MIN as i64, format_second)),
+        Payload::TimeVector(_, x) => each(x, limit, |x| temporal(*x as i32 as i64, i32::MIN as i64, format_time)),
+        x => vec![inline(x)],
+    }
+}
+
+/// The one line form of `-3!`, which the console also uses for atoms and vectors
+fn inline(payload: &Payload) -> String {
+    inline_items(payload, usize::MAX)
+}
+
+/// `inline` with at most `limit` items of a vector or list, enough to fill a line
+fn inline_items(payload: &Payload, limit: usize) -> String {
+    match payload {
+        Payload::Nil => String::from("::"),
+        Payload::Error(x) => format!("'{}", x),
+        Payload::Bool(x) => format!("{}b", *x as u8),
+        Payload::GUID(x) => guid(*x),
+        Payload::Byte(x) => format!("0x{:02x}", x),
+        Payload::Short(x) => integer(*x as i16 as i64, i16::MIN as i64, i16::MAX as i64).unwrap_or_else(|| String::from("0N")) + "h",
+        Payload::Int(x) => integer(*x as i32 as i64, i32::MIN as i64, i32::MAX as i64).unwrap_or_else(|| String::from("0N")) + "i",
+        Payload::Long(x) => integer(*x as i64, i64::MIN, i64::MAX).unwrap_or_else(|| String::from("0N")),
+        Payload::Real(x) => float(*x as f64).unwrap_or_else(|| String::from("0N")) + "e",
+        Payload::Float(x) => match float(*x) {
+            Some(x) if is_integral(&x) => x + "f",
+            Some(x) => x,
+            None => String::from("0n"),
+        },
+        Payload::Char(x) => format!("\"{}\"", x),
+        Payload::Symbol(x) => format!("`{}", x),
+        Payload::Timestamp(x) => temporal(*x as i64, i64::MIN, |x| format_timestamp(x, false)).unwrap_or_else(|| String::from("0Np")),
+        Payload::Month(x) => temporal(*x as i32 as i64, i32::MIN as i64, |x| format_month(x as i32) + "m").unwrap_or_else(|| String::from("0Nm")),
+        Payload::Date(x) => temporal(*x as i32 as i64, i32::MIN as i64, |x| format_date(x as i32)).unwrap_or_else(|| String::from("0Nd")),
+        Payload::DateTime(x) => datetime(f64::from_bits(*x)).unwrap_or_else(|| String::from("0Nz")),
+        Payload::TimeSpan(x) => temporal(*x as i64, i64::MIN, format_timespan).unwrap_or_else(|| String::from("0Nn")),
+        Payload::Minute(x) => temporal(*x as i32 as i64, i32::MIN as i64, format_minute).unwrap_or_else(|| String::from("0Nu")),
+        Payload::Second(x) => temporal(*x as i32 as i64, i32::MIN as i64, format_second).unwrap_or_else(|| String::from("0Nv")),
+        Payload::Time(x) => temporal(*x as i32 as i64, i32::MIN as i64, format_time).unwrap_or_else(|| String::from("0Nt")),
+        Payload::List(_, items) => match items.len() {
+            0 => String::from("()"),
+            1 => format!(",{}", inline(&items[0])),
+            _ => format!("({})", items.iter().take(limit).map(inline).collect::<Vec<_>>().join(";")),
+        },
+        // A table or dictionary as the key needs parentheses to parse back
+        Payload::Dictionary(keys, values) if is_table(keys) || matches!(keys.as_ref(), Payload::Dictionary(_, _)) => format!("({})!{}", inline(keys), inline(values)),
+        Payload::Dictionary(keys, values) => format!("{}!{}", inline(keys), inline(values)),
+        Payload::Table(_, dictionary) => format!("+{}", inline(dictionary)),
+        Payload::NilVector(_, x) => format!("({})", vec!["::"; x.len().min(limit)].join(";")),
+        Payload::CharVector(_, x) => match x.len() {
+            1 => format!(",\"{}\"", x),
+            len => format!("\"{}\"", String::from_utf8_lossy(&x.as_bytes()[..len.min(limit)])),
+        },
+        x @ Payload::InternedSymbolVector(..) => inline_items(&x.clone().resolve_symbols(), limit),
+        Payload::SymbolVector(_, x) if !x.is_empty() => {
+            let symbols: String = x.iter().take(limit).map(|x| format!("`{}", x)).collect();
+            if x.len() == 1 { format!(",{}", symbols) } else { symbols }
+        }
+        Payload::BoolVector(_, x) if !x.is_empty() => vector(x.len(), x.iter().take(limit).map(|x| (*x as u8).to_string()).collect::<String>() + "b"),
+        Payload::ByteVector(_, x) if !x.is_empty() => vector(x.len(), format!("0x{}", x.iter().take(limit).map(|x| format!("{:02x}", x)).collect::<String>())),
+        x => {
+            let items = cells(x, limit);
+            if items.is_empty() {
+                return format!("`{}$()", type_name(x.type_byte()));
+            }
+            let all_null = items.iter().all(String::is_empty);
+            let items: Vec<&str> = items.iter().map(|item| match item.as_str() {
+                "" => null(x.type_byte()),
+                item => item,
+            }).collect();
+            let suffix = match x.type_byte() {
+                5 => "h",
+                6 => "i",
+                8 => "e",
+                9 if items.iter().all(|x| is_integral(x)) => "f",
+                13 => "m",
+                12 | 14..=19 if all_null => null_suffix(x.type_byte()),
+                _ => "",
+            };
+            vector(items.len(), items.join(" ") + suffix)
+        }
+    }
+}
+
+/// A one item vector is shown enlisted
+fn vector(len: usize, text: String) -> String {
+    match len {
+        1 => format!(",{}", text),
+        _ => text,
+    }
+}
+
+fn is_integral(text: &str) -> bool {
+    text.bytes().all(|x| x.is_ascii_digit() || x == b'-')
+}
+
+fn null(type_byte: i8) -> &'static str {
+    match type_byte {
+        9 => "0n",
+        _ => "0N",
+    }
+}
+
+fn null_suffix(type_byte: i8) -> &'static str {
+    match type_byte {
+        12 => "p",
+        14 => "d",
+        15 => "z",
+        16 => "n",
+        17 => "u",
+        18 => "v",
+        19 => "t",
+        _ => "",
+    }
+}
+
+fn type_name(type_byte: i8) -> &'static str {
+    match type_byte {
+        1 => "boolean",
+        2 => "guid",
+        4 => "byte",
+        5 => "short",
+        6 => "int",
+        7 => "long",
+        8 => "real",
+        9 => "float",
+        10 => "char",
+        11 => "symbol",
+        12 => "timestamp",
+        13 => "month",
+        14 => "date",
+        15 => "datetime",
+        16 => "timespan",
+        17 => "minute",
+        18 => "second",
+        19 => "time",
+        _ => "",
+    }
+}
+
+fn guid(x: u128) -> String {
+    let hex: String = x.to_le_bytes().iter().map(|x| format!("{:02x}", x)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+/// Integers with q's null and infinities, `None` for null
+fn integer(x: i64, null: i64, infinity: i64) -> Option<String> {
+    match x {
+        x if x == null => None,
+        x if x == infinity => Some(String::from("0W")),
+        x if x == -infinity => Some(String::from("-0W")),
+        x => Some(x.to_string()),
+    }
+}
+
+/// C's `%.7g`, the default `\P 7` precision, `None` for null
+fn float(x: f64) -> Option<String> {
+    const PRECISION: i32 = 7;
+    if x.is_nan() {
+        return None;
+    }
+    if x.is_infinite() {
+        return Some(String::from(if x > 0.0 { "0w" } else { "-0w" }));
+    }
+    if x == 0.0 {
+        return Some(String::from("0"));
+    }
+    let scientific = format!("{:.*e}", PRECISION as usize - 1, x);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let trim = |x: &str| match x.contains('.') {
+        true => x.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => x.to_string(),
+    };
+    Some(match !(-4..PRECISION).contains(&exponent) {
+        true => format!("{}e{}{:02}", trim(mantissa), if exponent < 0 { '-' } else { '+' }, exponent.abs()),
+        false => trim(&format!("{:.*}", (PRECISION - 1 - exponent) as usize, x)),
+    })
+}
+
+/// Applies `f` to temporal values that aren't null or infinite, `null` is the minimum of the type
+fn temporal<F: Fn(i64) -> String>(x: i64, null: i64, f: F) -> Option<String> {
+    match x {
+        x if x == null => None,
+        x if x == -(null + 1) => Some(String::from("0W")),
+        x if x == null + 1 => Some(String::from("-0W")),
+        x => Some(f(x)),
+    }
+}
+
+fn datetime(days: f64) -> Option<String> {
+    Some(days).filter(|x| !x.is_nan()).map(|x| format_datetime(x, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::console::ConsoleSize;
+    use crate::builder::table;
+    use crate::fixtures::symbols;
+
+    #[test]
+    pub fn test_format_atoms_and_vectors() {
+        assert_eq!(Payload::Long(42).to_string(), "42");
+        assert_eq!(Payload::Float(2.0).to_string(), "2f");
+        assert_eq!(Payload::Float(std::f64::consts::PI).to_string(), "3.141593");
+        assert_eq!(Payload::Float(1e10).to_string(), "1e+10");
+        assert_eq!(Payload::Int(i32::MIN as u32).to_string(), "0Ni");
+        assert_eq!(Payload::LongVector(VectorAttribute::NoAttribute, vec![1, i64::MIN as u64, 3]).to_string(), "1 0N 3");
+        assert_eq!(Payload::ShortVector(VectorAttribute::NoAttribute, vec![1]).to_string(), ",1h");
+        assert_eq!(Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0, 2.0]).to_string(), "1 2f");
+        assert_eq!(Payload::BoolVector(VectorAttribute::NoAttribute, vec![true, false]).to_string(), "10b");
+        assert_eq!(Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["a", "b"])).to_string(), "`a`b");
+        assert_eq!(Payload::LongVector(VectorAttribute::NoAttribute, vec![]).to_string(), "`long$()");
+        assert_eq!(Payload::Timestamp(8796 * 86_400_000_000_000 + 3_600_000_000_001).to_string(), "2024.01.31D01:00:00.000000001");
+        assert_eq!(Payload::Month(288).to_string(), "2024.01m");
+        assert_eq!(Payload::TimeVector(VectorAttribute::NoAttribute, vec![1000, 61_001]).to_string(), "00:00:01.000 00:01:01.001");
+        assert_eq!(Payload::GUID(0).to_string(), "00000000-0000-0000-0000-000000000000");
+        assert_eq!(Payload::error("type").to_string(), "'type");
+        assert_eq!(Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Long(1), Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("ab"))]).to_string(), "1\n\"ab\"");
+    }
+
+    #[test]
+    pub fn test_format_tables_and_dictionaries() {
+        let trade = table(&["sym", "px"], vec![
+            Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["a", "bbbb"])),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, f64::NAN])]);
+        assert_eq!(trade.to_string(), "sym  px \n--------\na    1.5\nbbbb    ");
+        let dictionary = Payload::Dictionary(
+            Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["a", "bc"]))),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![Payload::Symbol(KdbString::from("x")),
+                Payload::LongVector(VectorAttribute::NoAttribute, vec![1, 2])])));
+        assert_eq!(dictionary.to_string(), "a | `x\nbc| 1 2");
+
+        let keyed = Payload::Dictionary(Box::new(table(&["sym"], vec![Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["a", "b"]))])),
+            Box::new(table(&["px"], vec![Payload::LongVector(VectorAttribute::NoAttribute, vec![1, 2])])));
+        assert_eq!(keyed.to_string(), "sym| px\n---| --\na  | 1 \nb  | 2 ");
+    }
+
+    #[test]
+    pub fn test_console_size() {
+        let numbers = Payload::LongVector(VectorAttribute::NoAttribute, (0..1000).collect());
+        assert_eq!(numbers.console(ConsoleSize { lines: 10, columns: 12 }).to_string(), "0 1 2 3 4 ..");
+        assert_eq!(format!("{:#}", numbers).len(), 3889);
+        let rows = table(&["a"], vec![Payload::LongVector(VectorAttribute::NoAttribute, (0..1000).collect())]);
+        assert_eq!(rows.console(ConsoleSize { lines: 10, columns: 80 }).to_string(), "a\n-\n0\n1\n2\n3\n4\n5\n..");
+        assert_eq!(rows.to_string().lines().count(), 24);
+    }
+}
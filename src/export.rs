@@ -0,0 +1,420 @@
+use std::io::{Read, Write};
+use crate::KdbConnection;
+use crate::codec::{KdbRequest, Payload};
+use crate::builder::table_parts;
+use crate::temporal::{format_datetime, format_minute, format_second, format_time, format_timespan, format_timestamp, iso_date, iso_month};
+
+/// Server side variable in `.iron` holding a result while its pages are fetched, suffixed with the
+/// connection's handle so exports over other connections to the same process don't collide
+const PAGED_RESULT: &str = "export";
+
+/// Runs `query` once and passes its result, which must be a table, to `f` `page_size` rows at a
+/// time. The result is kept on the server until the last page is fetched, so large results never
+/// need to fit in one message. An empty result is passed as one empty page so its columns are known.
+pub fn query_pages<R: Read, W: Write, F: FnMut(Payload) -> Result<(), String>>(connection: &mut KdbConnection<R, W>, query: &str, page_size: usize, mut f: F) -> Result<(), String> {
+    if page_size == 0 {
+        return Err(String::from("Page size must be positive"));
+    }
+    let name = match run(connection, ".z.w")? {
+        Payload::Int(x) => format!("{}{}", PAGED_RESULT, x),
+        x => return Err(format!("Expected a handle, got type {}", x.type_byte())),
+    };
+    let variable = format!(".iron.{}", name);
+    let escaped = query.replace('\\', "\\\\").replace('"', "\\\"");
+    let count = match run(connection, &format!("{}:0!value\"{}\";count {}", variable, escaped, variable))? {
+        Payload::Long(x) => x as usize,
+        x => return Err(format!("Expected a row count, got type {}", x.type_byte())),
+    };
+    let mut result = Ok(());
+    for start in (0..count.max(1)).step_by(page_size) {
+        let rows = page_size.min(count - start);
+        result = run(connection, &format!("{} {}+til {}", variable, start, rows)).and_then(&mut f);
+        if result.is_err() {
+            break;
+        }
+    }
+    // Free the result whether or not every page was written
+    let cleanup = run(connection, &format!("delete {} from `.iron", name));
+    result.and(cleanup.map(|_| ()))
+}
+
+fn run<R: Read, W: Write>(connection: &mut KdbConnection<R, W>, query: &str) -> Result<Payload, String> {
+    match connection.query(KdbRequest::new(query))? {
+        Payload::Error(x) => Err(format!("'{}", x)),
+        x => Ok(x),
+    }
+}
+
+/// Writes tables to a file a page at a time, `finish` must be called after the last page
+pub trait TableWriter {
+    fn write_table(&mut self, table: &Payload) -> Result<(), String>;
+
+    fn finish(&mut self) -> Result<(), String>;
+}
+
+/// Comma separated values with a header line, nulls are left empty and temporals are ISO 8601
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    header: bool,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W) -> CsvWriter<W> {
+        CsvWriter { writer, header: false }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TableWriter for CsvWriter<W> {
+    fn write_table(&mut self, table: &Payload) -> Result<(), String> {
+        let (names, columns) = table_parts(table)?;
+        if !self.header {
+            let header: Vec<String> = names.iter().map(|x| csv_field(&x.to_string())).collect();
+            writeln!(self.writer, "{}", header.join(",")).map_err(|x| x.to_string())?;
+            self.header = true;
+        }
+        let columns = columns.iter().map(values).collect::<Result<Vec<_>, _>>()?;
+        for row in 0..columns.first().map(Vec::len).unwrap_or(0) {
+            let fields: Vec<String> = columns.iter().map(|x| match &x[row] {
+                Value::Null => String::new(),
+                Value::Bool(x) => x.to_string(),
+                Value::Int(x) => x.to_string(),
+                Value::Float(x) => x.to_string(),
+                Value::Text(x) => csv_field(x),
+            }).collect();
+            writeln!(self.writer, "{}", fields.join(",")).map_err(|x| x.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|x| x.to_string())
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    match text.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+/// One JSON object per row and line, nulls are `null` and temporals are ISO 8601 strings
+pub struct NdjsonWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> NdjsonWriter<W> {
+    pub fn new(writer: W) -> NdjsonWriter<W> {
+        NdjsonWriter { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TableWriter for NdjsonWriter<W> {
+    fn write_table(&mut self, table: &Payload) -> Result<(), String> {
+        let (names, columns) = table_parts(table)?;
+        let names: Vec<String> = names.iter().map(|x| json_string(&x.to_string())).collect();
+        let columns = columns.iter().map(values).collect::<Result<Vec<_>, _>>()?;
+        for row in 0..columns.first().map(Vec::len).unwrap_or(0) {
+            let fields: Vec<String> = names.iter().zip(&columns).map(|(name, x)| format!("{}:{}", name, match &x[row] {
+                Value::Null => String::from("null"),
+                Value::Bool(x) => x.to_string(),
+                Value::Int(x) => x.to_string(),
+                // JSON has no infinities
+                Value::Float(x) if !x.is_finite() => String::from("null"),
+                Value::Float(x) => x.to_string(),
+                Value::Text(x) => json_string(x),
+            })).collect();
+            writeln!(self.writer, "{{{}}}", fields.join(",")).map_err(|x| x.to_string())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|x| x.to_string())
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut ret_val = String::with_capacity(text.len() + 2);
+    ret_val.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => ret_val.push_str("\\\""),
+            '\\' => ret_val.push_str("\\\\"),
+            '\n' => ret_val.push_str("\\n"),
+            '\r' => ret_val.push_str("\\r"),
+            '\t' => ret_val.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret_val.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret_val.push(c),
+        }
+    }
+    ret_val.push('"');
+    ret_val
+}
+
+/// A table cell as exported, q's nulls become `Null`
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+/// Cells of one table column
+fn values(column: &Payload) -> Result<Vec<Value>, String> {
+    fn each<T, F: Fn(&T) -> Value>(values: &[T], f: F) -> Vec<Value> {
+        values.iter().map(f).collect()
+    }
+    fn int(x: i64, null: i64) -> Value {
+        if x == null { Value::Null } else { Value::Int(x) }
+    }
+    fn float(x: f64) -> Value {
+        if x.is_nan() { Value::Null } else { Value::Float(x) }
+    }
+    fn text<F: Fn(i64) -> String>(x: i64, null: i64, f: F) -> Value {
+        if x == null { Value::Null } else { Value::Text(f(x)) }
+    }
+
+    Ok(match column {
+        Payload::BoolVector(_, x) => each(x, |x| Value::Bool(*x)),
+        Payload::GUIDVector(_, x) => each(x, |x| match x {
+            0 => Value::Null,
+            x => Value::Text(Payload::GUID(*x).to_string()),
+        }),
+        Payload::ByteVector(_, x) => each(x, |x| Value::Int(*x as i64)),
+        Payload::ShortVector(_, x) => each(x, |x| int(*x as i16 as i64, i16::MIN as i64)),
+        Payload::IntVector(_, x) => each(x, |x| int(*x as i32 as i64, i32::MIN as i64)),
+        Payload::LongVector(_, x) => each(x, |x| int(*x as i64, i64::MIN)),
+        Payload::RealVector(_, x) => each(x, |x| float(*x as f64)),
+        Payload::FloatVector(_, x) => each(x, |x| float(*x)),
+        Payload::CharVector(_, x) => x.as_bytes().iter().map(|item| Value::Text(String::from_utf8_lossy(&[*item]).into_owned())).collect(),
+        Payload::SymbolVector(_, x) => each(x, |x| match x.is_empty() {
+            true => Value::Null,
+            false => Value::Text(x.to_string()),
+        }),
+        x @ Payload::InternedSymbolVector(..) => values(&x.clone().resolve_symbols())?,
+        Payload::TimestampVector(_, x) => each(x, |x| text(*x as i64, i64::MIN, |x| format_timestamp(x, true))),
+        Payload::MonthVector(_, x) => each(x, |x| text(*x as i32 as i64, i32::MIN as i64, |x| iso_month(x as i32))),
+        Payload::DateVector(_, x) => each(x, |x| text(*x as i32 as i64, i32::MIN as i64, |x| iso_date(x as i32))),
+        Payload::DateTimeVector(_, x) => each(x, |x| match f64::from_bits(*x) {
+            x if x.is_nan() => Value::Null,
+            x => Value::Text(format_datetime(x, true)),
+        }),
+        Payload::TimeSpanVector(_, x) => each(x, |x| text(*x as i64, i64::MIN, format_timespan)),
+        Payload::MinuteVector(_, x) => each(x, |x| text(*x as i32 as i64, i32::MIN as i64, format_minute)),
+        Payload::SecondVector(_, x) => each(x, |x| text(*x as i32 as i64, i32::MIN as i64, format_second)),
+        Payload::TimeVector(_, x) => each(x, |x| text(*x as i32 as i64, i32::MIN as i64, format_time)),
+        // Strings, anything else nested is written as q shows it
+        Payload::List(_, x) => each(x, |x| match x {
+            Payload::CharVector(_, x) => Value::Text(x.to_string()),
+            x => Value::Text(format!("{:#}", x)),
+        }),
+        x => return Err(format!("Can't export a column of type {}", x.type_byte())),
+    })
+}
+
+/// Days from 1970.01.01 of a q date, as parquet stores dates
+#[cfg(feature = "parquet")]
+const fn unix_days(date: i32) -> i32 {
+    date + crate::temporal::KDB_EPOCH_DAYS
+}
+
+/// Parquet with the column types kept: temporals become parquet timestamps, dates and times,
+/// symbols and strings become UTF-8. Each page is written as its own row group.
+#[cfg(feature = "parquet")]
+pub struct ParquetWriter<W: Write + Send> {
+    writer: Option<W>,
+    arrow: Option<parquet::arrow::ArrowWriter<W>>,
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W) -> ParquetWriter<W> {
+        ParquetWriter { writer: Some(writer), arrow: None }
+    }
+
+    /// The underlying writer, once `finish` has written the footer
+    pub fn into_inner(self) -> Option<W> {
+        self.writer
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl<W: Write + Send> TableWriter for ParquetWriter<W> {
+    fn write_table(&mut self, table: &Payload) -> Result<(), String> {
+        use std::sync::Arc;
+        use arrow_array::RecordBatch;
+        use arrow_schema::{Field, Schema};
+
+        let (names, columns) = table_parts(table)?;
+        let arrays = columns.iter().map(arrow_array).collect::<Result<Vec<_>, _>>()?;
+        let fields: Vec<Field> = names.iter().zip(&arrays).map(|(name, x)| Field::new(name.to_string(), x.data_type().clone(), true)).collect();
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).map_err(|x| x.to_string())?;
+        if self.arrow.is_none() {
+            let writer = self.writer.take().ok_or("Parquet writer is already finished")?;
+            self.arrow = Some(parquet::arrow::ArrowWriter::try_new(writer, batch.schema(), None).map_err(|x| x.to_string())?);
+        }
+        let arrow = self.arrow.as_mut().ok_or("Parquet writer is already finished")?;
+        arrow.write(&batch).map_err(|x| x.to_string())?;
+        arrow.flush().map_err(|x| x.to_string())
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        match self.arrow.take() {
+            Some(arrow) => {
+                self.writer = Some(arrow.into_inner().map_err(|x| x.to_string())?);
+                Ok(())
+            }
+            None => Err(String::from("Can't write a parquet file without any tables")),
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn arrow_array(column: &Payload) -> Result<arrow_array::ArrayRef, String> {
+    use std::sync::Arc;
+    use arrow_array::*;
+
+    fn nullable<T: Copy, U, F: Fn(T) -> U>(values: &[T], null: impl Fn(T) -> bool, f: F) -> Vec<Option<U>> {
+        values.iter().map(|x| if null(*x) { None } else { Some(f(*x)) }).collect()
+    }
+    const UNIX_NANOS: i64 = crate::temporal::KDB_EPOCH_DAYS as i64 * crate::temporal::NANOS_PER_DAY;
+
+    Ok(match column {
+        Payload::BoolVector(_, x) => Arc::new(BooleanArray::from(x.clone())),
+        Payload::ByteVector(_, x) => Arc::new(UInt8Array::from(x.clone())),
+        Payload::ShortVector(_, x) => Arc::new(Int16Array::from(nullable(x, |x| x == 0x8000, |x| x as i16))),
+        Payload::IntVector(_, x) => Arc::new(Int32Array::from(nullable(x, |x| x as i32 == i32::MIN, |x| x as i32))),
+        Payload::LongVector(_, x) => Arc::new(Int64Array::from(nullable(x, |x| x as i64 == i64::MIN, |x| x as i64))),
+        Payload::RealVector(_, x) => Arc::new(Float32Array::from(nullable(x, |x| x.is_nan(), |x| x))),
+        Payload::FloatVector(_, x) => Arc::new(Float64Array::from(nullable(x, |x| x.is_nan(), |x| x))),
+        Payload::TimestampVector(_, x) => Arc::new(TimestampNanosecondArray::from(nullable(x, |x| x as i64 == i64::MIN, |x| (x as i64).saturating_add(UNIX_NANOS)))),
+        Payload::MonthVector(_, x) => Arc::new(Date32Array::from(nullable(x, |x| x as i32 == i32::MIN, |x| {
+            let month = x as i32;
+            unix_days(crate::temporal::days_from_civil(2000 + month.div_euclid(12), month.rem_euclid(12) as u32 + 1, 1))
+        }))),
+        Payload::DateVector(_, x) => Arc::new(Date32Array::from(nullable(x, |x| x as i32 == i32::MIN, |x| unix_days(x as i32)))),
+        Payload::DateTimeVector(_, x) => Arc::new(TimestampMillisecondArray::from(nullable(x, |x| f64::from_bits(x).is_nan(),
+            |x| ((f64::from_bits(x) + crate::temporal::KDB_EPOCH_DAYS as f64) * 86_400_000.0).round() as i64))),
+        Payload::TimeSpanVector(_, x) => Arc::new(DurationNanosecondArray::from(nullable(x, |x| x as i64 == i64::MIN, |x| x as i64))),
+        Payload::MinuteVector(_, x) => Arc::new(Time32SecondArray::from(nullable(x, |x| x as i32 == i32::MIN, |x| x as i32 * 60))),
+        Payload::SecondVector(_, x) => Arc::new(Time32SecondArray::from(nullable(x, |x| x as i32 == i32::MIN, |x| x as i32))),
+        Payload::TimeVector(_, x) => Arc::new(Time32MillisecondArray::from(nullable(x, |x| x as i32 == i32::MIN, |x| x as i32))),
+        x => Arc::new(StringArray::from(values(x)?.into_iter().map(|x| match x {
+            Value::Null => None,
+            Value::Text(x) => Some(x),
+            x => Some(format!("{:?}", x)),
+        }).collect::<Vec<_>>())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::KdbConnection;
+    use crate::export::{query_pages, CsvWriter, NdjsonWriter, TableWriter};
+    use crate::mock::MockServer;
+    use crate::builder::table;
+
+    fn trades() -> Payload {
+        table(&["sym", "time", "px", "note"], vec![
+            Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("a"), KdbString::new()]),
+            Payload::TimestampVector(VectorAttribute::NoAttribute, vec![8796 * 86_400_000_000_000 + 1, i64::MIN as u64]),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, f64::NAN]),
+            Payload::List(VectorAttribute::NoAttribute, vec![
+                Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("x,\"y\"")),
+                Payload::CharVector(VectorAttribute::NoAttribute, KdbString::new())])])
+    }
+
+    #[test]
+    pub fn test_csv_and_ndjson() {
+        let mut csv = CsvWriter::new(Vec::new());
+        csv.write_table(&trades()).unwrap();
+        csv.write_table(&trades()).unwrap();
+        csv.finish().unwrap();
+        let row = "a,2024-01-31T00:00:00.000000001,1.5,\"x,\"\"y\"\"\"\n,,,\n";
+        assert_eq!(String::from_utf8(csv.into_inner()).unwrap(), format!("sym,time,px,note\n{}{}", row, row));
+
+        let mut ndjson = NdjsonWriter::new(Vec::new());
+        ndjson.write_table(&trades()).unwrap();
+        assert!(ndjson.write_table(&Payload::Long(1)).is_err());
+        assert_eq!(String::from_utf8(ndjson.into_inner()).unwrap(),
+                   "{\"sym\":\"a\",\"time\":\"2024-01-31T00:00:00.000000001\",\"px\":1.5,\"note\":\"x,\\\"y\\\"\"}\n{\"sym\":null,\"time\":null,\"px\":null,\"note\":\"\"}\n");
+    }
+
+    #[test]
+    pub fn test_query_pages() {
+        let page = |x: Vec<u64>| table(&["a"], vec![Payload::LongVector(VectorAttribute::NoAttribute, x)]);
+        let mock = MockServer::start().unwrap();
+        mock.on_query(".z.w", Payload::Int(7));
+        mock.on_query(".iron.export7:0!value\"select from t where s=\\\"x\\\"\";count .iron.export7", Payload::Long(3));
+        mock.on_query(".iron.export7 0+til 2", page(vec![0, 1]));
+        mock.on_query(".iron.export7 2+til 1", page(vec![2]));
+        mock.on_query("delete export7 from `.iron", Payload::Nil);
+
+        let mut connection = KdbConnection::new(mock.address()).unwrap();
+        connection.connect("user", "pass").unwrap();
+        let mut pages = Vec::new();
+        query_pages(&mut connection, "select from t where s=\"x\"", 2, |x| {
+            pages.push(x);
+            Ok(())
+        }).unwrap();
+        assert_eq!(pages, vec![page(vec![0, 1]), page(vec![2])]);
+        assert_eq!(mock.requests().len(), 5);
+    }
+
+    #[test]
+    pub fn test_export_empty_result() {
+        let mock = MockServer::start().unwrap();
+        mock.on_query(".z.w", Payload::Int(7));
+        mock.on_query(".iron.export7:0!value\"select from t\";count .iron.export7", Payload::Long(0));
+        mock.on_query(".iron.export7 0+til 0", table(&["a", "b"], vec![
+            Payload::LongVector(VectorAttribute::NoAttribute, vec![]), Payload::List(VectorAttribute::NoAttribute, vec![])]));
+        mock.on_query("delete export7 from `.iron", Payload::Nil);
+
+        let mut connection = KdbConnection::new(mock.address()).unwrap();
+        connection.connect("user", "pass").unwrap();
+        let mut csv = CsvWriter::new(Vec::new());
+        query_pages(&mut connection, "select from t", 2, |x| csv.write_table(&x)).unwrap();
+        csv.finish().unwrap();
+        assert_eq!(String::from_utf8(csv.into_inner()).unwrap(), "a,b\n");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    pub fn test_parquet() {
+        use arrow_schema::{DataType, TimeUnit};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use crate::export::ParquetWriter;
+
+        let path = std::env::temp_dir().join(format!("iron_kdb_export_{}.parquet", std::process::id()));
+        let mut parquet = ParquetWriter::new(std::fs::File::create(&path).unwrap());
+        parquet.write_table(&trades()).unwrap();
+        parquet.write_table(&trades()).unwrap();
+        parquet.finish().unwrap();
+        assert!(parquet.into_inner().is_some());
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        let schema = reader.schema().clone();
+        assert_eq!(schema.field(1).data_type(), &DataType::Timestamp(TimeUnit::Nanosecond, None));
+        let batches = reader.build().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.iter().map(|x| x.num_rows()).sum::<usize>(), 4);
+        assert_eq!(batches.iter().map(|x| x.column(2).null_count()).sum::<usize>(), 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut empty = ParquetWriter::new(Vec::new());
+        empty.write_table(&table(&["a"], vec![Payload::LongVector(VectorAttribute::NoAttribute, vec![])])).unwrap();
+        empty.finish().unwrap();
+        assert!(!empty.into_inner().unwrap().is_empty());
+    }
+}
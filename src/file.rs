@@ -0,0 +1,115 @@
+use std::convert::TryFrom;
+use std::path::Path;
+use crate::codec::{Payload, VectorAttribute};
+use crate::compressed::{decompress, is_compressed};
+
+/// Header of objects q writes in the IPC serialisation, general lists, dictionaries, atoms etc.
+pub(crate) const OBJECT_HEADER: [u8; 2] = [0xff, 0x01];
+/// Header of simple vectors since kdb+ 3.0, padded to 16 bytes so the data can be mapped
+pub(crate) const VECTOR_HEADER: [u8; 2] = [0xfe, 0x20];
+pub(crate) const VECTOR_HEADER_LEN: usize = 16;
+
+impl Payload {
+    /// Reads a file written by q's `` `:path set x ``
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Payload, String> {
+        let bytes = std::fs::read(path.as_ref()).map_err(|x| format!("{}: {}", path.as_ref().display(), x))?;
+        Payload::from_file_bytes(&bytes)
+    }
+
+    /// Writes the payload the way `` `:path set x `` would, so q can `get` it
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        self.check()?;
+        std::fs::write(path.as_ref(), self.to_file_bytes()).map_err(|x| format!("{}: {}", path.as_ref().display(), x))
+    }
+
+    /// Decodes the contents of a q data file, compressed or not
+    pub fn from_file_bytes(bytes: &[u8]) -> Result<Payload, String> {
+        if is_compressed(bytes) {
+            return Payload::from_file_bytes(&decompress(bytes)?);
+        }
+        match bytes.get(0..2) {
+            Some(header) if header == OBJECT_HEADER => match Payload::serialized_len(&bytes[2..])? {
+                Some(len) if len == bytes.len() - 2 => Payload::from_bytes(&bytes[2..]),
+                _ => Err(String::from("Incomplete q data file")),
+            },
+            Some(header) if header == VECTOR_HEADER => {
+                let (type_byte, attribute, count, data) = vector_file_parts(bytes)?;
+                vector_from_parts(type_byte, attribute, count, data)
+            }
+            _ => Err(String::from("Not a q data file")),
+        }
+    }
+
+    /// Encodes the payload as q would write it to a file
+    pub fn to_file_bytes(&self) -> Vec<u8> {
+        let body = self.to_bytes();
+        match Payload::vector_width(self.type_byte()) {
+            Some(_) => {
+                let mut ret_val = Vec::with_capacity(VECTOR_HEADER_LEN + body.len() - 6);
+                ret_val.extend_from_slice(&VECTOR_HEADER);
+                ret_val.extend_from_slice(&body[0..2]);
+                ret_val.extend_from_slice(&[0; 4]);
+                ret_val.extend_from_slice(&(u32::from_le_bytes([body[2], body[3], body[4], body[5]]) as u64).to_le_bytes());
+                ret_val.extend_from_slice(&body[6..]);
+                ret_val
+            }
+            None => {
+                let mut ret_val = Vec::with_capacity(2 + body.len());
+                ret_val.extend_from_slice(&OBJECT_HEADER);
+                ret_val.extend_from_slice(&body);
+                ret_val
+            }
+        }
+    }
+}
+
+/// Type, attribute, element count and data of a file with the 16 byte vector header
+pub(crate) fn vector_file_parts(bytes: &[u8]) -> Result<(i8, u8, usize, &[u8]), String> {
+    if bytes.len() < VECTOR_HEADER_LEN {
+        return Err(String::from("Incomplete q data file"));
+    }
+    let mut count = [0u8; 8];
+    count.copy_from_slice(&bytes[8..16]);
+    let count = u64::from_le_bytes(count);
+    if count > u32::MAX as u64 {
+        return Err(format!("Vector of {} elements is too long", count));
+    }
+    Ok((bytes[2] as i8, bytes[3], count as usize, &bytes[VECTOR_HEADER_LEN..]))
+}
+
+/// Decodes `count` elements of a fixed width type laid out as in a vector file
+pub(crate) fn vector_from_parts(type_byte: i8, attribute: u8, count: usize, data: &[u8]) -> Result<Payload, String> {
+    let width = Payload::vector_width(type_byte).ok_or_else(|| format!("Can't read vectors of type {} without their enumeration", type_byte))?;
+    let data = data.get(..width * count).ok_or_else(|| String::from("Incomplete q data file"))?;
+    Payload::from_vector_data(type_byte, VectorAttribute::try_from(attribute)?, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+
+    #[test]
+    pub fn test_read_vector_file() {
+        // `:x set `s#til 3
+        let bytes = hex::decode("fe200701000000000300000000000000000000000000000001000000000000000200000000000000").unwrap();
+        let expected = Payload::LongVector(VectorAttribute::Sorted, vec![0, 1, 2]);
+        assert_eq!(Payload::from_file_bytes(&bytes).unwrap(), expected);
+        assert_eq!(expected.to_file_bytes(), bytes);
+        assert!(Payload::from_file_bytes(&bytes[..30]).is_err());
+    }
+
+    #[test]
+    pub fn test_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("iron_kdb_file_{}", std::process::id()));
+        let table = Payload::Table(VectorAttribute::NoAttribute, Box::new(Payload::Dictionary(
+            Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("sym"), KdbString::from("px")])),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![
+                Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("a")]),
+                Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5])])))));
+        table.write_file(&path).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes[0..4], [0xff, 0x01, 98, 0]);
+        assert_eq!(Payload::read_file(&path).unwrap(), table);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
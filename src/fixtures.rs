@@ -0,0 +1,46 @@
+use crate::codec::{KdbString, Payload, VectorAttribute};
+use crate::compressed::Algorithm;
+
+pub(crate) fn symbols(names: &[&str]) -> Vec<KdbString> {
+    names.iter().map(|x| KdbString::from(*x)).collect()
+}
+
+pub(crate) fn symbol_vector(names: &[&str]) -> Payload {
+    Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(names))
+}
+
+pub(crate) fn sym(name: &str) -> Payload {
+    Payload::Symbol(KdbString::from(name))
+}
+
+/// `(function; `table; columns)`, as a tickerplant publishes or is sent updates
+pub(crate) fn update(function: &str, table: &str, columns: Vec<Payload>) -> Payload {
+    Payload::List(VectorAttribute::NoAttribute, vec![sym(function), sym(table), Payload::List(VectorAttribute::NoAttribute, columns)])
+}
+
+/// A one row `upd` of a float column
+pub(crate) fn upd(table: &str, px: f64) -> Payload {
+    update("upd", table, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![px])])
+}
+
+/// Compresses `data` the way q lays out a compressed file, with `compress` for each block
+pub(crate) fn compressed_file<F: Fn(&[u8]) -> Vec<u8>>(algorithm: Algorithm, log2_block_size: u8, data: &[u8], compress: F) -> Vec<u8> {
+    let mut ret_val = b"kxzipped".to_vec();
+    let mut lens = Vec::new();
+    for block in data.chunks(1 << log2_block_size) {
+        let compressed = compress(block);
+        lens.push(compressed.len() as u32);
+        ret_val.extend_from_slice(&compressed);
+    }
+    lens.iter().for_each(|x| ret_val.extend_from_slice(&x.to_le_bytes()));
+    ret_val.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    ret_val.extend_from_slice(&[algorithm as u8, 6, log2_block_size, 0, 0, 0, 0, 0]);
+    ret_val
+}
+
+/// A block compressed with kx's IPC algorithm, without the message header
+pub(crate) fn kx(block: &[u8]) -> Vec<u8> {
+    let mut message = vec![0; 8];
+    message.extend_from_slice(block);
+    crate::compress(&message).unwrap().split_off(8)
+}
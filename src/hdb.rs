@@ -0,0 +1,510 @@
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use crate::codec::{KdbString, Payload, VectorAttribute};
+use crate::file::VECTOR_HEADER_LEN;
+use crate::builder::{table, table_parts};
+use crate::splayed::{read_column_file, read_sym, write_splayed, ColumnFile, SplayedTable, ENUM_TYPE};
+use crate::temporal::{format_date, format_month, parse_date, parse_month};
+
+/// Value of a partition directory, also the value of the virtual column q adds for it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Partition {
+    /// Days since 2000.01.01, e.g. `2024.01.31`
+    Date(i32),
+    /// Months since 2000.01, e.g. `2024.01`
+    Month(i32),
+    /// e.g. `2024` for yearly partitions
+    Int(i32),
+}
+
+impl Partition {
+    /// Partition of a directory name, `None` if it isn't one
+    pub fn parse(name: &str) -> Option<Partition> {
+        if let Some(date) = parse_date(name) {
+            return Some(Partition::Date(date));
+        }
+        if let Some(month) = parse_month(name) {
+            return Some(Partition::Month(month));
+        }
+        match name.bytes().all(|x| x.is_ascii_digit()) {
+            true => name.parse().ok().map(Partition::Int),
+            false => None,
+        }
+    }
+
+    /// Name of the virtual column, `date`, `month` or `int`
+    pub fn column(&self) -> &'static str {
+        match self {
+            Partition::Date(_) => "date",
+            Partition::Month(_) => "month",
+            Partition::Int(_) => "int",
+        }
+    }
+
+    fn vector(&self, rows: usize) -> Payload {
+        match *self {
+            Partition::Date(x) => Payload::DateVector(VectorAttribute::NoAttribute, vec![x as u32; rows]),
+            Partition::Month(x) => Payload::MonthVector(VectorAttribute::NoAttribute, vec![x as u32; rows]),
+            Partition::Int(x) => Payload::IntVector(VectorAttribute::NoAttribute, vec![x as u32; rows]),
+        }
+    }
+}
+
+impl Display for Partition {
+    /// The directory name, e.g. `2024.01.31`
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Partition::Date(x) => write!(f, "{}", format_date(x)),
+            Partition::Month(x) => write!(f, "{}", format_month(x)),
+            Partition::Int(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// A row condition evaluated against the mapped column files. Conditions on `s#` columns narrow the
+/// rows by binary search, equality on `p#` columns by binary searching the end of each run of equal
+/// items, so the rows read grow with the number of distinct values rather than the row count.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `column = value`, e.g. a symbol against an enumerated `sym` column
+    Equal(String, Payload),
+    /// `column within (low; high)`, both ends included
+    Within(String, Payload, Payload),
+}
+
+impl Filter {
+    fn column(&self) -> &str {
+        match self {
+            Filter::Equal(column, _) | Filter::Within(column, _, _) => column,
+        }
+    }
+
+    fn bounds(&self) -> (&Payload, &Payload) {
+        match self {
+            Filter::Equal(_, value) => (value, value),
+            Filter::Within(_, low, high) => (low, high),
+        }
+    }
+}
+
+/// A partitioned database, e.g. `db/2024.01.31/trade/`, or partitions spread over the
+/// segments listed in `db/par.txt`. Loading it is the equivalent of `\l db`.
+pub struct Hdb {
+    sym: Vec<KdbString>,
+    partitions: Vec<(Partition, PathBuf)>,
+}
+
+impl Hdb {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Hdb, String> {
+        let root = root.as_ref();
+        let par = root.join("par.txt");
+        let segments = match par.exists() {
+            true => std::fs::read_to_string(&par).map_err(|x| format!("{}: {}", par.display(), x))?
+                .lines().map(str::trim).filter(|x| !x.is_empty()).map(|x| root.join(x)).collect(),
+            false => vec![root.to_path_buf()],
+        };
+        let mut partitions = Vec::new();
+        for segment in segments {
+            for entry in std::fs::read_dir(&segment).map_err(|x| format!("{}: {}", segment.display(), x))? {
+                let entry = entry.map_err(|x| x.to_string())?;
+                let partition = entry.file_name().to_str().and_then(Partition::parse);
+                if let (Some(partition), true) = (partition, entry.path().is_dir()) {
+                    partitions.push((partition, entry.path()));
+                }
+            }
+        }
+        partitions.sort();
+        if partitions.windows(2).any(|x| x[0].0.column() != x[1].0.column()) {
+            return Err(format!("{} mixes partition types", root.display()));
+        }
+        let sym = match root.join("sym").exists() {
+            true => read_sym(root)?,
+            false => Vec::new(),
+        };
+        Ok(Hdb { sym, partitions })
+    }
+
+    /// Partitions in order, a partition in several segments is listed once
+    pub fn partitions(&self) -> Vec<Partition> {
+        let mut partitions: Vec<Partition> = self.partitions.iter().map(|x| x.0).collect();
+        partitions.dedup();
+        partitions
+    }
+
+    /// Tables in the last partition, which is how q decides what tables a database has
+    pub fn tables(&self) -> Result<Vec<String>, String> {
+        let last = match self.partitions.last() {
+            Some((_, directory)) => directory,
+            None => return Ok(Vec::new()),
+        };
+        let mut tables = Vec::new();
+        for entry in std::fs::read_dir(last).map_err(|x| format!("{}: {}", last.display(), x))? {
+            let entry = entry.map_err(|x| x.to_string())?;
+            if entry.path().join(".d").exists() {
+                tables.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        tables.sort();
+        Ok(tables)
+    }
+
+    pub fn sym(&self) -> &[KdbString] {
+        &self.sym
+    }
+
+    pub fn query(&self, table: &str) -> Query<'_> {
+        Query { hdb: self, table: table.to_string(), columns: None, range: None, filters: Vec::new() }
+    }
+}
+
+/// A `select columns from table where partition within range, filters` against an `Hdb`.
+pub struct Query<'a> {
+    hdb: &'a Hdb,
+    table: String,
+    columns: Option<Vec<String>>,
+    range: Option<(Partition, Partition)>,
+    filters: Vec<Filter>,
+}
+
+impl<'a> Query<'a> {
+    /// Reads only these columns, all of them by default
+    pub fn columns(mut self, columns: &[&str]) -> Self {
+        self.columns = Some(columns.iter().map(|x| x.to_string()).collect());
+        self
+    }
+
+    /// Only partitions from `from` to `to`, both included
+    pub fn partitions(mut self, from: Partition, to: Partition) -> Self {
+        self.range = Some((from, to));
+        self
+    }
+
+    /// Adds a condition, all conditions must hold
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Calls `f` with each partition's matching rows in partition order, without the partition
+    /// column, so long ranges can be scanned without holding them in memory
+    pub fn scan<F: FnMut(Partition, Payload) -> Result<(), String>>(&self, mut f: F) -> Result<(), String> {
+        for (partition, directory) in self.selected_partitions() {
+            let (_, columns, values) = self.select(directory)?;
+            f(*partition, table(&columns, values))?;
+        }
+        Ok(())
+    }
+
+    /// All matching rows as one table with the partition column first
+    pub fn run(&self) -> Result<Payload, String> {
+        let mut result: Option<(Vec<String>, Vec<Payload>)> = None;
+        for (partition, directory) in self.selected_partitions() {
+            let (rows, mut columns, mut values) = self.select(directory)?;
+            columns.insert(0, partition.column().to_string());
+            values.insert(0, partition.vector(rows));
+            match result.as_mut() {
+                Some((_, all)) => all.iter_mut().zip(values).try_for_each(|(all, x)| all.append(x))?,
+                None => result = Some((columns, values)),
+            }
+        }
+        match result {
+            Some((columns, values)) => Ok(table(&columns, values)),
+            None => Err(format!("No partitions to read {} from", self.table)),
+        }
+    }
+
+    fn selected_partitions(&self) -> impl Iterator<Item = &(Partition, PathBuf)> {
+        let range = self.range;
+        self.hdb.partitions.iter().filter(move |(x, _)| range.is_none_or(|(from, to)| from <= *x && *x <= to))
+    }
+
+    /// Row count, column names and values of the matching rows of one partition
+    fn select(&self, directory: &Path) -> Result<(usize, Vec<String>, Vec<Payload>), String> {
+        let table = SplayedTable::open(directory.join(&self.table))?;
+        let sym = self.hdb.sym.as_slice();
+        let mut rows = 0..table.row_count()?;
+        let mut scans = Vec::new();
+        for filter in &self.filters {
+            if !table.columns().iter().any(|x| x == filter.column()) {
+                return Err(format!("{} has no column {}", table.directory().display(), filter.column()));
+            }
+            let column = MappedColumn::open(&table.directory().join(filter.column()))?;
+            let (low, high) = filter.bounds();
+            let (low, high) = (column.atom_key(low)?, column.atom_key(high)?);
+            match (column.attribute, filter) {
+                (VectorAttribute::Sorted, _) => {
+                    let start = partition_point(rows.clone(), |row| Ok(column.key(row, sym)? < low))?;
+                    let end = partition_point(start..rows.end, |row| Ok(column.key(row, sym)? <= high))?;
+                    rows = start..end;
+                }
+                (VectorAttribute::Parted, Filter::Equal(_, _)) => {
+                    let mut start = rows.start;
+                    rows = loop {
+                        if start >= rows.end {
+                            break rows.end..rows.end;
+                        }
+                        let key = column.key(start, sym)?;
+                        // Equal items are contiguous, a null float never equals itself so its run is one row
+                        let end = partition_point(start..rows.end, |row| Ok(column.key(row, sym)? == key))?.max(start + 1);
+                        if key == low {
+                            break start..end;
+                        }
+                        start = end;
+                    };
+                }
+                _ => scans.push((column, low, high)),
+            }
+        }
+        let mut keep = vec![true; rows.len()];
+        for (column, low, high) in &scans {
+            for (row, keep) in rows.clone().zip(keep.iter_mut()) {
+                if *keep {
+                    let key = column.key(row, sym)?;
+                    *keep = *low <= key && key <= *high;
+                }
+            }
+        }
+
+        let columns = match &self.columns {
+            Some(columns) => columns.clone(),
+            None => table.columns().to_vec(),
+        };
+        let filtered = keep.iter().any(|x| !x);
+        let mut values = Vec::with_capacity(columns.len());
+        for column in &columns {
+            let value = table.read_column_rows(column, sym, rows.clone())?;
+            values.push(if filtered { value.filter_rows(&keep)? } else { value });
+        }
+        Ok((keep.iter().filter(|x| **x).count(), columns, values))
+    }
+}
+
+/// Writes the table `data` as `name` in `partition` of the database at `root`, the equivalent of
+/// `.Q.dpft[`:root;partition;parted;name]`: rows are grouped by the `parted` column, which is
+/// written first with `p#`, and symbol columns are enumerated against `root/sym`.
+pub fn write_partition<P: AsRef<Path>>(root: P, partition: Partition, name: &str, data: &Payload, parted: &str) -> Result<(), String> {
+    let root = root.as_ref();
+    let data = data.plain_symbols();
+    let (names, values) = table_parts(&data)?;
+    let position = names.iter().position(|x| x == parted).ok_or_else(|| format!("Table has no column {}", parted))?;
+    let bytes = values[position].to_file_bytes();
+    // Group by the stored value, sorting symbols by name like `xasc`
+    let keys: Vec<&[u8]> = match &values[position] {
+        Payload::SymbolVector(_, x) => x.iter().map(|x| x.as_bytes()).collect(),
+        x => match Payload::vector_width(x.type_byte()) {
+            Some(width) if x.type_byte() > 0 => bytes[VECTOR_HEADER_LEN..].chunks_exact(width).collect(),
+            _ => return Err(format!("Can't part on column {} of type {}", parted, x.type_byte())),
+        },
+    };
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by_key(|x| keys[*x]);
+
+    let mut columns = vec![names[position].clone()];
+    let mut ordered = vec![values[position].clone().take_rows(&order, VectorAttribute::Parted)?];
+    for (column, value) in names.iter().zip(values).filter(|(x, _)| *x != parted) {
+        columns.push(column.clone());
+        ordered.push(value.clone().take_rows(&order, VectorAttribute::NoAttribute)?);
+    }
+    let directory = root.join(partition.to_string()).join(name);
+    write_splayed(directory, root, &table(&columns, ordered))
+}
+
+/// First row of `rows` for which `predicate` is false, `predicate` must be true then false
+fn partition_point<F: Fn(usize) -> Result<bool, String>>(rows: Range<usize>, predicate: F) -> Result<usize, String> {
+    let (mut low, mut high) = (rows.start, rows.end);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if predicate(middle)? {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(low)
+}
+
+/// Comparable value of one item, numbers compare as numbers and symbols as text
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+enum Key<'a> {
+    Int(i64),
+    Float(f64),
+    Symbol(&'a [u8]),
+}
+
+/// A column file mapped for looking up single rows, compressed ones decompress only the blocks
+/// holding the rows looked up
+struct MappedColumn {
+    map: ColumnFile,
+    type_byte: i8,
+    attribute: VectorAttribute,
+    count: usize,
+}
+
+impl MappedColumn {
+    fn open(path: &Path) -> Result<MappedColumn, String> {
+        let map = read_column_file(path)?;
+        let (type_byte, attribute, count) = map.vector_header().map_err(|x| format!("{}: {}", path.display(), x))?
+            .ok_or_else(|| format!("{}: Can only filter on simple or enumerated columns", path.display()))?;
+        let width = match type_byte {
+            ENUM_TYPE => Some(8),
+            x => Payload::vector_width(x).filter(|_| x != 2),
+        };
+        match width {
+            Some(width) if map.len() >= VECTOR_HEADER_LEN + width * count => {}
+            Some(_) => return Err(format!("{}: Incomplete q data file", path.display())),
+            None => return Err(format!("{}: Can't filter on type {}", path.display(), type_byte)),
+        }
+        Ok(MappedColumn { type_byte, attribute: std::convert::TryFrom::try_from(attribute)?, count, map })
+    }
+
+    fn key<'s>(&self, row: usize, sym: &'s [KdbString]) -> Result<Key<'s>, String> {
+        if row >= self.count {
+            return Err(format!("Row {} out of range of {}", row, self.count));
+        }
+        let int = |width: usize| -> Result<i64, String> {
+            let mut bytes = [0u8; 8];
+            bytes[..width].copy_from_slice(&self.map.items(width, row..row + 1)?);
+            // Sign extend so nulls (the minimum) and negative temporals sort first, as in q
+            let shift = 64 - 8 * width as u32;
+            Ok(((i64::from_le_bytes(bytes)) << shift) >> shift)
+        };
+        Ok(match self.type_byte {
+            1 | 4 => Key::Int(int(1)? & 0xff),
+            10 => Key::Int(int(1)? & 0xff),
+            5 => Key::Int(int(2)?),
+            6 | 13 | 14 | 17 | 18 | 19 => Key::Int(int(4)?),
+            7 | 12 | 16 => Key::Int(int(8)?),
+            8 => Key::Float(f32::from_bits(int(4)? as u32) as f64),
+            9 | 15 => Key::Float(f64::from_bits(int(8)? as u64)),
+            ENUM_TYPE => {
+                let index = int(8)? as u64;
+                Key::Symbol(sym.get(index as usize).map(|x| x.as_bytes()).ok_or_else(|| format!("Enumeration index {} out of range of sym", index))?)
+            }
+            x => return Err(format!("Can't filter on type {}", x)),
+        })
+    }
+
+    /// Key of a filter value, which must be an atom of the column's type
+    fn atom_key<'p>(&self, atom: &'p Payload) -> Result<Key<'p>, String> {
+        let expected = if self.type_byte == ENUM_TYPE { -11 } else { -self.type_byte };
+        if atom.type_byte() != expected {
+            return Err(format!("Can't compare type {} to a column of type {}", atom.type_byte(), self.type_byte));
+        }
+        Ok(match atom {
+            Payload::Bool(x) => Key::Int(*x as i64),
+            Payload::Byte(x) => Key::Int(*x as i64),
+            Payload::Char(x) => Key::Int(*x as i64),
+            Payload::Short(x) => Key::Int(*x as i16 as i64),
+            Payload::Int(x) | Payload::Month(x) | Payload::Date(x) | Payload::Minute(x) | Payload::Second(x) | Payload::Time(x) => Key::Int(*x as i32 as i64),
+            Payload::Long(x) | Payload::Timestamp(x) | Payload::TimeSpan(x) => Key::Int(*x as i64),
+            Payload::Real(x) => Key::Float(*x as f64),
+            Payload::Float(x) => Key::Float(*x),
+            Payload::DateTime(x) => Key::Float(f64::from_bits(*x)),
+            Payload::Symbol(x) => Key::Symbol(x.as_bytes()),
+            x => return Err(format!("Can't filter on type {}", x.type_byte())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::codec::{Payload, VectorAttribute};
+    use crate::hdb::{write_partition, Filter, Hdb, Partition};
+    use crate::builder::table;
+    use crate::fixtures::{sym, symbols};
+
+    /// `trade` with a `p#` enumerated sym column and `s#` times per sym
+    fn write_trades(directory: &Path, syms: &[u64], times: &[u32], px: &[f64]) {
+        let directory = directory.join("trade");
+        std::fs::create_dir_all(&directory).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["sym", "time", "px"])).write_file(directory.join(".d")).unwrap();
+        let mut sym_file = vec![0xfe, 0x20, 20, 3, 0, 0, 0, 0];
+        sym_file.extend_from_slice(&(syms.len() as u64).to_le_bytes());
+        syms.iter().for_each(|x| sym_file.extend_from_slice(&x.to_le_bytes()));
+        std::fs::write(directory.join("sym"), sym_file).unwrap();
+        Payload::TimeVector(VectorAttribute::NoAttribute, times.to_vec()).write_file(directory.join("time")).unwrap();
+        Payload::FloatVector(VectorAttribute::Sorted, px.to_vec()).write_file(directory.join("px")).unwrap();
+    }
+
+    #[test]
+    pub fn test_query_partitions() {
+        let root = std::env::temp_dir().join(format!("iron_kdb_hdb_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["AAPL", "IBM"])).write_file(root.join("sym")).unwrap();
+        write_trades(&root.join("2024.01.30"), &[0, 0, 1], &[1000, 2000, 1500], &[1.0, 2.0, 3.0]);
+        write_trades(&root.join("2024.01.31"), &[1, 0, 0, 0], &[500, 1000, 2000, 3000], &[1.0, 2.0, 3.0, 4.0]);
+        std::fs::create_dir_all(root.join("2024.02.01")).unwrap();
+
+        let hdb = Hdb::open(&root).unwrap();
+        assert_eq!(hdb.partitions(), vec![Partition::Date(8795), Partition::Date(8796), Partition::Date(8797)]);
+        let query = hdb.query("trade")
+            .partitions(Partition::Date(8795), Partition::Date(8796))
+            .columns(&["time", "px"])
+            .filter(Filter::Equal(String::from("sym"), sym("AAPL")))
+            .filter(Filter::Within(String::from("time"), Payload::Time(1000), Payload::Time(2500)))
+            .filter(Filter::Within(String::from("px"), Payload::Float(1.5), Payload::Float(10.0)));
+        assert_eq!(query.run().unwrap(), table(&["date", "time", "px"], vec![
+            Payload::DateVector(VectorAttribute::NoAttribute, vec![8795, 8796, 8796]),
+            Payload::TimeVector(VectorAttribute::NoAttribute, vec![2000, 1000, 2000]),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![2.0, 2.0, 3.0])]));
+
+        let mut counts = Vec::new();
+        hdb.query("trade").partitions(Partition::Date(8796), Partition::Date(8796)).filter(Filter::Equal(String::from("sym"), sym("IBM")))
+            .scan(|partition, table| {
+                counts.push((partition, table));
+                Ok(())
+            }).unwrap();
+        assert_eq!(counts, vec![(Partition::Date(8796), table(&["sym", "time", "px"], vec![
+            Payload::SymbolVector(VectorAttribute::Parted, symbols(&["IBM"])),
+            Payload::TimeVector(VectorAttribute::NoAttribute, vec![500]),
+            Payload::FloatVector(VectorAttribute::Sorted, vec![1.0])]))]);
+        let missing = hdb.query("trade").partitions(Partition::Date(8795), Partition::Date(8796)).filter(Filter::Equal(String::from("sym"), sym("MSFT")));
+        assert_eq!(missing.run().unwrap().count(), 0);
+        assert!(hdb.query("trade").partitions(Partition::Date(8795), Partition::Date(8796)).filter(Filter::Equal(String::from("px"), Payload::Long(1))).run().is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_segments() {
+        let root = std::env::temp_dir().join(format!("iron_kdb_segments_{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("par.txt"), "a\nb\n").unwrap();
+        write_trades(&root.join("a").join("2024.01"), &[], &[], &[]);
+        write_trades(&root.join("b").join("2024.02"), &[], &[], &[]);
+        write_trades(&root.join("2024.03"), &[], &[], &[]);
+
+        let hdb = Hdb::open(&root).unwrap();
+        assert_eq!(hdb.partitions(), vec![Partition::Month(288), Partition::Month(289)]);
+        assert_eq!(hdb.tables().unwrap(), vec![String::from("trade")]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_write_partition() {
+        let root = std::env::temp_dir().join(format!("iron_kdb_write_partition_{}", std::process::id()));
+        let trade = table(&["time", "sym", "px"], vec![
+            Payload::TimeVector(VectorAttribute::Sorted, vec![1, 2, 3, 4]),
+            Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["IBM", "AAPL", "IBM", "AAPL"])),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0, 2.0, 3.0, 4.0])]);
+        write_partition(&root, Partition::Date(8796), "trade", &trade, "sym").unwrap();
+        assert_eq!(Partition::Date(8796).to_string(), "2024.01.31");
+        assert!(root.join("2024.01.31").join("trade").join(".d").exists());
+
+        let hdb = Hdb::open(&root).unwrap();
+        assert_eq!(hdb.tables().unwrap(), vec![String::from("trade")]);
+        let query = hdb.query("trade").filter(Filter::Equal(String::from("sym"), sym("IBM")));
+        let mut tables = Vec::new();
+        query.scan(|_, table| {
+            tables.push(table);
+            Ok(())
+        }).unwrap();
+        assert_eq!(tables, vec![table(&["sym", "time", "px"], vec![
+            Payload::SymbolVector(VectorAttribute::Parted, symbols(&["IBM", "IBM"])),
+            Payload::TimeVector(VectorAttribute::NoAttribute, vec![1, 3]),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0, 3.0])])]);
+        assert!(write_partition(&root, Partition::Date(8797), "trade", &trade, "size").is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,216 @@
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock, RwLockWriteGuard};
+use crate::codec::{KdbString, Payload};
+
+/// A symbol's index in the `SymbolTable` it came from. Ids from one table are equal exactly when
+/// their symbols are.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct SymbolId(u32);
+
+impl SymbolId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Distinct symbols, each stored once. Decoding against a table turns symbol vectors into
+/// `Payload::InternedSymbolVector`s of ids, so millions of rows drawn from a few thousand symbols
+/// allocate a few thousand strings. Clones share the table, which only grows, so one can serve a
+/// connection for its life. Tables are equal when they're the same table.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable(Arc<RwLock<Symbols>>);
+
+#[derive(Debug, Default)]
+pub(crate) struct Symbols {
+    names: Vec<Arc<KdbString>>,
+    ids: HashMap<Name, SymbolId>,
+}
+
+/// A symbol of `names` shared as a key of `ids`, looked up by its bytes
+#[derive(Debug, Eq, PartialEq, Hash)]
+struct Name(Arc<KdbString>);
+
+impl Borrow<[u8]> for Name {
+    fn borrow(&self) -> &[u8] {
+        self.0.as_bytes()
+    }
+}
+
+impl Symbols {
+    fn intern(&mut self, symbol: &[u8]) -> SymbolId {
+        if let Some(id) = self.ids.get(symbol) {
+            return *id;
+        }
+        let id = SymbolId(self.names.len() as u32);
+        let name = Arc::new(KdbString::from(symbol));
+        self.names.push(name.clone());
+        self.ids.insert(Name(name), id);
+        id
+    }
+
+    pub(crate) fn name(&self, id: SymbolId) -> &[u8] {
+        self.names.get(id.index()).map(|x| x.as_bytes()).unwrap_or_default()
+    }
+}
+
+/// A table write locked for a whole decode
+pub(crate) struct Interner<'a> {
+    table: &'a SymbolTable,
+    symbols: RwLockWriteGuard<'a, Symbols>,
+}
+
+impl Interner<'_> {
+    pub(crate) fn intern(&mut self, symbol: &[u8]) -> SymbolId {
+        self.symbols.intern(symbol)
+    }
+
+    pub(crate) fn table(&self) -> SymbolTable {
+        self.table.clone()
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// The id of `symbol`, adding it if it's new
+    pub fn intern(&self, symbol: &[u8]) -> SymbolId {
+        self.write().intern(symbol)
+    }
+
+    /// The id of `symbol` if it's in the table, for comparing against interned vectors
+    pub fn id(&self, symbol: &[u8]) -> Option<SymbolId> {
+        self.read(|x| x.ids.get(symbol).copied())
+    }
+
+    /// The symbol of an id from this table, shared rather than copied
+    pub fn resolve(&self, id: SymbolId) -> Option<Arc<KdbString>> {
+        self.read(|x| x.names.get(id.index()).cloned())
+    }
+
+    /// Copies of the symbols of `ids`, ids from another table are empty symbols
+    pub fn symbols(&self, ids: &[SymbolId]) -> Vec<KdbString> {
+        self.read(|x| ids.iter().map(|id| KdbString::from(x.name(*id))).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.read(|x| x.names.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `Payload::decode` with symbol vectors interned. Table column names and symbol atoms stay
+    /// plain.
+    pub fn decode(&self, bytes: &[u8]) -> Result<(Payload, usize), String> {
+        Payload::decode_with(bytes, Some(&mut self.interner()))
+    }
+
+    /// `Payload::from_bytes` with symbol vectors interned
+    pub fn decode_all(&self, bytes: &[u8]) -> Result<Payload, String> {
+        Payload::from_bytes_with(bytes, Some(&mut self.interner()))
+    }
+
+    /// Holding the table read locked
+    pub(crate) fn read<T, F: FnOnce(&Symbols) -> T>(&self, f: F) -> T {
+        f(&self.0.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Symbols> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn interner(&self) -> Interner<'_> {
+        Interner { table: self, symbols: self.write() }
+    }
+}
+
+impl PartialEq for SymbolTable {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Payload {
+    /// The payload with every interned symbol vector, however deep, turned back into a
+    /// `SymbolVector`
+    pub fn resolve_symbols(self) -> Payload {
+        match self {
+            Payload::InternedSymbolVector(attribute, table, ids) => Payload::SymbolVector(attribute, table.symbols(&ids)),
+            Payload::List(attribute, x) => Payload::List(attribute, x.into_iter().map(Payload::resolve_symbols).collect()),
+            Payload::Table(attribute, x) => Payload::Table(attribute, Box::new(x.resolve_symbols())),
+            Payload::Dictionary(keys, values) => Payload::Dictionary(Box::new(keys.resolve_symbols()), Box::new(values.resolve_symbols())),
+            x => x,
+        }
+    }
+
+    /// The payload itself, or a resolved copy if it holds interned symbols
+    pub(crate) fn plain_symbols(&self) -> Cow<'_, Payload> {
+        fn interned(payload: &Payload) -> bool {
+            match payload {
+                Payload::InternedSymbolVector(..) => true,
+                Payload::List(_, x) => x.iter().any(interned),
+                Payload::Table(_, x) => interned(x),
+                Payload::Dictionary(keys, values) => interned(keys) || interned(values),
+                _ => false,
+            }
+        }
+
+        match interned(self) {
+            true => Cow::Owned(self.clone().resolve_symbols()),
+            false => Cow::Borrowed(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::KdbConnection;
+    use crate::codec::{encode_message, KdbString, Payload, SynchronisationType, VectorAttribute};
+    use crate::intern::SymbolTable;
+    use crate::builder::table;
+    use crate::fixtures::symbol_vector;
+
+    #[test]
+    pub fn test_interned_decoding() {
+        let trades = table(&["sym", "side"], vec![symbol_vector(&["AAPL", "MSFT", "AAPL"]), symbol_vector(&["buy", "sell", "buy"])]);
+        let payload = Payload::List(VectorAttribute::NoAttribute, vec![Payload::Symbol(KdbString::from("upd")), trades]);
+        let bytes = payload.to_bytes();
+
+        let interned = SymbolTable::new();
+        let (decoded, len) = interned.decode(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(interned.len(), 4);
+        let (aapl, buy) = (interned.id(b"AAPL").unwrap(), interned.id(b"buy").unwrap());
+        assert_eq!(*interned.resolve(aapl).unwrap(), "AAPL");
+        // Held once by the table's list and index, and once here
+        assert_eq!(std::sync::Arc::strong_count(&interned.resolve(aapl).unwrap()), 3);
+        assert_eq!(interned.id(b"sym"), None);
+        if let Payload::List(_, x) = &decoded {
+            assert_eq!(x[0], Payload::Symbol(KdbString::from("upd")));
+            assert_eq!(x[1], table(&["sym", "side"], vec![
+                Payload::InternedSymbolVector(VectorAttribute::NoAttribute, interned.clone(), vec![aapl, interned.id(b"MSFT").unwrap(), aapl]),
+                Payload::InternedSymbolVector(VectorAttribute::NoAttribute, interned.clone(), vec![buy, interned.id(b"sell").unwrap(), buy])]));
+        } else {
+            panic!("Failed to decode the list");
+        }
+        assert_eq!(decoded.encoded_len(), bytes.len());
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.clone().resolve_symbols(), payload);
+
+        // Later messages reuse the ids
+        let (again, _) = interned.decode(&bytes).unwrap();
+        assert_eq!(again, decoded);
+        assert_eq!(interned.len(), 4);
+        assert_ne!(SymbolTable::new().decode(&bytes).unwrap().0, decoded);
+
+        let mut connection = KdbConnection::from_streams(Cursor::new(encode_message(SynchronisationType::Response, &payload)), Vec::new());
+        connection.intern_symbols(interned.clone());
+        assert_eq!(connection.receive_message().unwrap(), (SynchronisationType::Response, decoded));
+        assert_eq!(interned.len(), 4);
+    }
+}
@@ -0,0 +1,397 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::codec::{KdbString, Payload, VectorAttribute};
+use crate::temporal::{format_date, KDB_EPOCH_DAYS};
+
+/// Start of a log created with `.[`:log;();:;()]`, an empty general list. The entry count in
+/// bytes 4..8 is kept up to date by q on every append.
+pub(crate) const JOURNAL_HEADER: [u8; 4] = [0xff, 0x01, 0, 0];
+pub(crate) const JOURNAL_HEADER_LEN: u64 = 8;
+const READ_SIZE: usize = 64 * 1024;
+
+/// Iterates the entries of a tickerplant log, usually `(`upd; `table; data)` lists, the way
+/// `-11!` replays them. Entries are read in chunks so the file is never loaded whole.
+pub struct JournalReader<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    header_count: u32,
+    index: u64,
+    offset: u64,
+    failed: bool,
+    read_failed: bool,
+}
+
+impl JournalReader<File> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JournalReader<File>, String> {
+        JournalReader::new(File::open(path).map_err(|x| x.to_string())?)
+    }
+}
+
+impl<R: Read> JournalReader<R> {
+    pub fn new(mut reader: R) -> Result<JournalReader<R>, String> {
+        let mut header = [0u8; JOURNAL_HEADER_LEN as usize];
+        reader.read_exact(&mut header).map_err(|x| x.to_string())?;
+        if header[0..4] != JOURNAL_HEADER {
+            return Err(String::from("Not a tickerplant log"));
+        }
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&header[4..8]);
+        Ok(JournalReader { reader, buf: Vec::new(), pos: 0, header_count: u32::from_le_bytes(count), index: 0, offset: JOURNAL_HEADER_LEN, failed: false, read_failed: false })
+    }
+
+    /// Number of entries according to the header, the `-11!(-1;`:log)` equivalent
+    pub fn header_count(&self) -> u64 {
+        self.header_count as u64
+    }
+
+    /// Index of the next entry
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Byte offset of the next entry in the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Skips up to `n` entries without decoding them, returning how many were skipped
+    pub fn skip_entries(&mut self, n: u64) -> Result<u64, String> {
+        for skipped in 0..n {
+            if self.next_entry()?.is_none() {
+                return Ok(skipped);
+            }
+        }
+        Ok(n)
+    }
+
+    /// The raw bytes of the next entry, `None` at the end of the file
+    fn next_entry(&mut self) -> Result<Option<&[u8]>, String> {
+        loop {
+            let len = Payload::serialized_len(&self.buf[self.pos..])
+                .map_err(|x| format!("Corrupt entry {} at byte {}: {}", self.index, self.offset, x))?;
+            if let Some(len) = len {
+                let start = self.pos;
+                self.pos += len;
+                self.index += 1;
+                self.offset += len as u64;
+                return Ok(Some(&self.buf[start..start + len]));
+            }
+            if !self.fill()? {
+                return match self.pos == self.buf.len() {
+                    true => Ok(None),
+                    false => Err(format!("Incomplete entry {} at byte {}", self.index, self.offset)),
+                };
+            }
+        }
+    }
+
+    /// Reads more of the file behind the unconsumed bytes, false at the end of the file
+    fn fill(&mut self) -> Result<bool, String> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let start = self.buf.len();
+        // Grow geometrically so a large entry isn't measured over and over
+        self.buf.resize(start + start.max(READ_SIZE), 0);
+        let read = loop {
+            match self.reader.read(&mut self.buf[start..]) {
+                Ok(read) => break read,
+                Err(x) if x.kind() == ErrorKind::Interrupted => continue,
+                Err(x) => {
+                    self.buf.truncate(start);
+                    self.read_failed = true;
+                    return Err(x.to_string());
+                }
+            }
+        };
+        self.buf.truncate(start + read);
+        Ok(read > 0)
+    }
+}
+
+impl<R: Read> Iterator for JournalReader<R> {
+    type Item = Result<Payload, String>;
+
+    /// Stops after the first error, like `-11!` stopping at a corrupt entry
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_entry().and_then(|x| x.map(Payload::from_bytes).transpose());
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+/// Result of checking a log, see `validate`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Validation {
+    /// Complete entries before the end of the file or the first corrupt entry
+    pub entries: u64,
+    /// Bytes of the file holding the header and those entries
+    pub valid_len: u64,
+    pub file_len: u64,
+}
+
+impl Validation {
+    pub fn is_complete(&self) -> bool {
+        self.valid_len == self.file_len
+    }
+}
+
+/// Counts the well formed entries of a log, the equivalent of `-11!(-2;`:log)`
+pub fn validate<P: AsRef<Path>>(path: P) -> Result<Validation, String> {
+    let file = File::open(path).map_err(|x| x.to_string())?;
+    let file_len = file.metadata().map_err(|x| x.to_string())?.len();
+    let mut reader = JournalReader::new(file)?;
+    let error = loop {
+        match reader.next_entry() {
+            Ok(Some(_)) => continue,
+            Ok(None) => break None,
+            Err(x) => break Some(x),
+        }
+    };
+    // A bad entry ends the valid part of the log, failing to read it says nothing about the log
+    if let (Some(error), true) = (error, reader.read_failed) {
+        return Err(error);
+    }
+    Ok(Validation { entries: reader.index, valid_len: reader.offset, file_len })
+}
+
+/// Cuts a corrupt tail off a log and fixes the header count, so q can replay it again
+pub fn truncate_to_valid<P: AsRef<Path>>(path: P) -> Result<Validation, String> {
+    let validation = validate(path.as_ref())?;
+    let mut file = OpenOptions::new().write(true).open(path).map_err(|x| x.to_string())?;
+    file.set_len(validation.valid_len).map_err(|x| x.to_string())?;
+    file.seek(SeekFrom::Start(4)).map_err(|x| x.to_string())?;
+    file.write_all(&(validation.entries as u32).to_le_bytes()).map_err(|x| x.to_string())?;
+    file.sync_all().map_err(|x| x.to_string())?;
+    Ok(Validation { file_len: validation.valid_len, ..validation })
+}
+
+/// When a `JournalWriter` forces appended entries to disk with fsync.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SyncPolicy {
+    /// Leave it to the OS, as q does, a crash of the machine can lose recent entries
+    Never,
+    EveryEntry,
+    /// On the first append after this long since the last sync
+    Interval(Duration),
+}
+
+/// Appends entries to a tickerplant log in the format q writes with `h enlist x` on a handle
+/// to the log, so it can be replayed with `-11!`.
+pub struct JournalWriter {
+    file: File,
+    path: PathBuf,
+    count: u32,
+    policy: SyncPolicy,
+    last_sync: Instant,
+}
+
+impl JournalWriter {
+    /// Creates an empty log, replacing any existing file, like `.[`:log;();:;()]`
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<JournalWriter, String> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path.as_ref()).map_err(|x| x.to_string())?;
+        file.write_all(&JOURNAL_HEADER).map_err(|x| x.to_string())?;
+        file.write_all(&0u32.to_le_bytes()).map_err(|x| x.to_string())?;
+        Ok(JournalWriter { file, path: path.as_ref().to_path_buf(), count: 0, policy: SyncPolicy::Never, last_sync: Instant::now() })
+    }
+
+    /// Appends to an existing log, creating it if missing. Fails on a log with a corrupt tail,
+    /// see `truncate_to_valid`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JournalWriter, String> {
+        if !path.as_ref().exists() {
+            return Self::create(path);
+        }
+        let validation = validate(path.as_ref())?;
+        if !validation.is_complete() {
+            return Err(format!("{} has a corrupt tail after entry {}", path.as_ref().display(), validation.entries));
+        }
+        let mut file = OpenOptions::new().read(true).write(true).open(path.as_ref()).map_err(|x| x.to_string())?;
+        file.seek(SeekFrom::End(0)).map_err(|x| x.to_string())?;
+        Ok(JournalWriter { file, path: path.as_ref().to_path_buf(), count: validation.entries as u32, policy: SyncPolicy::Never, last_sync: Instant::now() })
+    }
+
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Appends `(`upd; `table; data)`
+    pub fn upd(&mut self, table: &str, data: Payload) -> Result<(), String> {
+        self.append(&Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Symbol(KdbString::from("upd")),
+            Payload::Symbol(KdbString::from(table)),
+            data,
+        ]))
+    }
+
+    /// Appends any entry, then updates the count in the header as q does
+    pub fn append(&mut self, entry: &Payload) -> Result<(), String> {
+        entry.check()?;
+        self.file.write_all(&entry.to_bytes()).map_err(|x| x.to_string())?;
+        self.count += 1;
+        self.file.seek(SeekFrom::Start(4)).map_err(|x| x.to_string())?;
+        self.file.write_all(&self.count.to_le_bytes()).map_err(|x| x.to_string())?;
+        self.file.seek(SeekFrom::End(0)).map_err(|x| x.to_string())?;
+        match self.policy {
+            SyncPolicy::Never => Ok(()),
+            SyncPolicy::EveryEntry => self.sync(),
+            SyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => self.sync(),
+            SyncPolicy::Interval(_) => Ok(()),
+        }
+    }
+
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.file.sync_data().map_err(|x| x.to_string())?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Entries in the log, the `.u.i` of a tickerplant
+    pub fn count(&self) -> u64 {
+        self.count as u64
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A `JournalWriter` starting a new log every day, named like kdb+tick's `sym2024.01.31`.
+pub struct RollingJournal {
+    directory: PathBuf,
+    name: String,
+    policy: SyncPolicy,
+    date: i32,
+    /// UTC date when the last entry was appended
+    clock: i32,
+    writer: JournalWriter,
+}
+
+impl RollingJournal {
+    /// Opens today's (UTC) log `<directory>/<name><date>`, appending if it exists
+    pub fn open<P: AsRef<Path>>(directory: P, name: &str) -> Result<RollingJournal, String> {
+        let date = today();
+        let writer = JournalWriter::open(log_path(directory.as_ref(), name, date))?;
+        Ok(RollingJournal { directory: directory.as_ref().to_path_buf(), name: name.to_string(), policy: SyncPolicy::Never, date, clock: date, writer })
+    }
+
+    pub fn with_sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.policy = policy;
+        self.writer = self.writer.with_sync_policy(policy);
+        self
+    }
+
+    /// Appends `(`upd; `table; data)` to the current log, rolling to a new log after midnight UTC
+    pub fn upd(&mut self, table: &str, data: Payload) -> Result<(), String> {
+        let today = today();
+        if today != self.clock {
+            self.clock = today;
+            self.roll(today)?;
+        }
+        self.writer.upd(table, data)
+    }
+
+    /// Syncs the current log and switches to the log for `date`, days since 2000.01.01 as in q.
+    /// The next roll still happens at midnight UTC.
+    pub fn roll(&mut self, date: i32) -> Result<(), String> {
+        self.writer.sync()?;
+        self.writer = JournalWriter::open(log_path(&self.directory, &self.name, date))?.with_sync_policy(self.policy);
+        self.date = date;
+        Ok(())
+    }
+
+    pub fn date(&self) -> i32 {
+        self.date
+    }
+
+    pub fn writer(&mut self) -> &mut JournalWriter {
+        &mut self.writer
+    }
+}
+
+fn log_path(directory: &Path, name: &str, date: i32) -> PathBuf {
+    directory.join(format!("{}{}", name, format_date(date)))
+}
+
+/// Current UTC date as days since 2000.01.01
+fn today() -> i32 {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    (seconds / 86400) as i32 - KDB_EPOCH_DAYS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::codec::{Payload, VectorAttribute};
+    use crate::journal::{truncate_to_valid, validate, JournalReader, JournalWriter, RollingJournal, SyncPolicy, JOURNAL_HEADER};
+    use crate::fixtures::upd;
+
+    fn write_log(name: &str, entries: &[Payload]) -> (PathBuf, Vec<u8>) {
+        let path = std::env::temp_dir().join(format!("iron_kdb_{}_{}.log", name, std::process::id()));
+        let mut bytes = JOURNAL_HEADER.to_vec();
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        entries.iter().for_each(|x| bytes.extend_from_slice(&x.to_bytes()));
+        std::fs::write(&path, &bytes).unwrap();
+        (path, bytes)
+    }
+
+    #[test]
+    pub fn test_read_entries() {
+        let entries: Vec<Payload> = (0..1000).map(|x| upd(if x % 2 == 0 { "trade" } else { "quote" }, x as f64)).collect();
+        let (path, _) = write_log("read", &entries);
+        let mut reader = JournalReader::open(&path).unwrap();
+        assert_eq!(reader.header_count(), 1000);
+        assert_eq!(reader.skip_entries(998).unwrap(), 998);
+        assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), entries[998..].to_vec());
+
+        let validation = validate(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(validation.entries, 1000);
+        assert!(validation.is_complete());
+    }
+
+    #[test]
+    pub fn test_corrupt_tail() {
+        let entries = vec![upd("trade", 1.0), upd("trade", 2.0), upd("trade", 3.0)];
+        let (path, bytes) = write_log("corrupt", &entries);
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+
+        let read: Vec<_> = JournalReader::open(&path).unwrap().collect();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[1], Ok(entries[1].clone()));
+        assert!(read[2].is_err());
+
+        let validation = truncate_to_valid(&path).unwrap();
+        assert_eq!(validation.entries, 2);
+        let reader = JournalReader::open(&path).unwrap();
+        assert_eq!(reader.header_count(), 2);
+        assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), entries[..2].to_vec());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    pub fn test_write_and_roll() {
+        let directory = std::env::temp_dir().join(format!("iron_kdb_journal_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let mut journal = RollingJournal::open(&directory, "sym").unwrap().with_sync_policy(SyncPolicy::EveryEntry);
+        journal.roll(8795).unwrap();
+        journal.upd("trade", Payload::List(VectorAttribute::NoAttribute, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0])])).unwrap();
+        journal.roll(8796).unwrap();
+        journal.upd("trade", Payload::List(VectorAttribute::NoAttribute, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![2.0])])).unwrap();
+        assert_eq!(journal.writer().path(), directory.join("sym2024.01.31").as_path());
+
+        let mut writer = JournalWriter::open(directory.join("sym2024.01.30")).unwrap();
+        assert_eq!(writer.count(), 1);
+        writer.upd("trade", Payload::List(VectorAttribute::NoAttribute, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![3.0])])).unwrap();
+        let bytes = std::fs::read(directory.join("sym2024.01.30")).unwrap();
+        assert_eq!(bytes[0..8], [0xff, 0x01, 0, 0, 2, 0, 0, 0]);
+        let entries = JournalReader::open(directory.join("sym2024.01.30")).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(entries, vec![upd("trade", 1.0), upd("trade", 3.0)]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
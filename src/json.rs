@@ -0,0 +1,532 @@
+use std::convert::TryInto;
+use serde_json::{Map, Number, Value};
+use crate::codec::{KdbString, Payload, VectorAttribute};
+use crate::builder::{table, table_parts};
+use crate::temporal::{format_datetime, format_minute, format_second, format_time, format_timespan, format_timestamp, iso_date, iso_month,
+    parse_iso_date, parse_iso_month, parse_iso_timestamp, parse_minute, parse_second, parse_time, parse_timespan};
+
+impl Payload {
+    /// JSON as q's `.j.j` writes it. Tables become arrays of objects with keyed tables unkeyed,
+    /// dictionaries objects, strings and symbols strings, temporals ISO 8601 strings and nulls
+    /// `null`. Bytes that aren't UTF-8 become U+FFFD. Types are lost, see `to_tagged_json` to
+    /// keep them.
+    pub fn to_json(&self) -> Result<Value, String> {
+        Ok(match self {
+            Payload::List(_, x) => Value::Array(x.iter().map(Payload::to_json).collect::<Result<_, _>>()?),
+            Payload::CharVector(_, x) => Value::String(x.to_string()),
+            Payload::Table(..) => {
+                let (names, columns) = table_parts(self)?;
+                rows(names.iter().zip(columns).collect())?
+            }
+            Payload::Dictionary(keys, values) => match (keys.as_ref(), values.as_ref()) {
+                // Each row of a keyed table starts with its key columns
+                (Payload::Table(..), Payload::Table(..)) => {
+                    let (key_names, keys) = table_parts(keys)?;
+                    let (names, columns) = table_parts(values)?;
+                    rows(key_names.iter().zip(keys).chain(names.iter().zip(columns)).collect())?
+                }
+                (keys, values) => object(keys, values)?,
+            },
+            Payload::Nil => Value::Null,
+            Payload::NilVector(_, x) => Value::Array(vec![Value::Null; x.len()]),
+            Payload::Error(x) => return Err(format!("'{}", x)),
+            x => match (x.type_byte() < 0, items(x, false)) {
+                (true, mut items) => items.pop().unwrap_or(Value::Null),
+                (false, items) => Value::Array(items),
+            },
+        })
+    }
+
+    /// Reads JSON as q's `.j.k` does: numbers and `null` become floats, strings char vectors,
+    /// arrays of numbers or booleans simple vectors, arrays of objects with the same keys
+    /// tables and objects dictionaries with symbol keys
+    pub fn from_json(value: &Value) -> Result<Payload, String> {
+        Ok(match value {
+            Value::Null => Payload::Float(f64::NAN),
+            Value::Bool(x) => Payload::Bool(*x),
+            Value::Number(x) => Payload::Float(x.as_f64().unwrap_or(f64::NAN)),
+            Value::String(x) => Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from(x.as_str())),
+            Value::Array(x) => match table_from_rows(x)? {
+                Some(table) => table,
+                None => collapse(x.iter().map(Payload::from_json).collect::<Result<_, _>>()?),
+            },
+            Value::Object(x) => Payload::Dictionary(
+                Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, x.keys().map(|x| KdbString::from(x.as_str())).collect())),
+                Box::new(collapse(x.values().map(Payload::from_json).collect::<Result<_, _>>()?))),
+        })
+    }
+
+    /// JSON keeping the q type and attribute of every value, `{"type":7,"attribute":"s","value":[1,2]}`,
+    /// so `from_tagged_json` restores the payload exactly. Values are as `to_json` writes them but
+    /// for floats, where infinities are `"inf"` and `"-inf"`, datetimes, which stay as days, and
+    /// strings that aren't UTF-8, which are arrays of their bytes. A dictionary's keys are under
+    /// `"key"`.
+    pub fn to_tagged_json(&self) -> Value {
+        let mut tagged = Map::new();
+        tagged.insert(String::from("type"), Value::from(self.type_byte()));
+        if let Some(attribute) = attribute_name(self.attribute()) {
+            tagged.insert(String::from("attribute"), Value::from(attribute));
+        }
+        let value = match self {
+            Payload::List(_, x) => Value::Array(x.iter().map(Payload::to_tagged_json).collect()),
+            Payload::CharVector(_, x) | Payload::Error(x) => text(x, true),
+            Payload::Table(_, x) => x.to_tagged_json(),
+            Payload::Dictionary(keys, values) => {
+                tagged.insert(String::from("key"), keys.to_tagged_json());
+                values.to_tagged_json()
+            }
+            Payload::Nil => Value::Null,
+            Payload::NilVector(_, x) => Value::Array(vec![Value::Null; x.len()]),
+            x => match (x.type_byte() < 0, items(x, true)) {
+                (true, mut items) => items.pop().unwrap_or(Value::Null),
+                (false, items) => Value::Array(items),
+            },
+        };
+        tagged.insert(String::from("value"), value);
+        Value::Object(tagged)
+    }
+
+    /// Reads JSON written by `to_tagged_json`
+    pub fn from_tagged_json(value: &Value) -> Result<Payload, String> {
+        let tagged = value.as_object().ok_or("Expected an object with a type and value")?;
+        let type_byte = tagged.get("type").and_then(Value::as_i64).ok_or("Expected a type")?;
+        let attribute = match tagged.get("attribute") {
+            None => VectorAttribute::NoAttribute,
+            Some(x) => x.as_str().and_then(attribute_from_name).ok_or_else(|| format!("Unknown attribute {}", x))?,
+        };
+        let value = tagged.get("value").ok_or("Expected a value")?;
+        Ok(match type_byte {
+            0 => Payload::List(attribute, vector(value, Payload::from_tagged_json)?),
+            -1 => Payload::Bool(boolean(value)?),
+            1 => Payload::BoolVector(attribute, vector(value, boolean)?),
+            -2 => Payload::GUID(guid(value)?),
+            2 => Payload::GUIDVector(attribute, vector(value, guid)?),
+            -4 => Payload::Byte(byte(value)?),
+            4 => Payload::ByteVector(attribute, vector(value, byte)?),
+            -5 => Payload::Short(short(value)?),
+            5 => Payload::ShortVector(attribute, vector(value, short)?),
+            -6 => Payload::Int(int(value)?),
+            6 => Payload::IntVector(attribute, vector(value, int)?),
+            -7 => Payload::Long(long(value)?),
+            7 => Payload::LongVector(attribute, vector(value, long)?),
+            -8 => Payload::Real(real(value)?),
+            8 => Payload::RealVector(attribute, vector(value, real)?),
+            -9 => Payload::Float(float(value)?),
+            9 => Payload::FloatVector(attribute, vector(value, float)?),
+            -10 => Payload::Char(character(value)?),
+            10 => Payload::CharVector(attribute, kdb_string(value)?),
+            -11 => Payload::Symbol(kdb_string(value)?),
+            11 => Payload::SymbolVector(attribute, vector(value, kdb_string)?),
+            -12 => Payload::Timestamp(timestamp(value)?),
+            12 => Payload::TimestampVector(attribute, vector(value, timestamp)?),
+            -13 => Payload::Month(month(value)?),
+            13 => Payload::MonthVector(attribute, vector(value, month)?),
+            -14 => Payload::Date(date(value)?),
+            14 => Payload::DateVector(attribute, vector(value, date)?),
+            -15 => Payload::DateTime(float(value)?.to_bits()),
+            15 => Payload::DateTimeVector(attribute, vector(value, |x| float(x).map(f64::to_bits))?),
+            -16 => Payload::TimeSpan(timespan(value)?),
+            16 => Payload::TimeSpanVector(attribute, vector(value, timespan)?),
+            -17 => Payload::Minute(minute(value)?),
+            17 => Payload::MinuteVector(attribute, vector(value, minute)?),
+            -18 => Payload::Second(second(value)?),
+            18 => Payload::SecondVector(attribute, vector(value, second)?),
+            -19 => Payload::Time(time(value)?),
+            19 => Payload::TimeVector(attribute, vector(value, time)?),
+            98 => Payload::Table(attribute, Box::new(Payload::from_tagged_json(value)?)),
+            99 => Payload::Dictionary(
+                Box::new(Payload::from_tagged_json(tagged.get("key").ok_or("Expected a dictionary key")?)?),
+                Box::new(Payload::from_tagged_json(value)?)),
+            -101 => Payload::Nil,
+            101 => Payload::NilVector(attribute, vector(value, |_| Ok(()))?),
+            -128 => Payload::Error(kdb_string(value)?),
+            x => return Err(format!("Unknown type {}", x)),
+        })
+    }
+}
+
+/// Objects of a table's rows from its columns
+fn rows(columns: Vec<(&KdbString, &Payload)>) -> Result<Value, String> {
+    let columns = columns.into_iter().map(|(name, column)| {
+        let values = match column {
+            // A char column is a char per row
+            Payload::CharVector(..) => items(column, false),
+            x => match x.to_json()? {
+                Value::Array(x) => x,
+                _ => return Err(format!("Column {} isn't a vector", name)),
+            },
+        };
+        Ok((name.to_string(), values))
+    }).collect::<Result<Vec<_>, String>>()?;
+    let count = columns.first().map(|x| x.1.len()).unwrap_or(0);
+    if columns.iter().any(|x| x.1.len() != count) {
+        return Err(String::from("Table columns differ in length"));
+    }
+    let mut ret_val = vec![Map::new(); count];
+    for (name, values) in columns {
+        for (row, value) in ret_val.iter_mut().zip(values) {
+            row.insert(name.clone(), value);
+        }
+    }
+    Ok(Value::Array(ret_val.into_iter().map(Value::Object).collect()))
+}
+
+/// A dictionary as an object, keys that aren't symbols are written as JSON text
+fn object(keys: &Payload, values: &Payload) -> Result<Value, String> {
+    let (keys, values) = match (keys.to_json()?, values.to_json()?) {
+        (Value::Array(keys), Value::Array(values)) if keys.len() == values.len() => (keys, values),
+        _ => return Err(String::from("Dictionary keys and values aren't lists of the same length")),
+    };
+    Ok(Value::Object(keys.into_iter().zip(values).map(|(key, value)| match key {
+        Value::String(key) => (key, value),
+        key => (key.to_string(), value),
+    }).collect()))
+}
+
+/// A table from objects with the same keys in the same order, `None` if they aren't
+fn table_from_rows(rows: &[Value]) -> Result<Option<Payload>, String> {
+    let first = match rows.first() {
+        Some(Value::Object(x)) => x,
+        _ => return Ok(None),
+    };
+    let mut columns = vec![Vec::with_capacity(rows.len()); first.len()];
+    for row in rows {
+        match row {
+            Value::Object(row) if row.len() == first.len() && row.keys().eq(first.keys()) => {
+                for (column, value) in columns.iter_mut().zip(row.values()) {
+                    column.push(Payload::from_json(value)?);
+                }
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(table(&first.keys().collect::<Vec<_>>(), columns.into_iter().map(collapse).collect())))
+}
+
+/// Floats or booleans as a simple vector as `.j.k` makes them, anything else as a general list
+fn collapse(items: Vec<Payload>) -> Payload {
+    if !items.is_empty() && items.iter().all(|x| matches!(x, Payload::Float(_))) {
+        Payload::FloatVector(VectorAttribute::NoAttribute, items.into_iter().filter_map(|x| match x {
+            Payload::Float(x) => Some(x),
+            _ => None,
+        }).collect())
+    } else if !items.is_empty() && items.iter().all(|x| matches!(x, Payload::Bool(_))) {
+        Payload::BoolVector(VectorAttribute::NoAttribute, items.into_iter().map(|x| x == Payload::Bool(true)).collect())
+    } else {
+        Payload::List(VectorAttribute::NoAttribute, items)
+    }
+}
+
+/// JSON of each item of a simple vector, or of an atom as its one item. `exact` keeps the
+/// infinities of floats and datetimes as days, as `to_tagged_json` needs.
+fn items(payload: &Payload, exact: bool) -> Vec<Value> {
+    fn each<T, F: Fn(&T) -> Value>(values: &[T], f: F) -> Vec<Value> {
+        values.iter().map(f).collect()
+    }
+    fn nullable(x: i64, null: i64) -> Value {
+        if x == null { Value::Null } else { Value::from(x) }
+    }
+    let float = |x: f64| match Number::from_f64(x) {
+        Some(x) => Value::Number(x),
+        None if exact && !x.is_nan() => Value::from(if x > 0.0 { "inf" } else { "-inf" }),
+        None => Value::Null,
+    };
+    // Shortest decimal that reads back as the same real
+    let real = |x: f32| float(x.to_string().parse().unwrap_or(x as f64));
+    let guid = |x: u128| match x {
+        0 if !exact => Value::Null,
+        x => Value::from(Payload::GUID(x).to_string()),
+    };
+    let datetime = |x: u64| match f64::from_bits(x) {
+        x if exact => float(x),
+        x if x.is_finite() => Value::from(format_datetime(x, true)),
+        x => float(x),
+    };
+
+    match payload {
+        Payload::Bool(x) => vec![Value::Bool(*x)],
+        Payload::BoolVector(_, x) => each(x, |x| Value::Bool(*x)),
+        Payload::GUID(x) => vec![guid(*x)],
+        Payload::GUIDVector(_, x) => each(x, |x| guid(*x)),
+        Payload::Byte(x) => vec![byte_text(*x)],
+        Payload::ByteVector(_, x) => each(x, |x| byte_text(*x)),
+        Payload::Short(x) => vec![nullable(*x as i16 as i64, i16::MIN as i64)],
+        Payload::ShortVector(_, x) => each(x, |x| nullable(*x as i16 as i64, i16::MIN as i64)),
+        Payload::Int(x) => vec![nullable(*x as i32 as i64, i32::MIN as i64)],
+        Payload::IntVector(_, x) => each(x, |x| nullable(*x as i32 as i64, i32::MIN as i64)),
+        Payload::Long(x) => vec![nullable(*x as i64, i64::MIN)],
+        Payload::LongVector(_, x) => each(x, |x| nullable(*x as i64, i64::MIN)),
+        Payload::Real(x) => vec![real(*x)],
+        Payload::RealVector(_, x) => each(x, |x| real(*x)),
+        Payload::Float(x) => vec![float(*x)],
+        Payload::FloatVector(_, x) => each(x, |x| float(*x)),
+        Payload::Char(x) => vec![Value::from(x.to_string())],
+        Payload::CharVector(_, x) => x.as_bytes().iter().map(|item| Value::from(String::from_utf8_lossy(&[*item]))).collect(),
+        Payload::Symbol(x) => vec![text(x, exact)],
+        Payload::SymbolVector(_, x) => each(x, |x| text(x, exact)),
+        x @ Payload::InternedSymbolVector(..) => items(&x.clone().resolve_symbols(), exact),
+        Payload::Timestamp(x) => vec![long_temporal(*x, |x| format_timestamp(x, true))],
+        Payload::TimestampVector(_, x) => each(x, |x| long_temporal(*x, |x| format_timestamp(x, true))),
+        Payload::Month(x) => vec![int_temporal(*x, iso_month)],
+        Payload::MonthVector(_, x) => each(x, |x| int_temporal(*x, iso_month)),
+        Payload::Date(x) => vec![int_temporal(*x, iso_date)],
+        Payload::DateVector(_, x) => each(x, |x| int_temporal(*x, iso_date)),
+        Payload::DateTime(x) => vec![datetime(*x)],
+        Payload::DateTimeVector(_, x) => each(x, |x| datetime(*x)),
+        Payload::TimeSpan(x) => vec![long_temporal(*x, format_timespan)],
+        Payload::TimeSpanVector(_, x) => each(x, |x| long_temporal(*x, format_timespan)),
+        Payload::Minute(x) => vec![int_temporal(*x, |x| format_minute(x as i64))],
+        Payload::MinuteVector(_, x) => each(x, |x| int_temporal(*x, |x| format_minute(x as i64))),
+        Payload::Second(x) => vec![int_temporal(*x, |x| format_second(x as i64))],
+        Payload::SecondVector(_, x) => each(x, |x| int_temporal(*x, |x| format_second(x as i64))),
+        Payload::Time(x) => vec![int_temporal(*x, |x| format_time(x as i64))],
+        Payload::TimeVector(_, x) => each(x, |x| int_temporal(*x, |x| format_time(x as i64))),
+        _ => Vec::new(),
+    }
+}
+
+/// Bytes are two hex digits, as `.j.j` writes them
+fn byte_text(x: u8) -> Value {
+    Value::from(format!("{:02x}", x))
+}
+
+/// A temporal held in a long, null is `null` and the infinities `"inf"` and `"-inf"`
+fn long_temporal<F: Fn(i64) -> String>(x: u64, f: F) -> Value {
+    match x as i64 {
+        i64::MIN => Value::Null,
+        i64::MAX => Value::from("inf"),
+        x if x == -i64::MAX => Value::from("-inf"),
+        x => Value::from(f(x)),
+    }
+}
+
+/// A temporal held in an int, null is `null` and the infinities `"inf"` and `"-inf"`
+fn int_temporal<F: Fn(i32) -> String>(x: u32, f: F) -> Value {
+    match x as i32 {
+        i32::MIN => Value::Null,
+        i32::MAX => Value::from("inf"),
+        x if x == -i32::MAX => Value::from("-inf"),
+        x => Value::from(f(x)),
+    }
+}
+
+fn attribute_name(attribute: VectorAttribute) -> Option<&'static str> {
+    match attribute {
+        VectorAttribute::NoAttribute => None,
+        VectorAttribute::Sorted => Some("s"),
+        VectorAttribute::Unique => Some("u"),
+        VectorAttribute::Parted => Some("p"),
+        VectorAttribute::Grouped => Some("g"),
+    }
+}
+
+fn attribute_from_name(name: &str) -> Option<VectorAttribute> {
+    match name {
+        "s" => Some(VectorAttribute::Sorted),
+        "u" => Some(VectorAttribute::Unique),
+        "p" => Some(VectorAttribute::Parted),
+        "g" => Some(VectorAttribute::Grouped),
+        _ => None,
+    }
+}
+
+/// A string or symbol, lossless as an array of bytes if `exact` and it isn't UTF-8
+fn text(x: &KdbString, exact: bool) -> Value {
+    match x.to_str() {
+        Ok(x) => Value::from(x),
+        Err(_) if exact => Value::from(x.as_bytes()),
+        Err(_) => Value::from(x.to_string()),
+    }
+}
+
+fn vector<T, F: Fn(&Value) -> Result<T, String>>(value: &Value, f: F) -> Result<Vec<T>, String> {
+    value.as_array().ok_or_else(|| format!("Expected an array, got {}", value))?.iter().map(f).collect()
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    value.as_str().ok_or_else(|| format!("Expected a string, got {}", value))
+}
+
+fn boolean(value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| format!("Expected a boolean, got {}", value))
+}
+
+fn guid(value: &Value) -> Result<u128, String> {
+    let hex = string(value)?.replace('-', "");
+    let bytes = (0..hex.len()).step_by(2).map(|x| hex.get(x..x + 2).and_then(|x| u8::from_str_radix(x, 16).ok()))
+        .collect::<Option<Vec<u8>>>().filter(|x| x.len() == 16).ok_or_else(|| format!("Expected a GUID, got {}", value))?;
+    Ok(u128::from_le_bytes(bytes.try_into().unwrap_or_default()))
+}
+
+fn byte(value: &Value) -> Result<u8, String> {
+    string(value).ok().filter(|x| x.len() == 2).and_then(|x| u8::from_str_radix(x, 16).ok()).ok_or_else(|| format!("Expected a byte, got {}", value))
+}
+
+/// An integer in `null + 1..=-(null + 1)`, `null` for q's null
+fn integer(value: &Value, null: i64) -> Result<i64, String> {
+    match value {
+        Value::Null => Ok(null),
+        x => x.as_i64().filter(|x| *x > null && *x <= -(null + 1)).ok_or_else(|| format!("Expected an integer, got {}", value)),
+    }
+}
+
+fn short(value: &Value) -> Result<u16, String> {
+    integer(value, i16::MIN as i64).map(|x| x as i16 as u16)
+}
+
+fn int(value: &Value) -> Result<u32, String> {
+    integer(value, i32::MIN as i64).map(|x| x as i32 as u32)
+}
+
+fn long(value: &Value) -> Result<u64, String> {
+    integer(value, i64::MIN).map(|x| x as u64)
+}
+
+fn float(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Null => Ok(f64::NAN),
+        Value::String(x) if x == "inf" => Ok(f64::INFINITY),
+        Value::String(x) if x == "-inf" => Ok(f64::NEG_INFINITY),
+        x => x.as_f64().ok_or_else(|| format!("Expected a number, got {}", value)),
+    }
+}
+
+fn real(value: &Value) -> Result<f32, String> {
+    float(value).map(|x| x as f32)
+}
+
+/// A char is one byte, read as the character with that code point
+fn character(value: &Value) -> Result<char, String> {
+    let mut chars = string(value)?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(x), None) if x <= '\u{ff}' => Ok(x),
+        _ => Err(format!("Expected one character up to U+00FF, got {}", value)),
+    }
+}
+
+/// A string or symbol written by `text`
+fn kdb_string(value: &Value) -> Result<KdbString, String> {
+    match value {
+        Value::String(x) => Ok(KdbString::from(x.as_str())),
+        x => vector(x, |x| x.as_u64().filter(|x| *x <= 0xff).map(|x| x as u8).ok_or_else(|| format!("Expected a byte, got {}", x))).map(KdbString::from),
+    }
+}
+
+/// A temporal written by `long_temporal` or `int_temporal`, held in the range of `null`'s type
+fn temporal<F: Fn(&str) -> Option<i64>>(value: &Value, null: i64, parse: F) -> Result<i64, String> {
+    match value {
+        Value::Null => Ok(null),
+        Value::String(x) if x == "inf" => Ok(-(null + 1)),
+        Value::String(x) if x == "-inf" => Ok(null + 1),
+        Value::String(x) => parse(x).filter(|x| *x > null + 1 && *x < -(null + 1)).ok_or_else(|| format!("Can't read {}", value)),
+        x => Err(format!("Expected a string, got {}", x)),
+    }
+}
+
+fn timestamp(value: &Value) -> Result<u64, String> {
+    temporal(value, i64::MIN, parse_iso_timestamp).map(|x| x as u64)
+}
+
+fn month(value: &Value) -> Result<u32, String> {
+    temporal(value, i32::MIN as i64, |x| parse_iso_month(x).map(i64::from)).map(|x| x as i32 as u32)
+}
+
+fn date(value: &Value) -> Result<u32, String> {
+    temporal(value, i32::MIN as i64, |x| parse_iso_date(x).map(i64::from)).map(|x| x as i32 as u32)
+}
+
+fn timespan(value: &Value) -> Result<u64, String> {
+    temporal(value, i64::MIN, parse_timespan).map(|x| x as u64)
+}
+
+fn minute(value: &Value) -> Result<u32, String> {
+    temporal(value, i32::MIN as i64, parse_minute).map(|x| x as i32 as u32)
+}
+
+fn second(value: &Value) -> Result<u32, String> {
+    temporal(value, i32::MIN as i64, parse_second).map(|x| x as i32 as u32)
+}
+
+fn time(value: &Value) -> Result<u32, String> {
+    temporal(value, i32::MIN as i64, parse_time).map(|x| x as i32 as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::builder::table;
+    use crate::fixtures::symbol_vector;
+
+    #[test]
+    pub fn test_to_json() {
+        let trades = table(&["sym", "time", "px"], vec![
+            symbol_vector(&["a", "b"]),
+            Payload::TimestampVector(VectorAttribute::NoAttribute, vec![8796 * 86_400_000_000_000 + 1, i64::MIN as u64]),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, f64::NAN])]);
+        assert_eq!(trades.to_json().unwrap(), json!([
+            {"sym": "a", "time": "2024-01-31T00:00:00.000000001", "px": 1.5},
+            {"sym": "b", "time": null, "px": null}]));
+
+        let keyed = Payload::Dictionary(
+            Box::new(table(&["sym"], vec![symbol_vector(&["a"])])),
+            Box::new(table(&["d", "b"], vec![
+                Payload::DateVector(VectorAttribute::NoAttribute, vec![8796]),
+                Payload::ByteVector(VectorAttribute::NoAttribute, vec![0x1f])])));
+        assert_eq!(keyed.to_json().unwrap().to_string(), r#"[{"sym":"a","d":"2024-01-31","b":"1f"}]"#);
+
+        let dictionary = Payload::Dictionary(Box::new(symbol_vector(&["a", "b"])), Box::new(Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Int(i32::MIN as u32),
+            Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("xy"))])));
+        assert_eq!(dictionary.to_json().unwrap(), json!({"a": null, "b": "xy"}));
+        assert!(Payload::error("type").to_json().is_err());
+    }
+
+    #[test]
+    pub fn test_from_json() {
+        let value: Value = serde_json::from_str(r#"[{"a":1,"b":"x"},{"a":null,"b":"y"}]"#).unwrap();
+        let table = Payload::from_json(&value).unwrap();
+        assert_eq!(table.to_json().unwrap(), json!([{"a": 1.0, "b": "x"}, {"a": null, "b": "y"}]));
+        assert_eq!(Payload::from_json(&json!({"a": true, "b": false})).unwrap(),
+                   Payload::Dictionary(Box::new(symbol_vector(&["a", "b"])), Box::new(Payload::BoolVector(VectorAttribute::NoAttribute, vec![true, false]))));
+        assert_eq!(Payload::from_json(&json!([1, "x"])).unwrap(), Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Float(1.0), Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("x"))]));
+        assert_eq!(Payload::from_json(&json!({"café": "ü"})).unwrap().to_json().unwrap(), json!({"café": "ü"}));
+    }
+
+    #[test]
+    pub fn test_tagged_round_trip() {
+        let values = Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::GUIDVector(VectorAttribute::NoAttribute, vec![0, 0x0123456789abcdef0123456789abcdef]),
+            Payload::ShortVector(VectorAttribute::Sorted, vec![i16::MIN as u16, i16::MAX as u16, 3]),
+            Payload::Long(i64::MIN as u64),
+            Payload::Real(0.1),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![f64::INFINITY, f64::NEG_INFINITY, -2.5]),
+            Payload::Char('q'),
+            Payload::Char('\u{e9}'),
+            Payload::Symbol(KdbString::new()),
+            Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("Zürich"), KdbString::from(vec![b'a', 0xff])]),
+            Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from(vec![0xc3])),
+            Payload::TimestampVector(VectorAttribute::NoAttribute, vec![i64::MIN as u64, i64::MAX as u64, -i64::MAX as u64, (-86_400_000_000_001i64) as u64]),
+            Payload::MonthVector(VectorAttribute::NoAttribute, vec![i32::MAX as u32, (-1i32) as u32]),
+            Payload::Date(i32::MIN as u32),
+            Payload::DateTime(1.25f64.to_bits()),
+            Payload::TimeSpanVector(VectorAttribute::NoAttribute, vec![(-86_400_000_000_001i64) as u64, 90_061_000_000_001]),
+            Payload::MinuteVector(VectorAttribute::NoAttribute, vec![(-61i32) as u32, 1500]),
+            Payload::Second(3661),
+            Payload::TimeVector(VectorAttribute::NoAttribute, vec![(-1i32) as u32, 86_399_999]),
+            Payload::Nil,
+            Payload::error("type"),
+        ]);
+        let keyed = Payload::Dictionary(
+            Box::new(table(&["sym"], vec![Payload::SymbolVector(VectorAttribute::Unique, vec![KdbString::from("a")])])),
+            Box::new(table(&["v"], vec![Payload::List(VectorAttribute::NoAttribute, vec![values])])));
+        let text = keyed.to_tagged_json().to_string();
+        assert_eq!(Payload::from_tagged_json(&serde_json::from_str(&text).unwrap()).unwrap(), keyed);
+
+        assert_eq!(Payload::Date(8796).to_tagged_json(), json!({"type": -14, "value": "2024-01-31"}));
+        assert!(Payload::from_tagged_json(&json!({"type": -14, "value": "2023-02-29"})).is_err());
+        assert!(Payload::from_tagged_json(&json!({"type": -5, "value": 40000})).is_err());
+        assert!(Payload::from_tagged_json(&json!({"type": 5, "attribute": "x", "value": []})).is_err());
+    }
+}
@@ -1,15 +1,52 @@
 pub mod codec;
+pub mod intern;
+pub mod builder;
+pub mod validate;
+pub mod reconnect;
+pub mod pool;
+pub mod tick;
+pub mod publish;
+pub mod server;
+pub mod record;
+pub mod journal;
+pub mod file;
+pub mod compressed;
+pub mod console;
+pub mod export;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod splayed;
+pub mod hdb;
+mod temporal;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+/// Payloads the tests of every module build tables and messages from
+#[cfg(test)]
+mod fixtures;
+#[cfg(feature = "tokio")]
+pub mod async_connection;
 
 use std::net::TcpStream;
 use std::net::ToSocketAddrs;
 use std::io::{Write, Read};
-use crate::codec::Payload;
-use ascii::IntoAsciiString;
-use std::convert::TryInto;
+use crate::codec::{Payload, SynchronisationType};
+use crate::intern::SymbolTable;
+use crate::record::{Direction, SessionRecorder};
+use std::array::TryFromSliceError;
+use std::convert::{TryInto, TryFrom};
+
+/// Answers a message initiated by the server, e.g. `neg[.z.w]` (async) or `.z.w` (sync).
+/// The result of a sync call is sent back as the response, errors as a q error.
+pub type RequestHandler = Box<dyn FnMut(SynchronisationType, Payload) -> Result<Payload, String> + Send>;
 
 pub struct KdbConnection<R : Read,W : Write> {
     tcp_connection_read: R,
-    tcp_connection_write: W
+    tcp_connection_write: W,
+    request_handler: Option<RequestHandler>,
+    compression: bool,
+    recorder: Option<SessionRecorder<Box<dyn Write + Send>>>,
+    symbols: Option<SymbolTable>,
+    max_message_size: u32,
 }
 
 impl KdbConnection<TcpStream,TcpStream> {
@@ -17,17 +54,55 @@ impl KdbConnection<TcpStream,TcpStream> {
         let tcp_connection_write = TcpStream::connect(address)?;
         let tcp_connection_read = tcp_connection_write.try_clone()?;
 
-        Ok(KdbConnection { tcp_connection_read,tcp_connection_write})
+        Ok(KdbConnection::from_streams(tcp_connection_read, tcp_connection_write))
     }
 }
 
 impl <R : Read,W : Write> KdbConnection<R,W> {
+    pub fn from_streams(tcp_connection_read: R, tcp_connection_write: W) -> KdbConnection<R, W> {
+        KdbConnection { tcp_connection_read, tcp_connection_write, request_handler: None, compression: false, recorder: None, symbols: None, max_message_size: u32::MAX }
+    }
+
+    /// Compresses outgoing payload messages larger than 2000 bytes, as q does for remote handles
+    pub fn set_compression(&mut self, compression: bool) {
+        self.compression = compression;
+    }
+
+    /// Fails rather than allocates for received messages longer than `size` bytes, compressed or
+    /// not. Unlimited by default.
+    pub fn set_max_message_size(&mut self, size: u32) {
+        self.max_message_size = size;
+    }
+
+    /// Decodes symbol vectors in the messages received from now on into ids in `symbols`, see
+    /// `SymbolTable`. The table may be shared with other connections.
+    pub fn intern_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    /// Installs the handler for calls the server makes back over this connection. Without one,
+    /// sync calls are answered with a `'nyi` error and async calls are dropped.
+    pub fn set_request_handler<F: FnMut(SynchronisationType, Payload) -> Result<Payload, String> + Send + 'static>(&mut self, handler: F) {
+        self.request_handler = Some(Box::new(handler));
+    }
+
+    /// Records every message sent and received from now on, see `record::SessionReader` to read it back
+    pub fn record_to<T: Write + Send + 'static>(&mut self, writer: T) -> std::io::Result<()> {
+        let writer: Box<dyn Write + Send> = Box::new(std::io::BufWriter::new(writer));
+        self.recorder = Some(SessionRecorder::new(writer)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recorder = None;
+    }
+
     /// Sends handshake byte
     pub fn connect(&mut self, user: &str, pwd: &str) -> std::io::Result<()> {
         let mut user_pass = format!("{}:{}", user, pwd);
         user_pass.push(3 as char);
         user_pass.push(0 as char);
-        self.tcp_connection_write.write_all(user_pass.into_ascii_string().unwrap().as_bytes())?;
+        self.tcp_connection_write.write_all(user_pass.as_bytes())?;
         let mut buf = [0u8; 1];
         self.tcp_connection_read.read_exact(&mut buf)?;
         Ok(())
@@ -37,17 +112,62 @@ impl <R : Read,W : Write> KdbConnection<R,W> {
         let vec: Vec<u8> = msg.to_bytes();
 
         //println!("Sent: {:?}", hex::encode(vec.clone()));
+        self.record(Direction::Sent, &vec)?;
         self.tcp_connection_write.write_all(vec.as_slice()).map_err(|x| x.to_string())?;
         self.receive()
     }
 
-    fn receive(&mut self) -> std::result::Result<Payload, String> {
+    /// Sync call with an arbitrary payload, e.g. a function application `(`f; arg1; arg2)`
+    pub fn call(&mut self, payload: &Payload) -> Result<Payload, String> {
+        self.write_message(SynchronisationType::Sync, payload)?;
+        self.receive()
+    }
+
+    /// Sends an async message, q does not reply to these
+    pub fn send_async(&mut self, payload: &Payload) -> Result<(), String> {
+        self.write_message(SynchronisationType::Async, payload)
+    }
+
+    fn write_message(&mut self, message_type: SynchronisationType, payload: &Payload) -> Result<(), String> {
+        payload.check()?;
+        let vec = codec::encode_message(message_type, payload);
+        let vec = match self.compression && vec.len() > 2000 {
+            true => compress(&vec).unwrap_or(vec),
+            false => vec,
+        };
+        self.record(Direction::Sent, &vec)?;
+        self.tcp_connection_write.write_all(vec.as_slice()).map_err(|x| x.to_string())
+    }
+
+    /// Writes every request before reading any reply, costing one round trip for the whole batch.
+    /// q answers sync messages in order so replies are returned in request order. Very large
+    /// batches can deadlock once both socket buffers are full, split them up if needed.
+    pub fn pipeline(&mut self, msgs: &[codec::KdbRequest]) -> Result<Vec<Payload>, String> {
+        let mut vec: Vec<u8> = Vec::new();
+        for msg in msgs {
+            let bytes = msg.to_bytes();
+            self.record(Direction::Sent, &bytes)?;
+            vec.extend_from_slice(&bytes);
+        }
+        self.tcp_connection_write.write_all(vec.as_slice()).map_err(|x| x.to_string())?;
+        msgs.iter().map(|_| self.receive()).collect()
+    }
+
+    /// Blocks until the server sends a message and hands it to the request handler
+    pub fn process_incoming(&mut self) -> Result<(), String> {
+        let (message_type, payload) = self.receive_message()?;
+        match message_type {
+            SynchronisationType::Response => Err(String::from("Received a response without an outstanding request")),
+            _ => self.handle_request(message_type, payload),
+        }
+    }
+
+    /// Reads the next message of any type without dispatching it
+    pub fn receive_message(&mut self) -> Result<(SynchronisationType, Payload), String> {
         let mut header = [0u8; 8];
         self.tcp_connection_read.read_exact(&mut header).map_err(|x| x.to_string())?;
-        let mut msg_size_array = [0u8; 4];
-        msg_size_array.clone_from_slice(&header[4..8]);
-        let msg_size: u32 = u32::from_le_bytes(msg_size_array);
-        let mut buf = vec![0;msg_size as usize];
+        let msg_size = message_size(&header, self.max_message_size)?;
+        let mut buf = vec![0;msg_size];
         // Alignment - Potential performance improvement at the cost of perhaps portability,
         // and having to deal with endianness - easy optimisation if both source and target are the same
         // endianness
@@ -58,57 +178,200 @@ impl <R : Read,W : Write> KdbConnection<R,W> {
 
         std::io::Read::by_ref(&mut self.tcp_connection_read).take((msg_size - 8) as u64).read_exact(&mut buf[8..]).map_err(|x| x.to_string())?;
 
-        if header[2] == 1 {
-            let uncompressed = uncompress(&buf[8..])?;
-            buf = Vec::with_capacity(uncompressed.len());
-            buf.extend_from_slice(&header);
-            buf.extend_from_slice(&uncompressed[8..]);
+        //println!("Received: {:?}", hex::encode(buf.clone()));
+        self.record(Direction::Received, &buf)?;
+        uncompressed_size(&buf, self.max_message_size)?;
+        Ok((SynchronisationType::try_from(header[1])?, decode_message(buf, self.symbols.as_ref())?))
+    }
+
+    /// Waits for the response to an outstanding request, serving any calls from the server meanwhile
+    fn receive(&mut self) -> std::result::Result<Payload, String> {
+        loop {
+            match self.receive_message()? {
+                (SynchronisationType::Response, payload) => return Ok(payload),
+                (message_type, payload) => self.handle_request(message_type, payload)?,
+            }
         }
+    }
 
-        //println!("Received: {:?}", hex::encode(buf.clone()));
-        let start = std::time::Instant::now();
-        let ret_val = Ok(Payload::from_bytes(&buf.as_slice()[8..])?);
-        println!("{:?}", std::time::Instant::now() - start);
-        ret_val
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> Result<(), String> {
+        match self.recorder.as_mut() {
+            Some(recorder) => recorder.record(direction, bytes).map_err(|x| x.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn handle_request(&mut self, message_type: SynchronisationType, payload: Payload) -> Result<(), String> {
+        let result = match self.request_handler.as_mut() {
+            Some(handler) => handler(message_type, payload),
+            None => Err(String::from("nyi")),
+        };
+        if message_type == SynchronisationType::Sync {
+            let response = result.and_then(|x| x.check().map(|_| x)).unwrap_or_else(|x| Payload::error(&x));
+            self.write_message(SynchronisationType::Response, &response)?;
+        }
+        Ok(())
+    }
+}
+
+/// Total message length (header included) as announced in bytes 4..8 of the header, failing if it
+/// can't hold the header or is over `max`
+pub(crate) fn message_size(header: &[u8; 8], max: u32) -> Result<usize, String> {
+    let mut msg_size_array = [0u8; 4];
+    msg_size_array.clone_from_slice(&header[4..8]);
+    match u32::from_le_bytes(msg_size_array) {
+        x if x < 8 => Err(format!("Invalid message size {}", x)),
+        x if x > max => Err(format!("Message of {} bytes exceeds the limit of {}", x, max)),
+        x => Ok(x as usize),
+    }
+}
+
+/// Length a complete message uncompresses to, failing if it's over `max`
+pub(crate) fn uncompressed_size(buf: &[u8], max: u32) -> Result<usize, String> {
+    match buf.get(2) {
+        Some(1) => match buf.get(8..12).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]])) {
+            Some(x) if x > max => Err(format!("Message uncompressing to {} bytes exceeds the limit of {}", x, max)),
+            Some(x) => Ok(x as usize),
+            None => Err(String::from("Truncated compressed message")),
+        },
+        _ => Ok(buf.len()),
+    }
+}
+
+/// Decodes a complete message (header included), uncompressing it first if flagged in byte 2
+pub(crate) fn decode_message(mut buf: Vec<u8>, symbols: Option<&SymbolTable>) -> Result<Payload, String> {
+    if buf.len() < 8 {
+        return Err(String::from("Truncated message header"));
+    }
+    if buf[2] == 1 {
+        let uncompressed = uncompress(&buf[8..])?;
+        let mut header = [0u8; 8];
+        header.copy_from_slice(&buf[0..8]);
+        buf = Vec::with_capacity(uncompressed.len());
+        buf.extend_from_slice(&header);
+        buf.extend_from_slice(&uncompressed[8..]);
+    }
+    match symbols {
+        Some(symbols) => symbols.decode_all(&buf.as_slice()[8..]),
+        None => Payload::from_bytes(&buf.as_slice()[8..]),
+    }
+}
+
+/// Compresses a complete message (header included) with kx's IPC algorithm, the inverse of
+/// `uncompress`. Returns `None` if the result would not be under half the original size.
+pub fn compress(bytes: &[u8]) -> Option<Vec<u8>> {
+    let t = bytes.len();
+    let e = t / 2;
+    if e < 17 + 12 {
+        return None;
+    }
+    let mut dst = vec![0u8; e];
+    dst[0..4].copy_from_slice(&bytes[0..4]);
+    dst[2] = 1;
+    dst[8..12].copy_from_slice(&(t as u32).to_le_bytes());
+    let mut aa = [0usize; 256];
+    let mut f_bit = 0u8;
+    let mut f = 0;
+    let mut d = 12;
+    let mut s = 8;
+    let mut s0 = 0;
+    let mut h = 0;
+    let mut h0 = 0;
+    while s < t {
+        if f_bit == 0 {
+            if d > e - 17 {
+                return None;
+            }
+            f_bit = 1;
+            f = d;
+            dst[f] = 0;
+            d += 1;
+        }
+        let mut p = 0;
+        let mut literal = s > t - 3;
+        if !literal {
+            h = (bytes[s] ^ bytes[s + 1]) as usize;
+            p = aa[h];
+            literal = p == 0 || bytes[s] != bytes[p];
+        }
+        if s0 > 0 {
+            aa[h0] = s0;
+            s0 = 0;
+        }
+        if literal {
+            h0 = h;
+            s0 = s;
+            dst[d] = bytes[s];
+            d += 1;
+            s += 1;
+        } else {
+            aa[h] = s;
+            dst[f] |= f_bit;
+            p += 2;
+            s += 2;
+            let r = s;
+            let end = (s + 255).min(t);
+            while s < end && bytes[p] == bytes[s] {
+                p += 1;
+                s += 1;
+            }
+            dst[d] = h as u8;
+            dst[d + 1] = (s - r) as u8;
+            d += 2;
+        }
+        f_bit = f_bit.wrapping_mul(2);
     }
+    dst[4..8].copy_from_slice(&(d as u32).to_le_bytes());
+    dst.truncate(d);
+    Some(dst)
 }
 
+/// Inverse of `compress`, given a message without its 8 byte header. Fails on truncated or
+/// inconsistent input rather than reading out of bounds.
 pub fn uncompress(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let byte = |i: usize| bytes.get(i).map(|x| *x as u32).ok_or_else(|| String::from("Truncated compressed message"));
     let mut n = 0;
     let mut f = 0;
     let mut s = 8;
     let mut p = 8;
     let mut f_bit = 0;
-    let result_size = bytes[0..4].try_into().map(u32::from_le_bytes).map_err(|x| x.to_string())?;
+    let result_size = bytes.get(0..4).ok_or_else(|| String::from("Truncated compressed message"))?
+        .try_into().map(u32::from_le_bytes).map_err(|x: TryFromSliceError| x.to_string())?;
+    if result_size < 8 {
+        return Err(format!("Invalid uncompressed size {}", result_size));
+    }
     let mut d = 4;
     let mut dst = vec![0u8; result_size as usize];
     let mut aa = [0u32; 256];
     while s < result_size {
         if f_bit == 0 {
-            f = 0xff & (bytes[d] as u32);
+            f = byte(d)?;
             d += 1;
             f_bit = 1;
         }
         if (f & f_bit) != 0 {
-            let mut r = aa[(0xff & (bytes[d] as u32)) as usize];
+            let mut r = aa[byte(d)? as usize];
             d += 1;
+            n = byte(d)?;
+            if n + 2 > result_size - s {
+                return Err(String::from("Compressed message overruns its uncompressed size"));
+            }
             dst[s as usize] = dst[r as usize];
             s += 1;
             r += 1;
             dst[s as usize] = dst[r as usize];
             s += 1;
             r += 1;
-            n = 0xff & (bytes[d] as u32);
             for m in 0..n {
                 dst[(s + m) as usize] = dst[(r + m) as usize];
             }
         } else {
-            dst[s as usize] = bytes[d as usize];
+            dst[s as usize] = byte(d)? as u8;
             s += 1;
         }
         d += 1;
         while p < (s - 1) {
-            aa[((0xff & (dst[p as usize] as u32)) ^ (0xff & (dst[(p + 1) as usize] as u32))) as usize] = p as u32;
+            aa[((0xff & (dst[p as usize] as u32)) ^ (0xff & (dst[(p + 1) as usize] as u32))) as usize] = p;
             p += 1;
         }
         if (f & f_bit) != 0 {
@@ -125,19 +388,22 @@ pub fn uncompress(bytes: &[u8]) -> Result<Vec<u8>, String> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{uncompress, KdbConnection};
-    use crate::codec::{Payload, KdbRequest, VectorAttribute};
+    use crate::{compress, uncompress, KdbConnection};
+    use crate::codec::{Payload, KdbRequest, KdbString, VectorAttribute, SynchronisationType};
     use crate::codec::Payload::LongVector;
     use crate::codec::VectorAttribute::NoAttribute;
-    use std::ops::Range;
     use std::io::{Read, Write};
     use std::io::Result;
-    use ascii::AsciiString;
 
     #[test]
     pub fn test_uncompress() {
         let a = hex::decode("ae0f0000c00700f401000000060106aa0200050300050400050500052e0600050700000408000400095500050a00050b00050c00050d5500050e00050f00051000051155000512000513000514000515550005160005170005180005195500051a00051b00051c00051d5500051e00051f00052000052155000522000523000524000525550005260005270005280005295500052a00052b00052c00052d5500052e00052f00053000053155000532000533000534000535550005360005370005380005395500053a00053b00053c00053d5500053e00053f00054000054155000542000543000544000545550005460005470005480005495500054a00054b00054c00054d5500054e00054f00055000055155000552000553000554000555550005560005570005580005595500055a00055b00055c00055d5500055e00055f00056000056155000562000563000564000565550005660005670005680005695500056a00056b00056c00056d5500056e00056f00057000057155000572000573000574000575550005760005770005780005795500057a00057b00057c00057d5500057e00057f00058000058155000582000583000584000585550005860005870005880005895500058a00058b00058c00058d5500058e00058f00059000059155000592000593000594000595550005960005970005980005995500059a00059b00059c00059d5500059e00059f0005a00005a1550005a20005a30005a40005a5550005a60005a70005a80005a9550005aa0005ab0005ac0005ad550005ae0005af0005b00005b1550005b20005b30005b40005b5550005b60005b70005b80005b9550005ba0005bb0005bc0005bd550005be0005bf0005c00005c1550005c20005c30005c40005c5550005c60005c70005c80005c9550005ca0005cb0005cc0005cd550005ce0005cf0005d00005d1550005d20005d30005d40005d5550005d60005d70005d80005d9550005da0005db0005dc0005dd550005de0005df0005e00005e1550005e20005e30005e40005e5550005e60005e70005e80005e9550005ea0005eb0005ec0005ed550005ee0005ef0005f00005f1550005f20005f30005f40005f5550005f60005f70005f80005f9550005fa0005fb0005fc0005fd550005fe0005ff00050001050155010502010503010504010505550105060105070105080105095501050a01050b01050c01050d5501050e01050f01051001051155010512010513010514010515550105160105170105180105195501051a01051b01051c01051d5501051e01051f01052001052155010522010523010524010525550105260105270105280105295501052a01052b01052c01052d5501052e01052f01053001053155010532010533010534010535550105360105370105380105395501053a01053b01053c01053d5501053e01053f01054001054155010542010543010544010545550105460105470105480105495501054a01054b01054c01054d5501054e01054f01055001055155010552010553010554010555550105560105570105580105595501055a01055b01055c01055d5501055e01055f01056001056155010562010563010564010565550105660105670105680105695501056a01056b01056c01056d5501056e01056f01057001057155010572010573010574010575550105760105770105780105795501057a01057b01057c01057d5501057e01057f01058001058155010582010583010584010585550105860105870105880105895501058a01058b01058c01058d5501058e01058f01059001059155010592010593010594010595550105960105970105980105995501059a01059b01059c01059d5501059e01059f0105a00105a1550105a20105a30105a40105a5550105a60105a70105a80105a9550105aa0105ab0105ac0105ad550105ae0105af0105b00105b1550105b20105b30105b40105b5550105b60105b70105b80105b9550105ba0105bb0105bc0105bd550105be0105bf0105c00105c1550105c20105c30105c40105c5550105c60105c70105c80105c9550105ca0105cb0105cc0105cd550105ce0105cf0105d00105d1550105d20105d30105d40105d5550105d60105d70105d80105d9550105da0105db0105dc0105dd550105de0105df0105e00105e1550105e20105e30105e40105e5550105e60105e70105e80105e9550105ea0105eb0105ec0105ed550105ee0105ef0105f00105f1150105f20105f30105").unwrap();
-        assert_eq!(Payload::from_bytes(&uncompress(&a).unwrap()[8..]).unwrap(), LongVector(NoAttribute,Range::from(0..500).into_iter().collect()))
+        assert_eq!(Payload::from_bytes(&uncompress(&a).unwrap()[8..]).unwrap(), LongVector(NoAttribute,(0..500).collect()));
+        assert!(uncompress(&a[..a.len() / 2]).is_err());
+        assert!(uncompress(&a[..2]).is_err());
+        let mut lying = a.clone();
+        lying[0..4].copy_from_slice(&5000u32.to_le_bytes());
+        assert!(uncompress(&lying).is_err());
     }
 
     struct MockWrite {
@@ -170,14 +436,11 @@ mod tests {
 
     #[test]
     pub fn test_connect() {
-        let mut kdb_connection = KdbConnection {
-            tcp_connection_read: MockRead{to_read: Vec::new()},
-            tcp_connection_write: MockWrite{written: Vec::new()}
-        };
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
 
         kdb_connection.tcp_connection_read.to_read = vec![3;1];
         kdb_connection.connect("MOCK_USER","MOCK_PASS").unwrap();
-        let connect_values = AsciiString::from_ascii("MOCK_USER:MOCK_PASS").unwrap();
+        let connect_values = KdbString::from("MOCK_USER:MOCK_PASS");
         let mut expected_bytes = Vec::from(connect_values.as_bytes());
         expected_bytes.push(3);
         expected_bytes.push(0);
@@ -186,8 +449,8 @@ mod tests {
 
         kdb_connection.tcp_connection_write.written = Vec::new();
 
-        kdb_connection.tcp_connection_read.to_read = hex::decode("010000001a0000000a000c00000069276d736f6d657175657279").unwrap();
-        let payload = kdb_connection.query(KdbRequest::new("somequery").unwrap()).unwrap();
+        kdb_connection.tcp_connection_read.to_read = hex::decode("010200001a0000000a000c00000069276d736f6d657175657279").unwrap();
+        let payload = kdb_connection.query(KdbRequest::new("somequery")).unwrap();
         if let Payload::CharVector(attriubte,string) = payload {
             assert_eq!(attriubte,VectorAttribute::NoAttribute);
             assert_eq!(string,"i'msomequery");
@@ -195,4 +458,76 @@ mod tests {
 
         assert_eq!(kdb_connection.tcp_connection_write.written, hex::decode("01010000170000000a0009000000736f6d657175657279").unwrap());
     }
+
+    #[test]
+    pub fn test_pipeline() {
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
+
+        kdb_connection.tcp_connection_read.to_read = hex::decode("0102000011000000f901000000000000000102000011000000f90200000000000000").unwrap();
+        let payloads = kdb_connection.pipeline(&[KdbRequest::new("a"), KdbRequest::new("b")]).unwrap();
+        assert_eq!(payloads, vec![Payload::Long(1), Payload::Long(2)]);
+        assert_eq!(kdb_connection.tcp_connection_write.written, hex::decode("010100000f0000000a000100000061010100000f0000000a000100000062").unwrap());
+    }
+
+    #[test]
+    pub fn test_server_calls_during_query() {
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
+        kdb_connection.set_request_handler(|message_type, payload| match payload {
+            Payload::Long(x) => Ok(Payload::Long(x + 1)),
+            _ => Err(format!("{:?}", message_type)),
+        });
+
+        // Async long 5, sync long 9, sync symbol `a and finally the response to our query
+        kdb_connection.tcp_connection_read.to_read = hex::decode(
+            "0100000011000000f905000000000000000101000011000000f909000000000000000101000\
+             00b000000f561000102000011000000f90100000000000000").unwrap();
+        assert_eq!(kdb_connection.query(KdbRequest::new("a")).unwrap(), Payload::Long(1));
+        assert_eq!(kdb_connection.tcp_connection_write.written, hex::decode(
+            "010100000f0000000a000100000061\
+             0102000011000000f90a00000000000000\
+             010200000e0000008053796e6300").unwrap());
+    }
+
+    #[test]
+    pub fn test_process_incoming_without_handler() {
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
+        kdb_connection.tcp_connection_read.to_read = hex::decode("0101000011000000f90100000000000000").unwrap();
+        kdb_connection.process_incoming().unwrap();
+        assert_eq!(kdb_connection.tcp_connection_write.written,
+                   crate::codec::encode_message(SynchronisationType::Response, &Payload::error("nyi")));
+    }
+
+    #[test]
+    pub fn test_invalid_payload_not_sent() {
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
+        let dictionary = Payload::Dictionary(Box::new(LongVector(NoAttribute, vec![1])), Box::new(LongVector(NoAttribute, vec![])));
+        assert_eq!(kdb_connection.send_async(&dictionary), Err(String::from("x: 1 keys for 0 values")));
+        assert!(kdb_connection.tcp_connection_write.written.is_empty());
+    }
+
+    #[test]
+    pub fn test_compress() {
+        let payload = Payload::List(NoAttribute, vec![LongVector(NoAttribute, (0..500).collect()), Payload::ByteVector(NoAttribute, vec![7; 3000])]);
+        let message = crate::codec::encode_message(SynchronisationType::Async, &payload);
+        let compressed = compress(&message).unwrap();
+        assert!(compressed.len() < message.len() / 2);
+        assert_eq!(compressed[2], 1);
+        assert_eq!(u32::from_le_bytes([compressed[4], compressed[5], compressed[6], compressed[7]]) as usize, compressed.len());
+        assert_eq!(uncompress(&compressed[8..]).unwrap()[8..], message[8..]);
+        assert_eq!(compress(&message[..40]), None);
+
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: compressed.clone()}, MockWrite{written: Vec::new()});
+        kdb_connection.set_max_message_size(message.len() as u32 - 1);
+        assert!(kdb_connection.receive_message().is_err());
+    }
+
+    #[test]
+    pub fn test_invalid_message_size() {
+        let mut kdb_connection = KdbConnection::from_streams(MockRead{to_read: Vec::new()}, MockWrite{written: Vec::new()});
+        kdb_connection.tcp_connection_read.to_read = hex::decode("0101000004000000").unwrap();
+        assert_eq!(kdb_connection.receive_message(), Err(String::from("Invalid message size 4")));
+        kdb_connection.tcp_connection_read.to_read = hex::decode("0101000011000000f90100000000000000").unwrap();
+        kdb_connection.set_max_message_size(16);
+        assert_eq!(kdb_connection.receive_message(), Err(String::from("Message of 17 bytes exceeds the limit of 16")));
+    }
 }
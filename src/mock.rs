@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::codec::{Payload, SynchronisationType};
+use crate::record::{request_responses, RecordedMessage};
+use crate::server::{Authenticator, KdbServer, ServerHandle, Session};
+
+/// What the mock does when a request matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Payload(Payload),
+    /// Sent back as a q error, `'message`
+    Error(String),
+    /// Waits before giving the inner reply
+    Delay(Duration, Box<Reply>),
+    /// Hangs up on the client without replying
+    Disconnect,
+}
+
+/// Which requests a scripted reply applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Matcher {
+    /// A query string, e.g. `KdbRequest::new("select from t")`
+    Query(String),
+    /// A function call list whose first element is this symbol, e.g. `(`.u.sub; `t; `)`
+    Call(String),
+    /// Exactly this payload
+    Payload(Payload),
+    Any,
+}
+
+impl Matcher {
+    fn matches(&self, request: &Payload) -> bool {
+        match (self, request) {
+            (Matcher::Query(query), Payload::CharVector(_, x)) => x.as_bytes() == query.as_bytes(),
+            (Matcher::Call(function), Payload::List(_, x)) => matches!(x.first(), Some(Payload::Symbol(x)) if x.as_bytes() == function.as_bytes()),
+            (Matcher::Payload(payload), x) => payload == x,
+            (Matcher::Any, _) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Script {
+    rules: Vec<(Matcher, VecDeque<Reply>)>,
+    requests: Vec<(SynchronisationType, Payload)>,
+}
+
+impl Script {
+    /// The next reply for the first matching rule, the last reply of a rule repeats forever
+    fn reply(&mut self, request: &Payload) -> Option<Reply> {
+        let (_, replies) = self.rules.iter_mut().find(|(matcher, _)| matcher.matches(request))?;
+        match replies.len() {
+            1 => replies.front().cloned(),
+            _ => replies.pop_front(),
+        }
+    }
+}
+
+/// Local stand-in for a q process answering requests from a script of canned replies, so code
+/// using `KdbConnection` can be tested without q. Enable the `test-support` feature to use it
+/// from another crate's tests.
+///
+/// Requests without a matching rule get a `'mock: unexpected request` error.
+pub struct MockServer {
+    handle: ServerHandle,
+    script: Arc<Mutex<Script>>,
+}
+
+impl MockServer {
+    /// Starts listening on an ephemeral localhost port, accepting any credentials
+    pub fn start() -> std::io::Result<MockServer> {
+        Self::start_with_authenticator(crate::server::AllowAll)
+    }
+
+    pub fn start_with_authenticator<A: Authenticator + 'static>(authenticator: A) -> std::io::Result<MockServer> {
+        let script = Arc::new(Mutex::new(Script::default()));
+        let shared = script.clone();
+        let handler = move |session: &Session, message_type: SynchronisationType, request: Payload| {
+            let reply = {
+                let mut script = shared.lock().map_err(|x| x.to_string())?;
+                let reply = script.reply(&request);
+                script.requests.push((message_type, request));
+                reply
+            };
+            respond(session, reply.unwrap_or_else(|| Reply::Error(String::from("mock: unexpected request"))))
+        };
+        let handle = KdbServer::bind("127.0.0.1:0", handler)?.with_authenticator(authenticator).spawn()?;
+        Ok(MockServer { handle, script })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// Adds a reply for matching requests. Calling this again for the same matcher queues
+    /// replies to give in order, the last one is repeated.
+    pub fn on(&self, matcher: Matcher, reply: Reply) {
+        let mut script = self.script.lock().unwrap_or_else(|x| x.into_inner());
+        match script.rules.iter_mut().find(|(x, _)| *x == matcher) {
+            Some((_, replies)) => replies.push_back(reply),
+            None => script.rules.push((matcher, VecDeque::from(vec![reply]))),
+        }
+    }
+
+    pub fn on_query(&self, query: &str, payload: Payload) {
+        self.on(Matcher::Query(query.to_string()), Reply::Payload(payload));
+    }
+
+    pub fn on_call(&self, function: &str, payload: Payload) {
+        self.on(Matcher::Call(function.to_string()), Reply::Payload(payload));
+    }
+
+    /// Scripts the sync requests of a recorded session to get the responses recorded for them,
+    /// in the order they were given. Server initiated messages are not replayed.
+    pub fn replay(&self, messages: &[RecordedMessage]) -> Result<(), String> {
+        for (request, response) in request_responses(messages)? {
+            self.on(Matcher::Payload(request), Reply::Payload(response));
+        }
+        Ok(())
+    }
+
+    /// Every request received so far, in arrival order
+    pub fn requests(&self) -> Vec<(SynchronisationType, Payload)> {
+        self.script.lock().unwrap_or_else(|x| x.into_inner()).requests.clone()
+    }
+
+    pub fn shutdown(self) -> std::io::Result<()> {
+        self.handle.shutdown()
+    }
+}
+
+fn respond(session: &Session, reply: Reply) -> Result<Payload, String> {
+    match reply {
+        Reply::Payload(payload) => Ok(payload),
+        Reply::Error(error) => Err(error),
+        Reply::Delay(delay, reply) => {
+            std::thread::sleep(delay);
+            respond(session, *reply)
+        }
+        Reply::Disconnect => {
+            session.disconnect();
+            Err(String::from("disconnected"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::KdbConnection;
+    use crate::codec::{KdbRequest, KdbString, Payload, SynchronisationType, VectorAttribute};
+    use crate::mock::{Matcher, MockServer, Reply};
+
+    #[test]
+    pub fn test_scripted_replies() {
+        let mock = MockServer::start().unwrap();
+        mock.on_query("til 2", Payload::LongVector(VectorAttribute::NoAttribute, vec![0, 1]));
+        mock.on_call("f", Payload::Long(1));
+        mock.on_call("f", Payload::Long(2));
+        mock.on(Matcher::Query("boom".to_string()), Reply::Error(String::from("type")));
+
+        let mut connection = KdbConnection::new(mock.address()).unwrap();
+        connection.connect("user", "pass").unwrap();
+        assert_eq!(connection.query(KdbRequest::new("til 2")).unwrap(), Payload::LongVector(VectorAttribute::NoAttribute, vec![0, 1]));
+        let call = Payload::List(VectorAttribute::NoAttribute, vec![Payload::Symbol(KdbString::from("f")), Payload::Long(0)]);
+        assert_eq!(connection.call(&call).unwrap(), Payload::Long(1));
+        assert_eq!(connection.call(&call).unwrap(), Payload::Long(2));
+        assert_eq!(connection.call(&call).unwrap(), Payload::Long(2));
+        assert_eq!(connection.query(KdbRequest::new("boom")).unwrap(), Payload::error("type"));
+        assert_eq!(connection.query(KdbRequest::new("other")).unwrap(), Payload::error("mock: unexpected request"));
+
+        assert_eq!(mock.requests().len(), 6);
+        assert_eq!(mock.requests()[1], (SynchronisationType::Sync, call));
+    }
+
+    #[test]
+    pub fn test_delay_and_disconnect() {
+        let mock = MockServer::start().unwrap();
+        mock.on(Matcher::Query("slow".to_string()), Reply::Delay(Duration::from_millis(50), Box::new(Reply::Payload(Payload::Long(1)))));
+        mock.on(Matcher::Any, Reply::Disconnect);
+
+        let mut connection = KdbConnection::new(mock.address()).unwrap();
+        connection.connect("user", "pass").unwrap();
+        let start = Instant::now();
+        assert_eq!(connection.query(KdbRequest::new("slow")).unwrap(), Payload::Long(1));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(connection.query(KdbRequest::new("anything")).is_err());
+    }
+}
@@ -0,0 +1,482 @@
+use std::collections::VecDeque;
+use std::net::TcpStream;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use crate::KdbConnection;
+use crate::codec::{KdbRequest, Payload};
+
+/// Settings shared by `Pool` and `AsyncPool`.
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    pub address: String,
+    pub user: String,
+    pub password: String,
+    /// Connections opened up front and never evicted for being idle
+    pub min_size: usize,
+    pub max_size: usize,
+    /// How long `get` waits for a connection to be returned once `max_size` are checked out
+    pub checkout_timeout: Duration,
+    /// Query run on every checkout, the connection is replaced if it fails or returns a q error
+    pub health_check: Option<String>,
+    /// Idle connections above `min_size` are closed after this long
+    pub idle_timeout: Option<Duration>,
+    /// Queries run once on every new connection after the user/password handshake
+    pub init_queries: Vec<String>,
+}
+
+impl PoolConfig {
+    pub fn new(address: &str, user: &str, password: &str) -> PoolConfig {
+        PoolConfig {
+            address: address.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            min_size: 0,
+            max_size: 8,
+            checkout_timeout: Duration::from_secs(30),
+            health_check: None,
+            idle_timeout: Some(Duration::from_secs(300)),
+            init_queries: Vec::new(),
+        }
+    }
+}
+
+/// Snapshot of how many connections a pool holds.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PoolStatus {
+    pub open: usize,
+    pub idle: usize,
+}
+
+struct IdleConnection<C> {
+    connection: C,
+    idle_since: Instant,
+}
+
+struct PoolState<C> {
+    idle: VecDeque<IdleConnection<C>>,
+    open: usize,
+}
+
+impl<C> PoolState<C> {
+    fn new() -> PoolState<C> {
+        PoolState { idle: VecDeque::new(), open: 0 }
+    }
+
+    /// Closes the oldest idle connections past `idle_timeout` while staying above `min_size`
+    fn evict_idle(&mut self, config: &PoolConfig) {
+        if let Some(idle_timeout) = config.idle_timeout {
+            while self.open > config.min_size && self.idle.front().is_some_and(|x| x.idle_since.elapsed() >= idle_timeout) {
+                self.idle.pop_front();
+                self.open -= 1;
+            }
+        }
+    }
+
+    fn release(&mut self, connection: C) {
+        self.idle.push_back(IdleConnection { connection, idle_since: Instant::now() });
+    }
+}
+
+fn is_healthy(result: Result<Payload, String>) -> bool {
+    !matches!(result, Err(_) | Ok(Payload::Error(_)))
+}
+
+struct PoolInner {
+    config: PoolConfig,
+    state: Mutex<PoolState<KdbConnection<TcpStream, TcpStream>>>,
+    available: Condvar,
+}
+
+/// Thread-safe pool of blocking connections, cheap to clone and share between threads.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<PoolInner>,
+}
+
+impl Pool {
+    /// Creates the pool and opens `min_size` connections
+    pub fn new(config: PoolConfig) -> Result<Pool, String> {
+        if config.max_size == 0 || config.min_size > config.max_size {
+            return Err(format!("Invalid pool size, min {} max {}", config.min_size, config.max_size));
+        }
+        let pool = Pool { inner: Arc::new(PoolInner { config, state: Mutex::new(PoolState::new()), available: Condvar::new() }) };
+        for _ in 0..pool.inner.config.min_size {
+            let connection = pool.open()?;
+            let mut state = pool.inner.state.lock().map_err(|x| x.to_string())?;
+            state.open += 1;
+            state.release(connection);
+        }
+        Ok(pool)
+    }
+
+    /// Checks out a connection, opening a new one if none are idle and the pool isn't full
+    pub fn get(&self) -> Result<PooledConnection, String> {
+        let deadline = Instant::now() + self.inner.config.checkout_timeout;
+        let mut state = self.inner.state.lock().map_err(|x| x.to_string())?;
+        loop {
+            state.evict_idle(&self.inner.config);
+            if let Some(idle) = state.idle.pop_back() {
+                drop(state);
+                let mut connection = idle.connection;
+                if self.check(&mut connection) {
+                    return Ok(self.wrap(connection));
+                }
+                state = self.inner.state.lock().map_err(|x| x.to_string())?;
+                state.open -= 1;
+            } else if state.open < self.inner.config.max_size {
+                state.open += 1;
+                drop(state);
+                return match self.open() {
+                    Ok(connection) => Ok(self.wrap(connection)),
+                    Err(error) => {
+                        self.inner.state.lock().map_err(|x| x.to_string())?.open -= 1;
+                        self.inner.available.notify_one();
+                        Err(error)
+                    }
+                };
+            } else {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(format!("Timed out after {:?} waiting for a connection", self.inner.config.checkout_timeout));
+                }
+                state = self.inner.available.wait_timeout(state, deadline - now).map_err(|x| x.to_string())?.0;
+            }
+        }
+    }
+
+    pub fn status(&self) -> PoolStatus {
+        let state = self.inner.state.lock().unwrap_or_else(|x| x.into_inner());
+        PoolStatus { open: state.open, idle: state.idle.len() }
+    }
+
+    fn open(&self) -> Result<KdbConnection<TcpStream, TcpStream>, String> {
+        let config = &self.inner.config;
+        let mut connection = KdbConnection::new(config.address.as_str()).map_err(|x| x.to_string())?;
+        connection.connect(&config.user, &config.password).map_err(|x| x.to_string())?;
+        for query in &config.init_queries {
+            if let Payload::Error(error) = connection.query(KdbRequest::new(query))? {
+                return Err(format!("Init query {} failed: {}", query, error));
+            }
+        }
+        Ok(connection)
+    }
+
+    fn check(&self, connection: &mut KdbConnection<TcpStream, TcpStream>) -> bool {
+        match &self.inner.config.health_check {
+            Some(query) => is_healthy(connection.query(KdbRequest::new(query))),
+            None => true,
+        }
+    }
+
+    fn wrap(&self, connection: KdbConnection<TcpStream, TcpStream>) -> PooledConnection {
+        PooledConnection { pool: self.inner.clone(), connection: Some(connection) }
+    }
+}
+
+/// A checked out connection, returned to its pool when dropped.
+pub struct PooledConnection {
+    pool: Arc<PoolInner>,
+    connection: Option<KdbConnection<TcpStream, TcpStream>>,
+}
+
+impl PooledConnection {
+    /// Closes the connection instead of returning it, e.g. after a failed query
+    pub fn discard(mut self) {
+        self.connection = None;
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = KdbConnection<TcpStream, TcpStream>;
+
+    fn deref(&self) -> &Self::Target {
+        self.connection.as_ref().expect("connection taken")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.connection.as_mut().expect("connection taken")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let mut state = self.pool.state.lock().unwrap_or_else(|x| x.into_inner());
+        match self.connection.take() {
+            Some(connection) => state.release(connection),
+            None => state.open -= 1,
+        }
+        self.pool.available.notify_one();
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::asynchronous::{AsyncPool, AsyncPooledConnection};
+
+#[cfg(feature = "tokio")]
+mod asynchronous {
+    use std::ops::{Deref, DerefMut};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+    use crate::async_connection::AsyncKdbConnection;
+    use crate::codec::{KdbRequest, Payload};
+    use crate::pool::{is_healthy, PoolConfig, PoolState, PoolStatus};
+
+    type Connection = AsyncKdbConnection<OwnedReadHalf, OwnedWriteHalf>;
+
+    struct AsyncPoolInner {
+        config: PoolConfig,
+        state: Mutex<PoolState<Connection>>,
+        permits: Arc<Semaphore>,
+    }
+
+    /// Tokio flavour of `Pool`, checkouts wait asynchronously for a free slot.
+    #[derive(Clone)]
+    pub struct AsyncPool {
+        inner: Arc<AsyncPoolInner>,
+    }
+
+    impl AsyncPool {
+        /// Creates the pool and opens `min_size` connections
+        pub async fn new(config: PoolConfig) -> Result<AsyncPool, String> {
+            if config.max_size == 0 || config.min_size > config.max_size {
+                return Err(format!("Invalid pool size, min {} max {}", config.min_size, config.max_size));
+            }
+            let permits = Arc::new(Semaphore::new(config.max_size));
+            let pool = AsyncPool { inner: Arc::new(AsyncPoolInner { config, state: Mutex::new(PoolState::new()), permits }) };
+            for _ in 0..pool.inner.config.min_size {
+                let connection = pool.open().await?;
+                let mut state = pool.inner.state.lock().map_err(|x| x.to_string())?;
+                state.open += 1;
+                state.release(connection);
+            }
+            Ok(pool)
+        }
+
+        /// Checks out a connection, opening a new one if none are idle
+        pub async fn get(&self) -> Result<AsyncPooledConnection, String> {
+            let timeout = self.inner.config.checkout_timeout;
+            let permit = tokio::time::timeout(timeout, self.inner.permits.clone().acquire_owned()).await
+                .map_err(|_| format!("Timed out after {:?} waiting for a connection", timeout))?
+                .map_err(|x| x.to_string())?;
+            loop {
+                let idle = {
+                    let mut state = self.inner.state.lock().map_err(|x| x.to_string())?;
+                    state.evict_idle(&self.inner.config);
+                    let idle = state.idle.pop_back();
+                    if idle.is_none() {
+                        state.open += 1;
+                    }
+                    idle
+                };
+                let slot = OpenSlot { state: &self.inner.state, taken: true };
+                match idle {
+                    Some(idle) => {
+                        let mut connection = idle.connection;
+                        if self.check(&mut connection).await {
+                            slot.keep();
+                            return Ok(self.wrap(connection, permit));
+                        }
+                    }
+                    None => {
+                        let connection = self.open().await?;
+                        slot.keep();
+                        return Ok(self.wrap(connection, permit));
+                    }
+                }
+            }
+        }
+
+        pub fn status(&self) -> PoolStatus {
+            let state = self.inner.state.lock().unwrap_or_else(|x| x.into_inner());
+            PoolStatus { open: state.open, idle: state.idle.len() }
+        }
+
+        async fn open(&self) -> Result<Connection, String> {
+            let config = &self.inner.config;
+            let mut connection = AsyncKdbConnection::new(config.address.as_str()).await.map_err(|x| x.to_string())?;
+            connection.connect(&config.user, &config.password).await.map_err(|x| x.to_string())?;
+            for query in &config.init_queries {
+                if let Payload::Error(error) = connection.query(KdbRequest::new(query)).await? {
+                    return Err(format!("Init query {} failed: {}", query, error));
+                }
+            }
+            Ok(connection)
+        }
+
+        async fn check(&self, connection: &mut Connection) -> bool {
+            match &self.inner.config.health_check {
+                Some(query) => is_healthy(connection.query(KdbRequest::new(query)).await),
+                None => true,
+            }
+        }
+
+        fn wrap(&self, connection: Connection, permit: OwnedSemaphorePermit) -> AsyncPooledConnection {
+            AsyncPooledConnection { pool: self.inner.clone(), connection: Some(connection), _permit: permit }
+        }
+    }
+
+    /// A connection counted as open while it's being opened or health checked. Frees its place in
+    /// the pool if that fails or the checkout's future is dropped, unless kept.
+    struct OpenSlot<'a> {
+        state: &'a Mutex<PoolState<Connection>>,
+        taken: bool,
+    }
+
+    impl OpenSlot<'_> {
+        fn keep(mut self) {
+            self.taken = false;
+        }
+    }
+
+    impl Drop for OpenSlot<'_> {
+        fn drop(&mut self) {
+            if self.taken {
+                self.state.lock().unwrap_or_else(|x| x.into_inner()).open -= 1;
+            }
+        }
+    }
+
+    /// A checked out async connection, returned to its pool when dropped.
+    pub struct AsyncPooledConnection {
+        pool: Arc<AsyncPoolInner>,
+        connection: Option<Connection>,
+        _permit: OwnedSemaphorePermit,
+    }
+
+    impl AsyncPooledConnection {
+        /// Closes the connection instead of returning it, e.g. after a failed query
+        pub fn discard(mut self) {
+            self.connection = None;
+        }
+    }
+
+    impl Deref for AsyncPooledConnection {
+        type Target = Connection;
+
+        fn deref(&self) -> &Self::Target {
+            self.connection.as_ref().expect("connection taken")
+        }
+    }
+
+    impl DerefMut for AsyncPooledConnection {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            self.connection.as_mut().expect("connection taken")
+        }
+    }
+
+    impl Drop for AsyncPooledConnection {
+        fn drop(&mut self) {
+            let mut state = self.pool.state.lock().unwrap_or_else(|x| x.into_inner());
+            match self.connection.take() {
+                Some(connection) => state.release(connection),
+                None => state.open -= 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pool::{Pool, PoolConfig, PoolStatus};
+    use crate::codec::{KdbRequest, Payload};
+    use std::net::TcpListener;
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    /// Accepts connections forever, answering every query with the long 1
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                std::thread::spawn(move || {
+                    let mut byte = [0u8; 1];
+                    while { stream.read_exact(&mut byte).unwrap(); byte[0] != 0 } {}
+                    stream.write_all(&[3]).unwrap();
+                    let mut header = [0u8; 8];
+                    while stream.read_exact(&mut header).is_ok() {
+                        let mut body = vec![0u8; u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize - 8];
+                        stream.read_exact(&mut body).unwrap();
+                        stream.write_all(&hex::decode("0102000011000000f90100000000000000").unwrap()).unwrap();
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    #[test]
+    pub fn test_checkout_and_return() {
+        let mut config = PoolConfig::new(&serve(), "user", "pass");
+        config.min_size = 1;
+        config.max_size = 2;
+        config.health_check = Some(String::from("1"));
+        let pool = Pool::new(config).unwrap();
+        assert_eq!(pool.status(), PoolStatus { open: 1, idle: 1 });
+
+        let mut first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert_eq!(pool.status(), PoolStatus { open: 2, idle: 0 });
+        assert_eq!(first.query(KdbRequest::new("1")).unwrap(), Payload::Long(1));
+
+        drop(first);
+        assert_eq!(pool.status(), PoolStatus { open: 2, idle: 1 });
+        second.discard();
+        assert_eq!(pool.status(), PoolStatus { open: 1, idle: 1 });
+    }
+
+    #[test]
+    pub fn test_checkout_timeout() {
+        let mut config = PoolConfig::new(&serve(), "user", "pass");
+        config.max_size = 1;
+        config.checkout_timeout = Duration::from_millis(10);
+        let pool = Pool::new(config).unwrap();
+        let _held = pool.get().unwrap();
+        assert!(pool.get().is_err());
+    }
+
+    #[test]
+    pub fn test_idle_eviction() {
+        let mut config = PoolConfig::new(&serve(), "user", "pass");
+        config.idle_timeout = Some(Duration::from_millis(0));
+        let pool = Pool::new(config).unwrap();
+        drop(pool.get().unwrap());
+        assert_eq!(pool.status(), PoolStatus { open: 1, idle: 1 });
+        drop(pool.get().unwrap());
+        assert_eq!(pool.status(), PoolStatus { open: 1, idle: 1 });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    pub async fn test_async_checkout_and_return() {
+        let mut config = PoolConfig::new(&serve(), "user", "pass");
+        config.max_size = 1;
+        config.checkout_timeout = Duration::from_millis(10);
+        config.health_check = Some(String::from("1"));
+        let pool = crate::pool::AsyncPool::new(config).await.unwrap();
+
+        let mut connection = pool.get().await.unwrap();
+        assert_eq!(connection.query(KdbRequest::new("1")).await.unwrap(), Payload::Long(1));
+        assert!(pool.get().await.is_err());
+        drop(connection);
+        assert_eq!(pool.status(), PoolStatus { open: 1, idle: 1 });
+        assert!(pool.get().await.is_ok());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    pub async fn test_async_cancelled_checkout() {
+        // Accepts connections but never answers the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = PoolConfig::new(&listener.local_addr().unwrap().to_string(), "user", "pass");
+        let pool = crate::pool::AsyncPool::new(config).await.unwrap();
+
+        assert!(tokio::time::timeout(Duration::from_millis(50), pool.get()).await.is_err());
+        assert_eq!(pool.status(), PoolStatus { open: 0, idle: 0 });
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use crate::KdbConnection;
+use crate::codec::{KdbString, Payload, VectorAttribute};
+
+/// When buffered rows are sent to the tickerplant.
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    /// Flush a table once this many rows are buffered
+    pub max_rows: usize,
+    /// Flush a table once its oldest buffered row is this old
+    pub max_delay: Duration,
+    /// Compress large batches with kx's IPC compression
+    pub compress: bool,
+    /// Function called on the tickerplant with `(table; columns)`
+    pub function: String,
+}
+
+impl Default for PublisherConfig {
+    fn default() -> Self {
+        PublisherConfig {
+            max_rows: 1000,
+            max_delay: Duration::from_millis(100),
+            compress: false,
+            function: String::from(".u.upd"),
+        }
+    }
+}
+
+struct TableBuffer {
+    table: String,
+    columns: Vec<Payload>,
+    rows: usize,
+    oldest: Instant,
+}
+
+/// Batches rows per table into column vectors and sends them as async `.u.upd[`table; columns]`.
+///
+/// There is no background thread, time based flushes happen on the next `publish` or an explicit
+/// `flush_due`. Call `flush` before dropping the publisher or buffered rows are lost.
+pub struct Publisher<R: Read, W: Write> {
+    connection: KdbConnection<R, W>,
+    config: PublisherConfig,
+    buffers: Vec<TableBuffer>,
+}
+
+impl Publisher<TcpStream, TcpStream> {
+    pub fn connect(address: &str, user: &str, pwd: &str, config: PublisherConfig) -> Result<Publisher<TcpStream, TcpStream>, String> {
+        let mut connection = KdbConnection::new(address).map_err(|x| x.to_string())?;
+        connection.connect(user, pwd).map_err(|x| x.to_string())?;
+        Ok(Publisher::new(connection, config))
+    }
+}
+
+impl<R: Read, W: Write> Publisher<R, W> {
+    pub fn new(mut connection: KdbConnection<R, W>, config: PublisherConfig) -> Publisher<R, W> {
+        connection.set_compression(config.compress);
+        Publisher { connection, config, buffers: Vec::new() }
+    }
+
+    /// Buffers one row of atoms, the column types are fixed by the first row of each batch. `Err`
+    /// means the row was rejected and nothing was buffered. Batches the row makes full or due are
+    /// sent straight away, but if that fails they stay buffered and the error comes from the next
+    /// `flush` or `flush_due`, so a row that returned `Ok` must not be published again.
+    pub fn publish(&mut self, table: &str, row: Vec<Payload>) -> Result<(), String> {
+        let index = match self.buffers.iter().position(|x| x.table == table) {
+            Some(index) => index,
+            None => {
+                self.buffers.push(TableBuffer { table: table.to_string(), columns: Vec::new(), rows: 0, oldest: Instant::now() });
+                self.buffers.len() - 1
+            }
+        };
+        let buffer = &mut self.buffers[index];
+        if buffer.rows == 0 {
+            buffer.columns = row.iter().map(Payload::empty_vector_for).collect();
+            buffer.oldest = Instant::now();
+        } else if buffer.columns.len() != row.len() {
+            return Err(format!("Expected {} columns for {} but got {}", buffer.columns.len(), table, row.len()));
+        }
+        // Check every column before appending so a bad row doesn't leave the batch ragged
+        if let Some((column, atom)) = buffer.columns.iter().zip(row.iter()).find(|(column, atom)| !column.accepts(atom)) {
+            return Err(format!("Can't append {:?} to type {} in {}", atom, column.type_byte(), table));
+        }
+        for (column, atom) in buffer.columns.iter_mut().zip(row) {
+            column.push(atom)?;
+        }
+        buffer.rows += 1;
+        // The row is buffered either way, a failed send is retried and reported by flush or flush_due
+        if buffer.rows >= self.config.max_rows {
+            let _ = self.flush_table(index);
+        }
+        let _ = self.flush_due();
+        Ok(())
+    }
+
+    /// Sends already columnar data straight away, after anything buffered for the table
+    pub fn publish_columns(&mut self, table: &str, columns: Vec<Payload>) -> Result<(), String> {
+        if let Some(index) = self.buffers.iter().position(|x| x.table == table) {
+            self.flush_table(index)?;
+        }
+        self.send(table, columns).map_err(|(error, _)| error)
+    }
+
+    /// Flushes the tables whose oldest buffered row is older than `max_delay`
+    pub fn flush_due(&mut self) -> Result<(), String> {
+        for index in 0..self.buffers.len() {
+            if self.buffers[index].rows > 0 && self.buffers[index].oldest.elapsed() >= self.config.max_delay {
+                self.flush_table(index)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        for index in 0..self.buffers.len() {
+            self.flush_table(index)?;
+        }
+        Ok(())
+    }
+
+    /// Rows buffered for `table` and not yet sent
+    pub fn pending(&self, table: &str) -> usize {
+        self.buffers.iter().find(|x| x.table == table).map_or(0, |x| x.rows)
+    }
+
+    /// Flushes everything buffered and hands back the connection
+    pub fn into_connection(mut self) -> Result<KdbConnection<R, W>, String> {
+        self.flush()?;
+        Ok(self.connection)
+    }
+
+    /// Sends a table's buffered rows, which stay buffered if sending fails so they can be retried
+    fn flush_table(&mut self, index: usize) -> Result<(), String> {
+        let buffer = &mut self.buffers[index];
+        if buffer.rows == 0 {
+            return Ok(());
+        }
+        let columns = std::mem::take(&mut buffer.columns);
+        let table = buffer.table.clone();
+        match self.send(&table, columns) {
+            Ok(()) => {
+                self.buffers[index].rows = 0;
+                Ok(())
+            }
+            Err((error, columns)) => {
+                self.buffers[index].columns = columns;
+                Err(error)
+            }
+        }
+    }
+
+    /// Sends `columns` to the tickerplant, handing them back with the error if that fails
+    fn send(&mut self, table: &str, columns: Vec<Payload>) -> Result<(), (String, Vec<Payload>)> {
+        let message = Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::Symbol(KdbString::from(self.config.function.as_str())),
+            Payload::Symbol(KdbString::from(table)),
+            Payload::List(VectorAttribute::NoAttribute, columns),
+        ]);
+        self.connection.send_async(&message).map_err(|error| match message {
+            Payload::List(_, mut x) => match x.pop() {
+                Some(Payload::List(_, columns)) => (error, columns),
+                _ => (error, Vec::new()),
+            },
+            _ => (error, Vec::new()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::time::Duration;
+    use crate::{decode_message, message_size, KdbConnection};
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::publish::{Publisher, PublisherConfig};
+    use crate::fixtures::{sym, symbol_vector, update};
+
+    fn messages(mut bytes: &[u8]) -> Vec<Payload> {
+        let mut ret_val = Vec::new();
+        while !bytes.is_empty() {
+            let mut header = [0u8; 8];
+            header.copy_from_slice(&bytes[0..8]);
+            let len = message_size(&header, u32::MAX).unwrap();
+            ret_val.push(decode_message(bytes[..len].to_vec(), None).unwrap());
+            bytes = &bytes[len..];
+        }
+        ret_val
+    }
+
+    #[test]
+    pub fn test_batches_rows_into_columns() {
+        let config = PublisherConfig { max_rows: 2, max_delay: Duration::from_secs(60), ..PublisherConfig::default() };
+        let mut publisher = Publisher::new(KdbConnection::from_streams(Cursor::new(Vec::new()), Vec::new()), config);
+        publisher.publish("trade", vec![sym("a"), Payload::Float(1.5)]).unwrap();
+        publisher.publish("quote", vec![sym("b")]).unwrap();
+        assert!(publisher.publish("trade", vec![Payload::Long(1), Payload::Float(1.5)]).is_err());
+        publisher.publish("trade", vec![sym("c"), Payload::Float(2.5)]).unwrap();
+        assert_eq!(publisher.pending("trade"), 0);
+        assert_eq!(publisher.pending("quote"), 1);
+
+        let connection = publisher.into_connection().unwrap();
+        assert_eq!(messages(&connection.tcp_connection_write), vec![
+            update(".u.upd", "trade", vec![symbol_vector(&["a", "c"]),
+                              Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, 2.5])]),
+            update(".u.upd", "quote", vec![symbol_vector(&["b"])]),
+        ]);
+    }
+
+    #[test]
+    pub fn test_rejected_row_leaves_batch() {
+        let mut publisher = Publisher::new(KdbConnection::from_streams(Cursor::new(Vec::new()), Vec::new()), PublisherConfig::default());
+        publisher.publish("trade", vec![sym("a"), Payload::Char('x')]).unwrap();
+        assert!(publisher.publish("trade", vec![sym("b"), Payload::Char('\u{100}')]).is_err());
+        assert_eq!(publisher.pending("trade"), 1);
+
+        let connection = publisher.into_connection().unwrap();
+        assert_eq!(messages(&connection.tcp_connection_write), vec![update(".u.upd", "trade", vec![symbol_vector(&["a"]),
+            Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("x"))])]);
+    }
+
+    struct Flaky {
+        fail: bool,
+        written: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            match self.fail {
+                true => Err(std::io::Error::other("Broken pipe")),
+                false => self.written.write(buf),
+            }
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_failed_flush_keeps_rows() {
+        let config = PublisherConfig { max_rows: 2, max_delay: Duration::from_secs(60), ..PublisherConfig::default() };
+        let connection = KdbConnection::from_streams(Cursor::new(Vec::new()), Flaky { fail: true, written: Vec::new() });
+        let mut publisher = Publisher::new(connection, config);
+        publisher.publish("trade", vec![sym("a")]).unwrap();
+        publisher.publish("trade", vec![sym("b")]).unwrap();
+        assert_eq!(publisher.pending("trade"), 2);
+        assert!(publisher.flush().is_err());
+        assert_eq!(publisher.pending("trade"), 2);
+
+        publisher.connection.tcp_connection_write.fail = false;
+        publisher.flush().unwrap();
+        assert_eq!(publisher.pending("trade"), 0);
+        assert_eq!(messages(&publisher.connection.tcp_connection_write.written), vec![
+            update(".u.upd", "trade", vec![symbol_vector(&["a", "b"])])]);
+    }
+
+    #[test]
+    pub fn test_compressed_batches() {
+        let config = PublisherConfig { max_rows: 1000, compress: true, ..PublisherConfig::default() };
+        let mut publisher = Publisher::new(KdbConnection::from_streams(Cursor::new(Vec::new()), Vec::new()), config);
+        for i in 0..1000 {
+            publisher.publish("trade", vec![sym("AAPL"), Payload::Long(i % 4)]).unwrap();
+        }
+        let connection = publisher.into_connection().unwrap();
+        assert_eq!(connection.tcp_connection_write[2], 1);
+        assert_eq!(messages(&connection.tcp_connection_write), vec![update(".u.upd", "trade", vec![
+            Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("AAPL"); 1000]),
+            Payload::LongVector(VectorAttribute::NoAttribute, (0..1000).map(|x| x % 4).collect())])]);
+    }
+}
@@ -0,0 +1,266 @@
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use crate::KdbConnection;
+use crate::codec::{KdbRequest, Payload};
+
+/// Lifecycle of the connection held by a `ReconnectingConnection`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+/// Exponential backoff applied between failed connection attempts.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Backoff {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: u32,
+    /// Give up after this many consecutive failed attempts, `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    /// Delay to wait after the given (zero based) failed attempt
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.initial_delay.checked_mul(factor).map_or(self.max_delay, |delay| delay.min(self.max_delay))
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+/// Counters describing how well the underlying connection has behaved so far.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionHealth {
+    pub connected_since: Option<Instant>,
+    pub reconnects: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+type StateListener = Box<dyn FnMut(ConnectionState, ConnectionState) + Send>;
+
+/// A `KdbConnection` that remembers how it was opened and transparently re-opens itself.
+///
+/// Any failed query drops the underlying socket and the next call reconnects, replaying the
+/// init queries (e.g. subscriptions). The failed query itself is not retried as q may already
+/// have executed it.
+pub struct ReconnectingConnection {
+    address: String,
+    user: String,
+    password: String,
+    backoff: Backoff,
+    init_queries: Vec<String>,
+    connection: Option<KdbConnection<TcpStream, TcpStream>>,
+    state: ConnectionState,
+    health: ConnectionHealth,
+    ever_connected: bool,
+    listeners: Vec<StateListener>,
+}
+
+impl ReconnectingConnection {
+    /// Creates the wrapper without connecting, call `ensure_connected` to connect eagerly
+    pub fn new(address: &str, user: &str, password: &str) -> ReconnectingConnection {
+        ReconnectingConnection {
+            address: address.to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            backoff: Backoff::default(),
+            init_queries: Vec::new(),
+            connection: None,
+            state: ConnectionState::Disconnected,
+            health: ConnectionHealth::default(),
+            ever_connected: false,
+            listeners: Vec::new(),
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Query run after every successful (re)connect, before any user query
+    pub fn with_init_query(mut self, query: &str) -> Self {
+        self.init_queries.push(query.to_string());
+        self
+    }
+
+    /// Registers a callback invoked with `(old, new)` whenever the state changes
+    pub fn on_state_change<F: FnMut(ConnectionState, ConnectionState) + Send + 'static>(&mut self, listener: F) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.is_some()
+    }
+
+    /// Connects if needed, retrying with backoff until connected or `max_attempts` is exhausted
+    pub fn ensure_connected(&mut self) -> Result<(), String> {
+        let mut attempt = 0;
+        while self.connection.is_none() {
+            self.set_state(ConnectionState::Connecting);
+            match self.open() {
+                Ok(connection) => {
+                    if self.ever_connected {
+                        self.health.reconnects += 1;
+                    }
+                    self.ever_connected = true;
+                    self.connection = Some(connection);
+                    self.health.connected_since = Some(Instant::now());
+                    self.health.consecutive_failures = 0;
+                    self.set_state(ConnectionState::Connected);
+                }
+                Err(error) => {
+                    self.health.consecutive_failures += 1;
+                    self.health.last_error = Some(error.clone());
+                    self.set_state(ConnectionState::Disconnected);
+                    if self.backoff.max_attempts.is_some_and(|max| attempt + 1 >= max) {
+                        return Err(format!("Failed to connect to {} after {} attempts: {}", self.address, attempt + 1, error));
+                    }
+                    std::thread::sleep(self.backoff.delay(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn query(&mut self, msg: KdbRequest) -> Result<Payload, String> {
+        self.ensure_connected()?;
+        let result = self.connection.as_mut().map_or_else(|| Err(String::from("Not connected")), |x| x.query(msg));
+        if let Err(error) = &result {
+            self.health.last_error = Some(error.clone());
+            self.disconnect();
+        }
+        result
+    }
+
+    /// Pipelined version of `query`, see `KdbConnection::pipeline`
+    pub fn pipeline(&mut self, msgs: &[KdbRequest]) -> Result<Vec<Payload>, String> {
+        self.ensure_connected()?;
+        let result = self.connection.as_mut().map_or_else(|| Err(String::from("Not connected")), |x| x.pipeline(msgs));
+        if let Err(error) = &result {
+            self.health.last_error = Some(error.clone());
+            self.disconnect();
+        }
+        result
+    }
+
+    /// Drops the underlying socket, the next query will reconnect
+    pub fn disconnect(&mut self) {
+        self.connection = None;
+        self.health.connected_since = None;
+        self.set_state(ConnectionState::Disconnected);
+    }
+
+    fn open(&self) -> Result<KdbConnection<TcpStream, TcpStream>, String> {
+        let mut connection = KdbConnection::new(self.address.as_str()).map_err(|x| x.to_string())?;
+        connection.connect(&self.user, &self.password).map_err(|x| x.to_string())?;
+        for query in &self.init_queries {
+            if let Payload::Error(error) = connection.query(KdbRequest::new(query))? {
+                return Err(format!("Init query {} failed: {}", query, error));
+            }
+        }
+        Ok(connection)
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state != state {
+            let old = self.state;
+            self.state = state;
+            for listener in self.listeners.iter_mut() {
+                listener(old, state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reconnect::{Backoff, ReconnectingConnection, ConnectionState};
+    use crate::codec::{KdbRequest, KdbString, Payload};
+    use std::net::TcpListener;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Accepts `sessions` connections, each answering a single query with the char vector "ok"
+    fn serve(listener: TcpListener, sessions: usize) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(sessions) {
+                let mut stream = stream.unwrap();
+                let mut byte = [0u8; 1];
+                while { stream.read_exact(&mut byte).unwrap(); byte[0] != 0 } {}
+                stream.write_all(&[3]).unwrap();
+                let mut header = [0u8; 8];
+                stream.read_exact(&mut header).unwrap();
+                let mut body = vec![0u8; u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize - 8];
+                stream.read_exact(&mut body).unwrap();
+                stream.write_all(&hex::decode("01020000100000000a00020000006f6b").unwrap()).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    pub fn test_backoff_delay() {
+        let backoff = Backoff { initial_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50), multiplier: 2, max_attempts: None };
+        assert_eq!(backoff.delay(0), Duration::from_millis(10));
+        assert_eq!(backoff.delay(2), Duration::from_millis(40));
+        assert_eq!(backoff.delay(3), Duration::from_millis(50));
+        assert_eq!(backoff.delay(100), Duration::from_millis(50));
+    }
+
+    #[test]
+    pub fn test_gives_up_after_max_attempts() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let mut connection = ReconnectingConnection::new(&address, "user", "pass")
+            .with_backoff(Backoff { initial_delay: Duration::from_millis(1), max_delay: Duration::from_millis(1), multiplier: 2, max_attempts: Some(3) });
+        assert!(connection.ensure_connected().is_err());
+        assert_eq!(connection.state(), ConnectionState::Disconnected);
+        assert_eq!(connection.health().consecutive_failures, 3);
+    }
+
+    #[test]
+    pub fn test_reconnects_after_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = serve(listener, 2);
+
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let mut connection = ReconnectingConnection::new(&address, "user", "pass");
+        let recorded = states.clone();
+        connection.on_state_change(move |_, new| recorded.lock().unwrap().push(new));
+
+        let expected = Payload::CharVector(crate::codec::VectorAttribute::NoAttribute, KdbString::from("ok"));
+        assert_eq!(connection.query(KdbRequest::new("a")).unwrap(), expected);
+        // The server hangs up after one query, so the next one fails and the one after reconnects
+        assert!(connection.query(KdbRequest::new("b")).is_err());
+        assert!(!connection.is_connected());
+        assert_eq!(connection.query(KdbRequest::new("c")).unwrap(), expected);
+        assert_eq!(connection.health().reconnects, 1);
+        server.join().unwrap();
+
+        assert_eq!(*states.lock().unwrap(), vec![ConnectionState::Connecting, ConnectionState::Connected, ConnectionState::Disconnected,
+                                                 ConnectionState::Connecting, ConnectionState::Connected]);
+    }
+}
@@ -0,0 +1,186 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::codec::{Payload, SynchronisationType};
+use crate::decode_message;
+
+/// Start of every recording file, the last byte is the format version
+const MAGIC: [u8; 8] = *b"IKDBREC\x01";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Sent = 0,
+    Received = 1,
+}
+
+impl TryFrom<u8> for Direction {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Direction::Sent),
+            1 => Ok(Direction::Received),
+            _ => Err(format!("Unknown direction {}.", value))
+        }
+    }
+}
+
+/// One IPC message exactly as it went over the wire, header and any compression included.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RecordedMessage {
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    pub bytes: Vec<u8>,
+}
+
+impl RecordedMessage {
+    pub fn message_type(&self) -> Result<SynchronisationType, String> {
+        SynchronisationType::try_from(*self.bytes.get(1).ok_or_else(|| String::from("Message too short"))?)
+    }
+
+    pub fn decode(&self) -> Result<Payload, String> {
+        if self.bytes.len() < 9 {
+            return Err(String::from("Message too short"));
+        }
+        decode_message(self.bytes.clone(), None)
+    }
+}
+
+/// Appends messages to a recording, one entry per message:
+/// `timestamp (u64 ns since epoch) | direction (u8) | length (u32) | message bytes`, little endian.
+pub struct SessionRecorder<W: Write> {
+    writer: W,
+}
+
+impl SessionRecorder<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<SessionRecorder<BufWriter<File>>> {
+        SessionRecorder::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> SessionRecorder<W> {
+    /// Writes the file header, `writer` should be empty
+    pub fn new(mut writer: W) -> std::io::Result<SessionRecorder<W>> {
+        writer.write_all(&MAGIC)?;
+        Ok(SessionRecorder { writer })
+    }
+
+    /// Records a message and flushes, so a crash loses at most the message being written
+    pub fn record(&mut self, direction: Direction, bytes: &[u8]) -> std::io::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        self.writer.write_all(&timestamp.to_le_bytes())?;
+        self.writer.write_all(&[direction as u8])?;
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(bytes)?;
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Iterates the messages of a recording made by `SessionRecorder`.
+pub struct SessionReader<R: Read> {
+    reader: R,
+}
+
+impl SessionReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SessionReader<BufReader<File>>, String> {
+        SessionReader::new(BufReader::new(File::open(path).map_err(|x| x.to_string())?))
+    }
+}
+
+impl<R: Read> SessionReader<R> {
+    pub fn new(mut reader: R) -> Result<SessionReader<R>, String> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|x| x.to_string())?;
+        if magic != MAGIC {
+            return Err(String::from("Not an iron_kdb session recording"));
+        }
+        Ok(SessionReader { reader })
+    }
+
+    fn read_message(&mut self) -> Result<Option<RecordedMessage>, String> {
+        let mut entry_header = [0u8; 13];
+        match self.reader.read(&mut entry_header[..1]).map_err(|x| x.to_string())? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut entry_header[1..]).map_err(|x| x.to_string())?,
+        }
+        let mut timestamp = [0u8; 8];
+        timestamp.copy_from_slice(&entry_header[0..8]);
+        let direction = Direction::try_from(entry_header[8])?;
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&entry_header[9..13]);
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes).map_err(|x| x.to_string())?;
+        Ok(Some(RecordedMessage { timestamp: UNIX_EPOCH + Duration::from_nanos(u64::from_le_bytes(timestamp)), direction, bytes }))
+    }
+}
+
+impl<R: Read> Iterator for SessionReader<R> {
+    type Item = Result<RecordedMessage, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_message().transpose()
+    }
+}
+
+/// Pairs each sync request with the response that followed it, e.g. to script a `MockServer`
+pub fn request_responses(messages: &[RecordedMessage]) -> Result<Vec<(Payload, Payload)>, String> {
+    let mut pending = std::collections::VecDeque::new();
+    let mut ret_val = Vec::new();
+    for message in messages {
+        match (message.direction, message.message_type()?) {
+            (Direction::Sent, SynchronisationType::Sync) => pending.push_back(message.decode()?),
+            (Direction::Received, SynchronisationType::Response) => {
+                let request = pending.pop_front().ok_or_else(|| String::from("Response without a request"))?;
+                ret_val.push((request, message.decode()?));
+            }
+            _ => {}
+        }
+    }
+    Ok(ret_val)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Cursor;
+    use crate::KdbConnection;
+    use crate::codec::{encode_message, KdbRequest, Payload, SynchronisationType};
+    use crate::mock::MockServer;
+    use crate::record::{Direction, SessionReader};
+
+    #[test]
+    pub fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("iron_kdb_record_{}.bin", std::process::id()));
+        let response = encode_message(SynchronisationType::Response, &Payload::Long(3));
+        let mut connection = KdbConnection::from_streams(Cursor::new(response.clone()), Vec::new());
+        connection.record_to(File::create(&path).unwrap()).unwrap();
+        assert_eq!(connection.query(KdbRequest::new("1+2")).unwrap(), Payload::Long(3));
+        connection.send_async(&Payload::Long(4)).unwrap();
+        connection.stop_recording();
+
+        let messages = SessionReader::open(&path).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(messages.iter().map(|x| x.direction).collect::<Vec<_>>(), vec![Direction::Sent, Direction::Received, Direction::Sent]);
+        assert_eq!(messages[0].bytes, KdbRequest::new("1+2").to_bytes());
+        assert_eq!(messages[1].bytes, response);
+        assert_eq!(messages[2].decode().unwrap(), Payload::Long(4));
+        assert!(messages[0].timestamp <= messages[1].timestamp);
+
+        let mock = MockServer::start().unwrap();
+        mock.replay(&messages).unwrap();
+        let mut connection = KdbConnection::new(mock.address()).unwrap();
+        connection.connect("user", "pass").unwrap();
+        assert_eq!(connection.query(KdbRequest::new("1+2")).unwrap(), Payload::Long(3));
+    }
+
+    #[test]
+    pub fn test_rejects_other_files() {
+        assert!(SessionReader::new(Cursor::new(b"not a recording".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use crate::KdbConnection;
+use crate::codec::{Payload, SynchronisationType};
+
+/// Longest `user:password` accepted during the handshake
+const MAX_CREDENTIALS_LEN: usize = 1024;
+/// Longest message accepted from a client unless set with `KdbServer::with_max_message_size`
+const DEFAULT_MAX_MESSAGE_SIZE: u32 = 256 << 20;
+/// Highest IPC capability we speak, 3 adds compression and timestamps/timespans
+const CAPABILITY: u8 = 3;
+
+/// Decides whether a client may connect.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, user: &str, password: &str) -> bool;
+}
+
+/// Accepts every client, like a q process without `-u`/`-U`.
+pub struct AllowAll;
+
+impl Authenticator for AllowAll {
+    fn authenticate(&self, _user: &str, _password: &str) -> bool {
+        true
+    }
+}
+
+impl<F: Fn(&str, &str) -> bool + Send + Sync> Authenticator for F {
+    fn authenticate(&self, user: &str, password: &str) -> bool {
+        self(user, password)
+    }
+}
+
+/// The client a request came from, the equivalent of q's `.z.u` and `.z.a`.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user: String,
+    pub peer: SocketAddr,
+    stream: Arc<TcpStream>,
+}
+
+impl Session {
+    /// Closes the client's connection, the equivalent of `hclose .z.w`
+    pub fn disconnect(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Answers requests sent to a `KdbServer`. Query strings arrive as a `CharVector` and function
+/// calls as a `List`. For sync requests the result is sent back, errors as a q error (`'msg`).
+pub trait Handler: Send + Sync {
+    fn handle(&self, session: &Session, message_type: SynchronisationType, request: Payload) -> Result<Payload, String>;
+}
+
+impl<F: Fn(&Session, SynchronisationType, Payload) -> Result<Payload, String> + Send + Sync> Handler for F {
+    fn handle(&self, session: &Session, message_type: SynchronisationType, request: Payload) -> Result<Payload, String> {
+        self(session, message_type, request)
+    }
+}
+
+/// Listens for q clients (`hopen`) and serves each connection on its own thread.
+pub struct KdbServer {
+    listener: TcpListener,
+    authenticator: Arc<dyn Authenticator>,
+    handler: Arc<dyn Handler>,
+    max_message_size: u32,
+}
+
+impl KdbServer {
+    pub fn bind<T: ToSocketAddrs, H: Handler + 'static>(address: T, handler: H) -> std::io::Result<KdbServer> {
+        Ok(KdbServer { listener: TcpListener::bind(address)?, authenticator: Arc::new(AllowAll), handler: Arc::new(handler),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE })
+    }
+
+    pub fn with_authenticator<A: Authenticator + 'static>(mut self, authenticator: A) -> Self {
+        self.authenticator = Arc::new(authenticator);
+        self
+    }
+
+    /// Disconnects clients sending a message longer than `size` bytes, compressed or not, before
+    /// allocating for it. 256 MiB by default.
+    pub fn with_max_message_size(mut self, size: u32) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections forever on the current thread
+    pub fn serve(&self) -> std::io::Result<()> {
+        self.serve_until(&AtomicBool::new(false))
+    }
+
+    /// Serves on a background thread until the returned handle is shut down or dropped
+    pub fn spawn(self) -> std::io::Result<ServerHandle> {
+        let address = self.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let flag = stopped.clone();
+        let thread = std::thread::spawn(move || self.serve_until(&flag));
+        Ok(ServerHandle { address, stopped, thread: Some(thread) })
+    }
+
+    fn serve_until(&self, stopped: &AtomicBool) -> std::io::Result<()> {
+        for stream in self.listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let authenticator = self.authenticator.clone();
+            let handler = self.handler.clone();
+            let max_message_size = self.max_message_size;
+            std::thread::spawn(move || serve_connection(stream, authenticator.as_ref(), handler, max_message_size));
+        }
+        Ok(())
+    }
+}
+
+/// Stops a spawned `KdbServer` from accepting new connections.
+pub struct ServerHandle {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting, connections already open are served until the client hangs up
+    pub fn shutdown(mut self) -> std::io::Result<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> std::io::Result<()> {
+        if let Some(thread) = self.thread.take() {
+            self.stopped.store(true, Ordering::SeqCst);
+            // Wake the blocking accept
+            let _ = TcpStream::connect(self.address);
+            return thread.join().map_err(|_| std::io::Error::other("Server thread panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Performs the server side of the handshake, returning the authenticated user
+fn accept<R: Read, W: Write>(read: &mut R, write: &mut W, authenticator: &dyn Authenticator) -> Result<String, String> {
+    let mut credentials = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        read.read_exact(&mut byte).map_err(|x| x.to_string())?;
+        if byte[0] == 0 {
+            break;
+        }
+        if credentials.len() == MAX_CREDENTIALS_LEN {
+            return Err(String::from("Credentials too long"));
+        }
+        credentials.push(byte[0]);
+    }
+    // Clients older than kdb+ 2.6 send no capability byte
+    let capability = match credentials.last() {
+        Some(x) if *x < b' ' => credentials.pop().unwrap_or(0),
+        _ => 0,
+    };
+    let credentials = String::from_utf8_lossy(&credentials).into_owned();
+    let (user, password) = credentials.split_once(':').unwrap_or((credentials.as_str(), ""));
+    if !authenticator.authenticate(user, password) {
+        return Err(format!("Access denied for {}", user));
+    }
+    write.write_all(&[capability.min(CAPABILITY)]).map_err(|x| x.to_string())?;
+    Ok(user.to_string())
+}
+
+fn serve_connection(mut stream: TcpStream, authenticator: &dyn Authenticator, handler: Arc<dyn Handler>, max_message_size: u32) -> Result<(), String> {
+    let peer = stream.peer_addr().map_err(|x| x.to_string())?;
+    let mut read = stream.try_clone().map_err(|x| x.to_string())?;
+    // Dropping the stream without replying is how q rejects a client
+    let user = accept(&mut read, &mut stream, authenticator)?;
+    let session = Session { user, peer, stream: Arc::new(stream.try_clone().map_err(|x| x.to_string())?) };
+
+    let mut connection = KdbConnection::from_streams(read, stream);
+    connection.set_max_message_size(max_message_size);
+    connection.set_request_handler(move |message_type, request| handler.handle(&session, message_type, request));
+    loop {
+        connection.process_incoming()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::KdbConnection;
+    use crate::codec::{KdbRequest, KdbString, Payload, SynchronisationType, VectorAttribute};
+    use crate::server::{KdbServer, Session};
+
+    fn handler(session: &Session, _: SynchronisationType, request: Payload) -> Result<Payload, String> {
+        match request {
+            Payload::CharVector(_, query) if query == "user" => Ok(Payload::Symbol(KdbString::from(session.user.as_str()))),
+            Payload::CharVector(_, query) => Err(format!("{}", query)),
+            Payload::List(_, mut args) => Ok(args.pop().unwrap_or(Payload::Nil)),
+            _ => Err(String::from("type")),
+        }
+    }
+
+    #[test]
+    pub fn test_answers_queries() {
+        let server = KdbServer::bind("127.0.0.1:0", handler).unwrap().spawn().unwrap();
+        let mut connection = KdbConnection::new(server.local_addr()).unwrap();
+        connection.connect("alice", "secret").unwrap();
+
+        assert_eq!(connection.query(KdbRequest::new("user")).unwrap(), Payload::Symbol(KdbString::from("alice")));
+        assert_eq!(connection.query(KdbRequest::new("nope")).unwrap(), Payload::error("nope"));
+        let call = Payload::List(VectorAttribute::NoAttribute, vec![Payload::Symbol(KdbString::from("f")), Payload::Long(42)]);
+        connection.send_async(&call).unwrap();
+        assert_eq!(connection.call(&call).unwrap(), Payload::Long(42));
+        server.shutdown().unwrap();
+    }
+
+    #[test]
+    pub fn test_rejects_large_messages() {
+        let server = KdbServer::bind("127.0.0.1:0", handler).unwrap().with_max_message_size(64).spawn().unwrap();
+        let mut connection = KdbConnection::new(server.local_addr()).unwrap();
+        connection.connect("alice", "secret").unwrap();
+
+        assert!(connection.query(KdbRequest::new("user")).is_ok());
+        assert!(connection.query(KdbRequest::new(&"x".repeat(64))).is_err());
+    }
+
+    #[test]
+    pub fn test_rejects_bad_credentials() {
+        let server = KdbServer::bind("127.0.0.1:0", handler).unwrap()
+            .with_authenticator(|user: &str, password: &str| user == "alice" && password == "secret")
+            .spawn().unwrap();
+        let mut connection = KdbConnection::new(server.local_addr()).unwrap();
+        assert!(connection.connect("alice", "wrong").is_err());
+        let mut connection = KdbConnection::new(server.local_addr()).unwrap();
+        assert!(connection.connect("alice", "secret").is_ok());
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use memmap2::Mmap;
+use crate::codec::{KdbString, Payload, VectorAttribute};
+use crate::builder::{table, table_parts};
+use crate::compressed::{is_compressed, CompressedFile};
+use crate::file::{vector_file_parts, vector_from_parts, VECTOR_HEADER, VECTOR_HEADER_LEN};
+
+/// Type of enumerated columns, always enumerated against the database's `sym` file
+pub(crate) const ENUM_TYPE: i8 = 20;
+/// Nested columns have type 77 plus the type of their items, the items live in `<column>#`
+pub(crate) const NESTED_TYPE: i8 = 77;
+
+/// Reads the symbols enumerated columns of the database at `root` index into
+pub fn read_sym<P: AsRef<Path>>(root: P) -> Result<Vec<KdbString>, String> {
+    match Payload::read_file(root.as_ref().join("sym"))? {
+        Payload::SymbolVector(_, x) => Ok(x),
+        x => Err(format!("sym file holds type {} instead of symbols", x.type_byte())),
+    }
+}
+
+/// A table saved one file per column, with the column order in `.d`, e.g. `` `:db/t/ set .Q.en[`:db] t ``.
+pub struct SplayedTable {
+    directory: PathBuf,
+    columns: Vec<String>,
+}
+
+impl SplayedTable {
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<SplayedTable, String> {
+        let columns = match Payload::read_file(directory.as_ref().join(".d"))? {
+            Payload::SymbolVector(_, x) => x.into_iter().map(|x| x.to_string()).collect(),
+            x => return Err(format!(".d holds type {} instead of symbols", x.type_byte())),
+        };
+        Ok(SplayedTable { directory: directory.as_ref().to_path_buf(), columns })
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Reads every column into a `Payload::Table`
+    pub fn read(&self, sym: &[KdbString]) -> Result<Payload, String> {
+        let columns: Vec<&str> = self.columns.iter().map(String::as_str).collect();
+        self.read_columns(&columns, sym)
+    }
+
+    /// Reads the given columns into a `Payload::Table`, in the order given
+    pub fn read_columns(&self, columns: &[&str], sym: &[KdbString]) -> Result<Payload, String> {
+        let values = columns.iter().map(|x| self.read_column(x, sym)).collect::<Result<Vec<_>, _>>()?;
+        Ok(table(columns, values))
+    }
+
+    /// Reads one column, resolving enumerated symbols against `sym`
+    pub fn read_column(&self, column: &str, sym: &[KdbString]) -> Result<Payload, String> {
+        self.read_column_range(column, sym, None)
+    }
+
+    /// Reads only `rows` of a column, the rest of the file is never touched
+    pub fn read_column_rows(&self, column: &str, sym: &[KdbString], rows: Range<usize>) -> Result<Payload, String> {
+        self.read_column_range(column, sym, Some(rows))
+    }
+
+    /// Rows in the table, the length of its first column
+    pub fn row_count(&self) -> Result<usize, String> {
+        let column = match self.columns.first() {
+            Some(x) => self.column_path(x)?,
+            None => return Ok(0),
+        };
+        let file = read_column_file(&column)?;
+        match file.vector_header().map_err(|x| format!("{}: {}", column.display(), x))? {
+            Some((_, _, count)) => Ok(count),
+            // Every vector has its count after the type and attribute bytes
+            None => file.bytes(4..8).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]) as usize)
+                .map_err(|x| format!("{}: {}", column.display(), x)),
+        }
+    }
+
+    fn column_path(&self, column: &str) -> Result<PathBuf, String> {
+        match self.columns.iter().any(|x| x == column) {
+            true => Ok(self.directory.join(column)),
+            false => Err(format!("{} has no column {}", self.directory.display(), column)),
+        }
+    }
+
+    fn read_column_range(&self, column: &str, sym: &[KdbString], rows: Option<Range<usize>>) -> Result<Payload, String> {
+        let path = self.column_path(column)?;
+        let at_path = |x: String| format!("{}: {}", path.display(), x);
+        let incomplete = || format!("{}: Incomplete q data file", path.display());
+        let file = read_column_file(&path)?;
+        let (type_byte, attribute, count) = match file.vector_header().map_err(at_path)? {
+            Some(x) => x,
+            None => {
+                let payload = Payload::from_file_bytes(&file.all().map_err(at_path)?).map_err(at_path)?;
+                return match rows {
+                    Some(rows) if rows.start > rows.end || rows.end > payload.count() => {
+                        Err(format!("{}: rows {:?} out of range of {}", path.display(), rows, payload.count()))
+                    }
+                    Some(rows) => payload.filter_rows(&(0..rows.end).map(|x| rows.contains(&x)).collect::<Vec<_>>()),
+                    None => Ok(payload),
+                };
+            }
+        };
+        let rows = rows.unwrap_or(0..count);
+        if rows.start > rows.end || rows.end > count {
+            return Err(format!("{}: rows {:?} out of range of {}", path.display(), rows, count));
+        }
+        match type_byte {
+            ENUM_TYPE => {
+                let data = file.items(8, rows.clone()).map_err(at_path)?;
+                let indices = long_values(&data, rows.len()).ok_or_else(incomplete)?;
+                let symbols = indices.map(|x| sym.get(x as usize).cloned().ok_or_else(|| format!("{}: enumeration index {} out of range of sym", path.display(), x)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Payload::SymbolVector(VectorAttribute::try_from(attribute)?, symbols))
+            }
+            x if x >= NESTED_TYPE => {
+                let items = read_column_file(&self.directory.join(format!("{}#", column)))?;
+                let (item_type, _, item_count) = items.vector_header().map_err(at_path)?
+                    .ok_or_else(|| format!("{}# isn't a vector file", path.display()))?;
+                if item_type != x - NESTED_TYPE {
+                    return Err(format!("{}# holds type {} instead of {}", path.display(), item_type, x - NESTED_TYPE));
+                }
+                let width = Payload::vector_width(item_type).ok_or_else(|| format!("{}: Can't read nested type {}", path.display(), x))?;
+                // Each row holds the end of its items, the first row starts at 0
+                let offsets = file.items(8, rows.start.saturating_sub(1)..rows.end).map_err(at_path)?;
+                let mut offsets = long_values(&offsets, offsets.len() / 8).ok_or_else(incomplete)?;
+                let mut start = match rows.start {
+                    0 => 0,
+                    _ => offsets.next().ok_or_else(incomplete)? as usize,
+                };
+                let first = start;
+                let mut bounds = Vec::with_capacity(rows.len());
+                for end in offsets {
+                    let end = end as usize;
+                    if end < start || end > item_count {
+                        return Err(format!("{}: item offset {} out of range", path.display(), end));
+                    }
+                    bounds.push(start..end);
+                    start = end;
+                }
+                // Only the items of the rows read, decompressing just the blocks holding them
+                let item_data = items.items(width, first..start).map_err(at_path)?;
+                let values = bounds.into_iter().map(|x| vector_from_parts(item_type, 0, x.len(), &item_data[(x.start - first) * width..]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Payload::List(VectorAttribute::try_from(attribute)?, values))
+            }
+            _ => {
+                let width = Payload::vector_width(type_byte).ok_or_else(|| format!("{}: Can't read type {}", path.display(), type_byte))?;
+                let data = file.items(width, rows.clone()).map_err(at_path)?;
+                vector_from_parts(type_byte, attribute, rows.len(), &data).map_err(at_path)
+            }
+        }
+    }
+}
+
+/// Reads a whole splayed table, resolving enumerations against the `sym` file in `root`
+pub fn read_splayed<P: AsRef<Path>, Q: AsRef<Path>>(directory: P, root: Q) -> Result<Payload, String> {
+    SplayedTable::open(directory)?.read(&read_sym(root)?)
+}
+
+/// Indices of `symbols` in the `sym` file of the database at `root`, appending the ones it lacks
+/// like `.Q.en` does. The file is only rewritten when new symbols were added.
+pub fn enumerate<P: AsRef<Path>>(root: P, symbols: &[&KdbString]) -> Result<Vec<u64>, String> {
+    let root = root.as_ref();
+    let mut sym = match root.join("sym").exists() {
+        true => read_sym(root)?,
+        false => Vec::new(),
+    };
+    let existing = sym.len();
+    let mut index: HashMap<KdbString, u64> = sym.iter().enumerate().map(|(i, x)| (x.clone(), i as u64)).collect();
+    let mut indices = Vec::with_capacity(symbols.len());
+    for symbol in symbols {
+        let next = index.len() as u64;
+        let position = *index.entry((*symbol).clone()).or_insert_with(|| {
+            sym.push((*symbol).clone());
+            next
+        });
+        indices.push(position);
+    }
+    if sym.len() > existing || !root.join("sym").exists() {
+        std::fs::create_dir_all(root).map_err(|x| format!("{}: {}", root.display(), x))?;
+        Payload::SymbolVector(VectorAttribute::NoAttribute, sym).write_file(root.join("sym"))?;
+    }
+    Ok(indices)
+}
+
+/// Writes a table the way `` `:directory/ set .Q.en[`:root] t `` does: one file per column, symbol
+/// columns enumerated against `root/sym` and `.d` written last. Attributes are written as given,
+/// so `s#` and `p#` columns must already be sorted or grouped.
+pub fn write_splayed<P: AsRef<Path>, Q: AsRef<Path>>(directory: P, root: Q, table: &Payload) -> Result<(), String> {
+    let directory = directory.as_ref();
+    let table = table.plain_symbols();
+    // Check every column's type, rows and name before sym or any column file is touched, so a bad
+    // table leaves nothing half written
+    table.check()?;
+    let (names, values) = table_parts(&table)?;
+    let mut paths = Vec::with_capacity(names.len());
+    for (name, value) in names.iter().zip(values) {
+        if let Payload::List(_, items) = value {
+            if nested_type(items).is_none() && items.iter().any(|x| matches!(x, Payload::SymbolVector(_, _))) {
+                return Err(format!("Can't write column {} of symbol lists", name));
+            }
+        }
+        paths.push(directory.join(name.to_str().map_err(|_| format!("Column name {:?} isn't UTF-8", name))?));
+    }
+    std::fs::create_dir_all(directory).map_err(|x| format!("{}: {}", directory.display(), x))?;
+    // Enumerate every symbol column at once so sym is rewritten at most once
+    let symbols: Vec<&KdbString> = values.iter().flat_map(|x| match x {
+        Payload::SymbolVector(_, x) => x.iter().collect(),
+        _ => Vec::new(),
+    }).collect();
+    let mut indices = enumerate(root, &symbols)?.into_iter();
+    for ((name, value), path) in names.iter().zip(values).zip(paths) {
+        let files = match value {
+            Payload::SymbolVector(attribute, x) => {
+                let data: Vec<u64> = indices.by_ref().take(x.len()).collect();
+                vec![(path, long_file(ENUM_TYPE, *attribute, &data))]
+            }
+            Payload::List(attribute, items) if nested_type(items).is_some() => {
+                let mut all = items[0].clone();
+                let mut ends = Vec::with_capacity(items.len());
+                let mut end = all.count() as u64;
+                ends.push(end);
+                for item in &items[1..] {
+                    end += item.count() as u64;
+                    ends.push(end);
+                    all.append(item.clone())?;
+                }
+                let type_byte = NESTED_TYPE + all.type_byte();
+                vec![(path, long_file(type_byte, *attribute, &ends)), (directory.join(format!("{}#", name)), all.to_file_bytes())]
+            }
+            x => vec![(path, x.to_file_bytes())],
+        };
+        for (path, bytes) in files {
+            std::fs::write(&path, bytes).map_err(|x| format!("{}: {}", path.display(), x))?;
+        }
+    }
+    Payload::SymbolVector(VectorAttribute::NoAttribute, names.to_vec()).write_file(directory.join(".d"))
+}
+
+/// Type of the items of a nested column, when they're all simple vectors of one type
+fn nested_type(items: &[Payload]) -> Option<i8> {
+    let type_byte = items.first()?.type_byte();
+    Some(type_byte).filter(|x| Payload::vector_width(*x).is_some() && items.iter().all(|item| item.type_byte() == *x))
+}
+
+/// A vector file of 64 bit values, the layout of enumerations and nested column offsets
+fn long_file(type_byte: i8, attribute: VectorAttribute, values: &[u64]) -> Vec<u8> {
+    let mut ret_val = Vec::with_capacity(VECTOR_HEADER_LEN + 8 * values.len());
+    ret_val.extend_from_slice(&VECTOR_HEADER);
+    ret_val.extend_from_slice(&[type_byte as u8, attribute as u8, 0, 0, 0, 0]);
+    ret_val.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    values.iter().for_each(|x| ret_val.extend_from_slice(&x.to_le_bytes()));
+    ret_val
+}
+
+pub(crate) fn map_file(path: &Path) -> Result<Mmap, String> {
+    let file = File::open(path).map_err(|x| format!("{}: {}", path.display(), x))?;
+    // Safety: HDB files are written once and not modified while mapped, as q itself assumes
+    unsafe { Mmap::map(&file) }.map_err(|x| format!("{}: {}", path.display(), x))
+}
+
+/// A column file, mapped, or read a block at a time when written with compression
+pub(crate) enum ColumnFile {
+    Mapped(Mmap),
+    Compressed(CompressedFile),
+}
+
+impl ColumnFile {
+    /// Length of the (uncompressed) contents
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            ColumnFile::Mapped(x) => x.len(),
+            ColumnFile::Compressed(x) => x.len(),
+        }
+    }
+
+    /// Bytes in `range`, borrowed from the map or decompressed from only the blocks it overlaps
+    pub(crate) fn bytes(&self, range: Range<usize>) -> Result<Cow<'_, [u8]>, String> {
+        match self {
+            ColumnFile::Mapped(x) => x.get(range).map(Cow::Borrowed).ok_or_else(|| String::from("Incomplete q data file")),
+            ColumnFile::Compressed(x) => x.read(range).map(Cow::Owned),
+        }
+    }
+
+    /// The whole contents, for files holding something other than a vector
+    pub(crate) fn all(&self) -> Result<Cow<'_, [u8]>, String> {
+        match self {
+            ColumnFile::Mapped(x) => Ok(Cow::Borrowed(x)),
+            ColumnFile::Compressed(x) => x.read_all().map(Cow::Owned),
+        }
+    }
+
+    /// Type, attribute and count of a vector file, `None` if the file holds any other object
+    pub(crate) fn vector_header(&self) -> Result<Option<(i8, u8, usize)>, String> {
+        let header = self.bytes(0..VECTOR_HEADER_LEN.min(self.len()))?;
+        if header.get(0..2) != Some(&VECTOR_HEADER[..]) {
+            return Ok(None);
+        }
+        let (type_byte, attribute, count, _) = vector_file_parts(&header)?;
+        Ok(Some((type_byte, attribute, count)))
+    }
+
+    /// Data of the vector items in `items`, each `width` bytes
+    pub(crate) fn items(&self, width: usize, items: Range<usize>) -> Result<Cow<'_, [u8]>, String> {
+        self.bytes(VECTOR_HEADER_LEN + items.start * width..VECTOR_HEADER_LEN + items.end * width)
+    }
+}
+
+pub(crate) fn read_column_file(path: &Path) -> Result<ColumnFile, String> {
+    let map = map_file(path)?;
+    match is_compressed(&map) {
+        true => CompressedFile::from_map(map).map(ColumnFile::Compressed).map_err(|x| format!("{}: {}", path.display(), x)),
+        false => Ok(ColumnFile::Mapped(map)),
+    }
+}
+
+/// The first `count` little endian 64 bit values of `data`, `None` if there are fewer
+pub(crate) fn long_values(data: &[u8], count: usize) -> Option<impl Iterator<Item = u64> + '_> {
+    let data = data.get(..count.checked_mul(8)?)?;
+    Some(data.chunks_exact(8).map(|x| u64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]])))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::builder::table;
+    use crate::splayed::{read_splayed, read_sym, write_splayed, SplayedTable};
+    use crate::compressed::Algorithm;
+    use crate::fixtures::{compressed_file, kx, symbols};
+
+    fn vector_file(type_byte: u8, attribute: u8, count: u64, data: &[u64]) -> Vec<u8> {
+        let mut ret_val = vec![0xfe, 0x20, type_byte, attribute, 0, 0, 0, 0];
+        ret_val.extend_from_slice(&count.to_le_bytes());
+        data.iter().for_each(|x| ret_val.extend_from_slice(&x.to_le_bytes()));
+        ret_val
+    }
+
+    #[test]
+    pub fn test_read_splayed() {
+        let root = std::env::temp_dir().join(format!("iron_kdb_splayed_{}", std::process::id()));
+        let directory = root.join("trade");
+        std::fs::create_dir_all(&directory).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["IBM", "AAPL"])).write_file(root.join("sym")).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["sym", "px", "id"])).write_file(directory.join(".d")).unwrap();
+        std::fs::write(directory.join("sym"), vector_file(20, 3, 3, &[1, 1, 0])).unwrap();
+        Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, 2.5, 3.5]).write_file(directory.join("px")).unwrap();
+        std::fs::write(directory.join("id"), vector_file(87, 0, 3, &[2, 2, 5])).unwrap();
+        Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("abxyz")).write_file(directory.join("id#")).unwrap();
+
+        let expected = Payload::Table(VectorAttribute::NoAttribute, Box::new(Payload::Dictionary(
+            Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["sym", "px", "id"]))),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![
+                Payload::SymbolVector(VectorAttribute::Parted, symbols(&["AAPL", "AAPL", "IBM"])),
+                Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5, 2.5, 3.5]),
+                Payload::List(VectorAttribute::NoAttribute, vec![
+                    Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("ab")),
+                    Payload::CharVector(VectorAttribute::NoAttribute, KdbString::new()),
+                    Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("xyz"))])])))));
+        assert_eq!(read_splayed(&directory, &root).unwrap(), expected);
+
+        let table = SplayedTable::open(&directory).unwrap();
+        assert_eq!(table.columns(), ["sym", "px", "id"]);
+        assert_eq!(table.row_count().unwrap(), 3);
+        assert_eq!(table.read_column_rows("id", &[], 1..3).unwrap(), Payload::List(VectorAttribute::NoAttribute, vec![
+            Payload::CharVector(VectorAttribute::NoAttribute, KdbString::new()),
+            Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("xyz"))]));
+        assert!(table.read_column("size", &[]).is_err());
+        assert!(table.read_column("sym", &symbols(&["IBM"])).is_err());
+
+        // Columns that aren't simple vectors are stored as objects
+        let notes = root.join("notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["note"])).write_file(notes.join(".d")).unwrap();
+        Payload::List(VectorAttribute::NoAttribute, vec![Payload::Long(1), Payload::Float(2.5)]).write_file(notes.join("note")).unwrap();
+        let table = SplayedTable::open(&notes).unwrap();
+        assert_eq!(table.read_column_rows("note", &[], 1..2).unwrap(), Payload::List(VectorAttribute::NoAttribute, vec![Payload::Float(2.5)]));
+        assert!(table.read_column_rows("note", &[], 1..3).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn test_read_compressed_rows() {
+        let directory = std::env::temp_dir().join(format!("iron_kdb_compressed_splayed_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["px"])).write_file(directory.join(".d")).unwrap();
+        let px = Payload::LongVector(VectorAttribute::NoAttribute, (0..2000).collect()).to_file_bytes();
+        // The last of four blocks is corrupt, so reading rows only works if it's left alone
+        let bytes = compressed_file(Algorithm::Kx, 12, &px, |x| if x.len() < 4096 { vec![0; 4] } else { kx(x) });
+        std::fs::write(directory.join("px"), bytes).unwrap();
+
+        let table = SplayedTable::open(&directory).unwrap();
+        assert_eq!(table.row_count().unwrap(), 2000);
+        assert_eq!(table.read_column_rows("px", &[], 500..503).unwrap(), Payload::LongVector(VectorAttribute::NoAttribute, vec![500, 501, 502]));
+        assert!(table.read_column("px", &[]).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    pub fn test_write_splayed() {
+        let root = std::env::temp_dir().join(format!("iron_kdb_write_splayed_{}", std::process::id()));
+        let directory = root.join("quote");
+        std::fs::create_dir_all(&root).unwrap();
+        Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["IBM"])).write_file(root.join("sym")).unwrap();
+        let quote = table(&["sym", "bid", "venue", "notes"], vec![
+            Payload::SymbolVector(VectorAttribute::Grouped, symbols(&["MSFT", "IBM", "MSFT"])),
+            Payload::FloatVector(VectorAttribute::Sorted, vec![1.0, 1.5, 2.0]),
+            Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["X", "Y", "X"])),
+            Payload::List(VectorAttribute::NoAttribute, vec![
+                Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("ab")),
+                Payload::CharVector(VectorAttribute::NoAttribute, KdbString::new()),
+                Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from("xyz"))])]);
+        write_splayed(&directory, &root, &quote).unwrap();
+        assert_eq!(read_sym(&root).unwrap(), symbols(&["IBM", "MSFT", "X", "Y"]));
+        assert_eq!(std::fs::read(directory.join("sym")).unwrap()[0..4], [0xfe, 0x20, 20, 4]);
+        assert_eq!(read_splayed(&directory, &root).unwrap(), quote);
+
+        let ragged = table(&["sym", "bid"], vec![
+            Payload::SymbolVector(VectorAttribute::NoAttribute, symbols(&["AMZN"])),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0, 2.0])]);
+        assert!(write_splayed(root.join("ragged"), &root, &ragged).is_err());
+        assert!(!root.join("ragged").exists());
+        assert_eq!(read_sym(&root).unwrap(), symbols(&["IBM", "MSFT", "X", "Y"]));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
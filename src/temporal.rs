@@ -0,0 +1,238 @@
+/// Days from 1970.01.01 to q's epoch 2000.01.01
+pub(crate) const KDB_EPOCH_DAYS: i32 = 10957;
+pub(crate) const NANOS_PER_DAY: i64 = 86_400_000_000_000;
+
+/// Year, month and day of a q date (days since 2000.01.01)
+pub(crate) fn civil_from_days(date: i32) -> (i32, u32, u32) {
+    // Howard Hinnant's civil_from_days, with eras starting on 0000.03.01
+    let z = date as i64 + KDB_EPOCH_DAYS as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+    (year, month, day)
+}
+
+/// q date (days since 2000.01.01) of a year, month and day, the inverse of `civil_from_days`
+pub(crate) fn days_from_civil(year: i32, month: u32, day: u32) -> i32 {
+    let year = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146097 + day_of_era - 719468) as i32 - KDB_EPOCH_DAYS
+}
+
+/// Parses `2024.01.31` into a q date, checking the day exists
+pub(crate) fn parse_date(text: &str) -> Option<i32> {
+    parse_civil(text, '.')
+}
+
+#[cfg(feature = "json")]
+/// Parses ISO 8601 `2024-01-31` into a q date
+pub(crate) fn parse_iso_date(text: &str) -> Option<i32> {
+    parse_civil(text, '-')
+}
+
+fn parse_civil(text: &str, separator: char) -> Option<i32> {
+    let mut parts = text.split(separator);
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    let date = days_from_civil(year, month, day);
+    Some(date).filter(|x| civil_from_days(*x) == (year, month, day))
+}
+
+/// Parses `2024.01` into a q month (months since 2000.01)
+pub(crate) fn parse_month(text: &str) -> Option<i32> {
+    parse_year_month(text, '.')
+}
+
+#[cfg(feature = "json")]
+/// Parses ISO 8601 `2024-01` into a q month
+pub(crate) fn parse_iso_month(text: &str) -> Option<i32> {
+    parse_year_month(text, '-')
+}
+
+fn parse_year_month(text: &str, separator: char) -> Option<i32> {
+    let (year, month) = text.split_once(separator)?;
+    if year.len() != 4 || month.len() != 2 {
+        return None;
+    }
+    let (year, month) = (year.parse::<i32>().ok()?, month.parse::<i32>().ok()?);
+    Some((year - 2000) * 12 + month - 1).filter(|_| (1..=12).contains(&month))
+}
+
+/// Formats a q date as `2024.01.31`
+pub(crate) fn format_date(date: i32) -> String {
+    let (year, month, day) = civil_from_days(date);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// Formats a q month as `2024.01`
+pub(crate) fn format_month(month: i32) -> String {
+    format!("{:04}.{:02}", 2000 + month.div_euclid(12), month.rem_euclid(12) + 1)
+}
+
+/// ISO 8601 form of a q month, `2024-01`
+pub(crate) fn iso_month(month: i32) -> String {
+    format!("{:04}-{:02}", 2000 + month.div_euclid(12), month.rem_euclid(12) + 1)
+}
+
+/// ISO 8601 form of a q date, `2024-01-31`
+pub(crate) fn iso_date(date: i32) -> String {
+    let (year, month, day) = civil_from_days(date);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// A q timestamp (nanoseconds since 2000.01.01) as `2024.01.31D01:00:00.000000000`, or as
+/// `2024-01-31T01:00:00.000000000` in ISO 8601
+pub(crate) fn format_timestamp(nanos: i64, iso: bool) -> String {
+    let date = nanos.div_euclid(NANOS_PER_DAY) as i32;
+    let time = clock(nanos.rem_euclid(NANOS_PER_DAY), 1_000_000_000, 9);
+    match iso {
+        true => format!("{}T{}", iso_date(date), time),
+        false => format!("{}D{}", format_date(date), time),
+    }
+}
+
+/// A q datetime (fractional days since 2000.01.01) to the millisecond, `2024.01.31T01:00:00.000`
+/// or `2024-01-31T01:00:00.000` in ISO 8601
+pub(crate) fn format_datetime(days: f64, iso: bool) -> String {
+    let millis = (days * 86_400_000.0).round() as i64;
+    let date = millis.div_euclid(86_400_000) as i32;
+    let date = if iso { iso_date(date) } else { format_date(date) };
+    format!("{}T{}", date, clock(millis.rem_euclid(86_400_000), 1000, 3))
+}
+
+/// A q timespan as `0D01:00:00.000000000`
+pub(crate) fn format_timespan(nanos: i64) -> String {
+    let sign = if nanos < 0 { "-" } else { "" };
+    let nanos = nanos.unsigned_abs() as i64;
+    format!("{}{}D{}", sign, nanos / NANOS_PER_DAY, clock(nanos % NANOS_PER_DAY, 1_000_000_000, 9))
+}
+
+pub(crate) fn format_minute(minutes: i64) -> String {
+    let sign = if minutes < 0 { "-" } else { "" };
+    format!("{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60)
+}
+
+pub(crate) fn format_second(seconds: i64) -> String {
+    let sign = if seconds < 0 { "-" } else { "" };
+    format!("{}{}", sign, clock(seconds.abs(), 1, 0))
+}
+
+pub(crate) fn format_time(millis: i64) -> String {
+    let sign = if millis < 0 { "-" } else { "" };
+    format!("{}{}", sign, clock(millis.abs(), 1000, 3))
+}
+
+/// `hh:mm:ss` of a positive count of `per_second` units, with `digits` of the fraction
+fn clock(units: i64, per_second: i64, digits: usize) -> String {
+    let seconds = units / per_second;
+    let text = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+    match digits {
+        0 => text,
+        _ => format!("{}.{:0digits$}", text, units % per_second, digits = digits),
+    }
+}
+
+#[cfg(feature = "json")]
+/// Parses `2024-01-31T01:00:00.000000000`, the ISO 8601 form of `format_timestamp`
+pub(crate) fn parse_iso_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once('T')?;
+    Some(parse_iso_date(date)? as i64 * NANOS_PER_DAY + parse_clock(time, 1_000_000_000, 9).filter(|x| *x < NANOS_PER_DAY)?)
+}
+
+#[cfg(feature = "json")]
+/// Parses `0D01:00:00.000000000`, the inverse of `format_timespan`
+pub(crate) fn parse_timespan(text: &str) -> Option<i64> {
+    let (sign, text) = sign(text);
+    let (days, time) = text.split_once('D')?;
+    let time = parse_clock(time, 1_000_000_000, 9).filter(|x| *x < NANOS_PER_DAY)?;
+    Some(sign * days.parse::<i64>().ok()?.checked_mul(NANOS_PER_DAY)?.checked_add(time)?)
+}
+
+#[cfg(feature = "json")]
+pub(crate) fn parse_minute(text: &str) -> Option<i64> {
+    let (sign, text) = sign(text);
+    let (hours, minutes) = text.split_once(':')?;
+    Some(sign * (hours.parse::<i64>().ok()? * 60 + sixty(minutes)?))
+}
+
+#[cfg(feature = "json")]
+pub(crate) fn parse_second(text: &str) -> Option<i64> {
+    let (sign, text) = sign(text);
+    Some(sign * parse_clock(text, 1, 0)?)
+}
+
+#[cfg(feature = "json")]
+pub(crate) fn parse_time(text: &str) -> Option<i64> {
+    let (sign, text) = sign(text);
+    Some(sign * parse_clock(text, 1000, 3)?)
+}
+
+#[cfg(feature = "json")]
+fn sign(text: &str) -> (i64, &str) {
+    match text.strip_prefix('-') {
+        Some(text) => (-1, text),
+        None => (1, text),
+    }
+}
+
+#[cfg(feature = "json")]
+/// Minutes or seconds, two digits below 60
+fn sixty(text: &str) -> Option<i64> {
+    Some(text).filter(|x| x.len() == 2 && x.bytes().all(|x| x.is_ascii_digit())).and_then(|x| x.parse().ok()).filter(|x| (0..60).contains(x))
+}
+
+#[cfg(feature = "json")]
+/// Units of `hh:mm:ss` with exactly `digits` of fraction, the inverse of `clock`
+fn parse_clock(text: &str, per_second: i64, digits: usize) -> Option<i64> {
+    let (text, fraction) = match digits {
+        0 => (text, 0),
+        _ => {
+            let (text, fraction) = text.split_once('.')?;
+            (text, Some(fraction).filter(|x| x.len() == digits && x.bytes().all(|x| x.is_ascii_digit()))?.parse().ok()?)
+        }
+    };
+    let mut parts = text.split(':');
+    let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || !hours.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let seconds = hours.parse::<i64>().ok()? * 3600 + sixty(minutes)? * 60 + sixty(seconds)?;
+    seconds.checked_mul(per_second)?.checked_add(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::temporal::{civil_from_days, days_from_civil, format_date, format_month, parse_date, parse_month};
+
+    #[test]
+    pub fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (2000, 1, 1));
+        assert_eq!(civil_from_days(59), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1999, 12, 31));
+        assert_eq!(civil_from_days(8796), (2024, 1, 31));
+    }
+
+    #[test]
+    pub fn test_parse() {
+        assert_eq!(days_from_civil(2024, 1, 31), 8796);
+        assert_eq!(days_from_civil(1999, 12, 31), -1);
+        assert_eq!(parse_date("2024.01.31"), Some(8796));
+        assert_eq!(parse_date("2023.02.29"), None);
+        assert_eq!(parse_month("2000.02"), Some(1));
+        assert_eq!(parse_month("2024.13"), None);
+        assert_eq!(format_date(8796), "2024.01.31");
+        assert_eq!(format_month(-1), "1999.12");
+    }
+}
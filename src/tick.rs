@@ -0,0 +1,393 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use crate::KdbConnection;
+use crate::codec::{KdbString, Payload, SynchronisationType, VectorAttribute};
+
+/// One `(`upd; `table; data)` message published by a tickerplant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: String,
+    /// Usually a list of column vectors, or a table if the tickerplant publishes them
+    pub data: Payload,
+}
+
+type UpdateCallback = Box<dyn FnMut(&Update) + Send>;
+
+/// Client side of kdb+tick's `.u.sub`/`upd` protocol.
+pub struct Subscriber<R: Read, W: Write> {
+    connection: KdbConnection<R, W>,
+    tables: Vec<String>,
+    /// Updates that arrived while waiting for the reply to a sync call
+    pending: VecDeque<Update>,
+    /// Set once iterating has yielded an error, after which it ends
+    failed: bool,
+    callbacks: HashMap<String, UpdateCallback>,
+    default_callback: Option<UpdateCallback>,
+}
+
+impl Subscriber<TcpStream, TcpStream> {
+    pub fn connect(address: &str, user: &str, pwd: &str) -> Result<Subscriber<TcpStream, TcpStream>, String> {
+        let mut connection = KdbConnection::new(address).map_err(|x| x.to_string())?;
+        connection.connect(user, pwd).map_err(|x| x.to_string())?;
+        Ok(Subscriber::new(connection))
+    }
+}
+
+impl<R: Read, W: Write> Subscriber<R, W> {
+    pub fn new(connection: KdbConnection<R, W>) -> Subscriber<R, W> {
+        Subscriber { connection, tables: Vec::new(), pending: VecDeque::new(), failed: false, callbacks: HashMap::new(), default_callback: None }
+    }
+
+    /// Calls `.u.sub[table; syms]` for every table, or for every table in `.u.t` if `tables` is
+    /// empty. An empty `syms` subscribes to all symbols. Returns each table's schema. Updates to
+    /// tables subscribed first that arrive meanwhile are kept for `next_update`.
+    pub fn subscribe(&mut self, tables: &[&str], syms: &[&str]) -> Result<Vec<(String, Payload)>, String> {
+        let tables = if tables.is_empty() {
+            match self.call(&query(".u.t"))?.resolve_symbols() {
+                Payload::SymbolVector(_, x) => x.iter().map(|x| x.to_string()).collect(),
+                x => return Err(format!("Unexpected .u.t {:?}", x)),
+            }
+        } else {
+            tables.iter().map(|x| x.to_string()).collect::<Vec<String>>()
+        };
+        let syms = if syms.is_empty() { symbol("") } else { symbols(syms) };
+
+        let mut schemas = Vec::with_capacity(tables.len());
+        for table in tables {
+            let reply = self.call(&Payload::List(VectorAttribute::NoAttribute, vec![symbol(".u.sub"), symbol(&table), syms.clone()]))?;
+            match reply {
+                Payload::List(_, mut x) if x.len() == 2 => {
+                    let schema = x.pop().unwrap_or(Payload::Nil);
+                    schemas.push((table.clone(), schema));
+                }
+                Payload::Error(x) => return Err(format!("Failed to subscribe to {}: {}", table, x)),
+                x => return Err(format!("Unexpected .u.sub reply {:?}", x)),
+            }
+            self.tables.push(table);
+        }
+        Ok(schemas)
+    }
+
+    /// Number of messages in the tickerplant's log and its path, `(.u.i; .u.L)`, for replaying it
+    pub fn log_position(&mut self) -> Result<(u64, String), String> {
+        match self.call(&query("(.u.i;.u.L)"))? {
+            Payload::List(_, x) => match x.as_slice() {
+                [Payload::Long(count), Payload::Symbol(path)] => Ok((*count, path.to_string())),
+                [Payload::Int(count), Payload::Symbol(path)] => Ok((*count as u64, path.to_string())),
+                _ => Err(format!("Unexpected log position {:?}", x)),
+            },
+            x => Err(format!("Unexpected log position {:?}", x)),
+        }
+    }
+
+    /// Callback for updates to `table`, replacing any previous one
+    pub fn on_update<F: FnMut(&Update) + Send + 'static>(&mut self, table: &str, callback: F) {
+        self.callbacks.insert(table.to_string(), Box::new(callback));
+    }
+
+    /// Callback for updates to tables without their own callback
+    pub fn on_any_update<F: FnMut(&Update) + Send + 'static>(&mut self, callback: F) {
+        self.default_callback = Some(Box::new(callback));
+    }
+
+    /// Blocks until the next update, any other message is given to the connection's request handler
+    pub fn next_update(&mut self) -> Result<Update, String> {
+        loop {
+            if let Some(update) = self.pending.pop_front() {
+                return Ok(update);
+            }
+            let (message_type, payload) = self.connection.receive_message()?;
+            self.accept(message_type, payload)?;
+        }
+    }
+
+    /// Sync call on the subscribed socket, queueing rather than dropping updates that arrive
+    /// before its reply
+    fn call(&mut self, payload: &Payload) -> Result<Payload, String> {
+        self.connection.write_message(SynchronisationType::Sync, payload)?;
+        loop {
+            match self.connection.receive_message()? {
+                (SynchronisationType::Response, payload) => return Ok(payload),
+                (message_type, payload) => self.accept(message_type, payload)?,
+            }
+        }
+    }
+
+    /// Queues an update, any other message is given to the connection's request handler
+    fn accept(&mut self, message_type: SynchronisationType, payload: Payload) -> Result<(), String> {
+        match (message_type, parse_update(payload)) {
+            (SynchronisationType::Async, Ok(update)) => {
+                self.pending.push_back(update);
+                Ok(())
+            }
+            (message_type, Ok(update)) => self.connection.handle_request(message_type, to_payload(update)),
+            (message_type, Err(payload)) => self.connection.handle_request(message_type, payload),
+        }
+    }
+
+    /// Waits for the next update and passes it to its table's callback
+    pub fn dispatch_next(&mut self) -> Result<(), String> {
+        let update = self.next_update()?;
+        if let Some(callback) = self.callbacks.get_mut(&update.table) {
+            callback(&update);
+        } else if let Some(callback) = self.default_callback.as_mut() {
+            callback(&update);
+        }
+        Ok(())
+    }
+
+    /// Dispatches updates until the connection fails
+    pub fn run(&mut self) -> Result<(), String> {
+        loop {
+            self.dispatch_next()?;
+        }
+    }
+
+    /// Removes this handle from the tickerplant's subscriptions with `.u.del`
+    pub fn unsubscribe(&mut self) -> Result<(), String> {
+        for table in std::mem::take(&mut self.tables) {
+            if let Payload::Error(x) = self.call(&query(&format!(".u.del[`{};.z.w]", table)))? {
+                return Err(format!("Failed to unsubscribe from {}: {}", table, x));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn into_connection(self) -> KdbConnection<R, W> {
+        self.connection
+    }
+}
+
+impl<R: Read, W: Write> Iterator for Subscriber<R, W> {
+    type Item = Result<Update, String>;
+
+    /// Stops after the first error, which is usually the connection closing
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_update();
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+fn query(text: &str) -> Payload {
+    Payload::CharVector(VectorAttribute::NoAttribute, KdbString::from(text))
+}
+
+fn symbol(value: &str) -> Payload {
+    Payload::Symbol(KdbString::from(value))
+}
+
+fn symbols(values: &[&str]) -> Payload {
+    Payload::SymbolVector(VectorAttribute::NoAttribute, values.iter().map(|x| KdbString::from(*x)).collect())
+}
+
+/// Recognises `(`upd; `table; data)`, handing anything else back untouched
+fn parse_update(payload: Payload) -> Result<Update, Payload> {
+    match payload {
+        Payload::List(attribute, mut x) => {
+            if let [Payload::Symbol(function), Payload::Symbol(table), _] = x.as_slice() {
+                if function == "upd" {
+                    let table = table.to_string();
+                    let data = x.pop().unwrap_or(Payload::Nil);
+                    return Ok(Update { table, data });
+                }
+            }
+            Err(Payload::List(attribute, x))
+        }
+        x => Err(x),
+    }
+}
+
+fn to_payload(update: Update) -> Payload {
+    Payload::List(VectorAttribute::NoAttribute, vec![symbol("upd"), symbol(&update.table), update.data])
+}
+
+#[cfg(feature = "tokio")]
+pub use self::asynchronous::AsyncSubscriber;
+
+#[cfg(feature = "tokio")]
+mod asynchronous {
+    use std::collections::VecDeque;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio_stream::wrappers::ReceiverStream;
+    use crate::async_connection::AsyncKdbConnection;
+    use crate::codec::{Payload, SynchronisationType, VectorAttribute};
+    use crate::tick::{parse_update, query, symbol, symbols, to_payload, Update};
+
+    /// Tokio flavour of `Subscriber` whose updates can be consumed as a `Stream`.
+    pub struct AsyncSubscriber<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> {
+        connection: AsyncKdbConnection<R, W>,
+        tables: Vec<String>,
+        pending: VecDeque<Update>,
+    }
+
+    impl<R: AsyncRead + Unpin + Send + 'static, W: AsyncWrite + Unpin + Send + 'static> AsyncSubscriber<R, W> {
+        pub fn new(connection: AsyncKdbConnection<R, W>) -> AsyncSubscriber<R, W> {
+            AsyncSubscriber { connection, tables: Vec::new(), pending: VecDeque::new() }
+        }
+
+        /// Calls `.u.sub[table; syms]` for every table, see `Subscriber::subscribe`
+        pub async fn subscribe(&mut self, tables: &[&str], syms: &[&str]) -> Result<Vec<(String, Payload)>, String> {
+            let tables = if tables.is_empty() {
+                match self.call(&query(".u.t")).await?.resolve_symbols() {
+                    Payload::SymbolVector(_, x) => x.iter().map(|x| x.to_string()).collect(),
+                    x => return Err(format!("Unexpected .u.t {:?}", x)),
+                }
+            } else {
+                tables.iter().map(|x| x.to_string()).collect::<Vec<String>>()
+            };
+            let syms = if syms.is_empty() { symbol("") } else { symbols(syms) };
+            let mut schemas = Vec::with_capacity(tables.len());
+            for table in tables {
+                let reply = self.call(&Payload::List(VectorAttribute::NoAttribute, vec![symbol(".u.sub"), symbol(&table), syms.clone()])).await?;
+                match reply {
+                    Payload::List(_, mut x) if x.len() == 2 => schemas.push((table.clone(), x.pop().unwrap_or(Payload::Nil))),
+                    Payload::Error(x) => return Err(format!("Failed to subscribe to {}: {}", table, x)),
+                    x => return Err(format!("Unexpected .u.sub reply {:?}", x)),
+                }
+                self.tables.push(table);
+            }
+            Ok(schemas)
+        }
+
+        /// Waits for the next update, any other message is given to the connection's request handler
+        pub async fn next_update(&mut self) -> Result<Update, String> {
+            loop {
+                if let Some(update) = self.pending.pop_front() {
+                    return Ok(update);
+                }
+                let (message_type, payload) = self.connection.receive_message().await?;
+                self.accept(message_type, payload).await?;
+            }
+        }
+
+        /// See `Subscriber::call`
+        async fn call(&mut self, payload: &Payload) -> Result<Payload, String> {
+            self.connection.write_message(SynchronisationType::Sync, payload).await?;
+            loop {
+                match self.connection.receive_message().await? {
+                    (SynchronisationType::Response, payload) => return Ok(payload),
+                    (message_type, payload) => self.accept(message_type, payload).await?,
+                }
+            }
+        }
+
+        async fn accept(&mut self, message_type: SynchronisationType, payload: Payload) -> Result<(), String> {
+            match (message_type, parse_update(payload)) {
+                (SynchronisationType::Async, Ok(update)) => {
+                    self.pending.push_back(update);
+                    Ok(())
+                }
+                (message_type, Ok(update)) => self.connection.handle_request(message_type, to_payload(update)).await,
+                (message_type, Err(payload)) => self.connection.handle_request(message_type, payload).await,
+            }
+        }
+
+        /// Removes this handle from the tickerplant's subscriptions with `.u.del`
+        pub async fn unsubscribe(&mut self) -> Result<(), String> {
+            for table in std::mem::take(&mut self.tables) {
+                if let Payload::Error(x) = self.call(&query(&format!(".u.del[`{};.z.w]", table))).await? {
+                    return Err(format!("Failed to unsubscribe from {}: {}", table, x));
+                }
+            }
+            Ok(())
+        }
+
+        /// Reads updates on a spawned task, the stream ends after the first error
+        pub fn into_stream(mut self, buffer: usize) -> ReceiverStream<Result<Update, String>> {
+            let (sender, receiver) = tokio::sync::mpsc::channel(buffer);
+            tokio::spawn(async move {
+                loop {
+                    let update = self.next_update().await;
+                    let failed = update.is_err();
+                    if sender.send(update).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            ReceiverStream::new(receiver)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use crate::KdbConnection;
+    use crate::codec::{encode_message, KdbString, Payload, SynchronisationType, VectorAttribute};
+    use crate::fixtures::{sym, upd};
+    use crate::tick::{Subscriber, Update};
+
+    fn subscriber(messages: &[(SynchronisationType, Payload)]) -> Subscriber<Cursor<Vec<u8>>, Vec<u8>> {
+        let bytes = messages.iter().flat_map(|(message_type, payload)| encode_message(*message_type, payload)).collect();
+        Subscriber::new(KdbConnection::from_streams(Cursor::new(bytes), Vec::new()))
+    }
+
+    #[test]
+    pub fn test_subscribe() {
+        let schema = Payload::Table(VectorAttribute::NoAttribute, Box::new(Payload::Dictionary(
+            Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("px")])),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![])])))));
+        let mut subscriber = subscriber(&[(SynchronisationType::Response, Payload::List(VectorAttribute::NoAttribute, vec![sym("trade"), schema.clone()]))]);
+
+        assert_eq!(subscriber.subscribe(&["trade"], &["AAPL"]).unwrap(), vec![(String::from("trade"), schema)]);
+        let request = Payload::List(VectorAttribute::NoAttribute, vec![sym(".u.sub"), sym("trade"),
+            Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("AAPL")])]);
+        assert_eq!(subscriber.connection.tcp_connection_write, encode_message(SynchronisationType::Sync, &request));
+    }
+
+    #[test]
+    pub fn test_updates_while_subscribing() {
+        let reply = |table: &str| (SynchronisationType::Response, Payload::List(VectorAttribute::NoAttribute, vec![sym(table), Payload::Nil]));
+        let mut subscriber = subscriber(&[
+            reply("trade"),
+            (SynchronisationType::Async, upd("trade", 1.5)),
+            reply("quote"),
+            (SynchronisationType::Async, upd("quote", 2.5)),
+            (SynchronisationType::Response, Payload::List(VectorAttribute::NoAttribute, vec![Payload::Long(2), sym(":tp")])),
+        ]);
+
+        assert_eq!(subscriber.subscribe(&["trade", "quote"], &[]).unwrap().len(), 2);
+        assert_eq!(subscriber.log_position().unwrap(), (2, String::from(":tp")));
+        assert_eq!(subscriber.next_update().unwrap().table, "trade");
+        assert_eq!(subscriber.next_update().unwrap().table, "quote");
+        assert!(subscriber.next_update().is_err());
+    }
+
+    #[test]
+    pub fn test_iterate_until_closed() {
+        let subscriber = subscriber(&[(SynchronisationType::Async, upd("trade", 1.5)), (SynchronisationType::Async, upd("quote", 2.5))]);
+        let updates = subscriber.collect::<Vec<_>>();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[1].as_ref().unwrap().table, "quote");
+        assert!(updates[2].is_err());
+    }
+
+    #[test]
+    pub fn test_dispatch_updates() {
+        let mut subscriber = subscriber(&[
+            (SynchronisationType::Async, upd("trade", 1.5)),
+            (SynchronisationType::Async, Payload::List(VectorAttribute::NoAttribute, vec![sym(".u.end"), Payload::Date(8766)])),
+            (SynchronisationType::Async, upd("quote", 2.5)),
+        ]);
+        let trades = Arc::new(Mutex::new(Vec::new()));
+        let others = Arc::new(Mutex::new(Vec::new()));
+        let recorded = trades.clone();
+        subscriber.on_update("trade", move |x| recorded.lock().unwrap().push(x.clone()));
+        let recorded = others.clone();
+        subscriber.on_any_update(move |x| recorded.lock().unwrap().push(x.table.clone()));
+
+        subscriber.dispatch_next().unwrap();
+        subscriber.dispatch_next().unwrap();
+        assert!(subscriber.dispatch_next().is_err());
+
+        assert_eq!(*trades.lock().unwrap(), vec![Update { table: String::from("trade"),
+            data: Payload::List(VectorAttribute::NoAttribute, vec![Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.5])]) }]);
+        assert_eq!(*others.lock().unwrap(), vec![String::from("quote")]);
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use crate::codec::{Payload, VectorAttribute};
+
+/// An invariant a payload breaks, found by `Payload::validate`. The path leads from the payload,
+/// `x`, to the value at fault: `x[2]` is an item of a list, `x.px` a table column and `x.key` and
+/// `x.value` the sides of a dictionary.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl Payload {
+    /// Checks everything q relies on when it reads a payload: tables are symbols and a list of
+    /// vectors of one length, dictionaries have as many keys as values, chars fit in a byte, symbols
+    /// hold no null byte and `s#`, `u#` and `p#` vectors are sorted, distinct and grouped. Every
+    /// violation is reported, not just the first. Connections, journals and `write_file` refuse
+    /// payloads that fail it.
+    pub fn validate(&self) -> Result<(), Vec<Violation>> {
+        let mut violations = Vec::new();
+        check(self, &Path::Root, &mut violations);
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+
+    /// `validate` with the violations as one message, for refusing to send or write a payload
+    pub(crate) fn check(&self) -> Result<(), String> {
+        self.validate().map_err(|x| x.iter().map(Violation::to_string).collect::<Vec<_>>().join("; "))
+    }
+}
+
+/// Where a value sits, only written out as `x[2].px` when a violation is reported there
+enum Path<'a> {
+    Root,
+    Item(&'a Path<'a>, usize),
+    Field(&'a Path<'a>, &'a dyn Display),
+    /// A column without a name, `x.value[3]`
+    Column(&'a Path<'a>, usize),
+}
+
+impl Display for Path<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Path::Root => write!(f, "x"),
+            Path::Item(parent, index) => write!(f, "{}[{}]", parent, index),
+            Path::Field(parent, name) => write!(f, "{}.{}", parent, name),
+            Path::Column(parent, index) => write!(f, "{}.value[{}]", parent, index),
+        }
+    }
+}
+
+fn report(violations: &mut Vec<Violation>, path: &Path, message: String) {
+    violations.push(Violation { path: path.to_string(), message });
+}
+
+fn check(payload: &Payload, path: &Path, violations: &mut Vec<Violation>) {
+    // IPC counts are signed ints
+    if (0..98).contains(&payload.type_byte()) && payload.count() > i32::MAX as usize {
+        report(violations, path, format!("{} items are more than a message can hold", payload.count()));
+    }
+    match payload {
+        Payload::List(_, x) => x.iter().enumerate().for_each(|(index, item)| check(item, &Path::Item(path, index), violations)),
+        Payload::Char(x) if *x > '\u{ff}' => report(violations, path, format!("Char {:?} doesn't fit in a byte", x)),
+        Payload::Symbol(x) | Payload::Error(x) if x.as_bytes().contains(&0) => report(violations, path, String::from("Symbol holds a null byte")),
+        Payload::SymbolVector(_, x) => {
+            if let Some(index) = x.iter().position(|x| x.as_bytes().contains(&0)) {
+                report(violations, path, format!("Symbol {} holds a null byte", index));
+            }
+        }
+        Payload::InternedSymbolVector(_, t, x) => {
+            if let Some(index) = t.read(|symbols| x.iter().position(|x| symbols.name(*x).contains(&0))) {
+                report(violations, path, format!("Symbol {} holds a null byte", index));
+            }
+        }
+        Payload::Table(_, x) => table(x, path, violations),
+        Payload::Dictionary(keys, values) => {
+            for (side, x) in [("key", keys), ("value", values)].iter() {
+                let side_path = Path::Field(path, side);
+                if !(0..=98).contains(&x.type_byte()) {
+                    report(violations, &side_path, format!("Type {} is neither a list nor a table", x.type_byte()));
+                }
+                check(x, &side_path, violations);
+            }
+            if keys.count() != values.count() {
+                report(violations, path, format!("{} keys for {} values", keys.count(), values.count()));
+            }
+        }
+        _ => {}
+    }
+    if let Some(message) = attribute(payload) {
+        report(violations, path, message);
+    }
+}
+
+fn table(dictionary: &Payload, path: &Path, violations: &mut Vec<Violation>) {
+    let (names, columns) = match dictionary {
+        Payload::Dictionary(names, columns) => match (names.as_ref(), columns.as_ref()) {
+            (Payload::SymbolVector(_, names), Payload::List(_, columns)) => (names, columns),
+            (names, columns) => {
+                let message = format!("Table columns are types {} and {}, not symbols and a list", names.type_byte(), columns.type_byte());
+                return report(violations, path, message);
+            }
+        },
+        x => return report(violations, path, format!("Table holds type {}, not a dictionary", x.type_byte())),
+    };
+    if names.len() != columns.len() {
+        report(violations, path, format!("{} column names for {} columns", names.len(), columns.len()));
+    }
+    let mut seen = HashSet::new();
+    if let Some(name) = names.iter().find(|x| !seen.insert(*x)) {
+        report(violations, path, format!("Column {} appears twice", name));
+    }
+    let rows = columns.first().map(Payload::count).unwrap_or(0);
+    for (index, column) in columns.iter().enumerate() {
+        let column_path = match names.get(index) {
+            Some(name) => Path::Field(path, name),
+            None => Path::Column(path, index),
+        };
+        if !(0..98).contains(&column.type_byte()) {
+            report(violations, &column_path, format!("Type {} isn't a vector", column.type_byte()));
+        } else if column.count() != rows {
+            report(violations, &column_path, format!("{} rows but the first column has {}", column.count(), rows));
+        }
+        check(column, &column_path, violations);
+    }
+}
+
+/// What's wrong with the items of a vector with `s#`, `u#` or `p#`
+fn attribute(payload: &Payload) -> Option<String> {
+    let attribute = payload.attribute();
+    let message = match payload {
+        Payload::BoolVector(_, x) => keyed(attribute, x.iter().copied()),
+        Payload::GUIDVector(_, x) => keyed(attribute, x.iter().copied()),
+        Payload::ByteVector(_, x) => keyed(attribute, x.iter().copied()),
+        Payload::ShortVector(_, x) => keyed(attribute, x.iter().map(|x| *x as i16)),
+        Payload::IntVector(_, x) | Payload::MonthVector(_, x) | Payload::DateVector(_, x) | Payload::MinuteVector(_, x)
+            | Payload::SecondVector(_, x) | Payload::TimeVector(_, x) => keyed(attribute, x.iter().map(|x| *x as i32)),
+        Payload::LongVector(_, x) | Payload::TimestampVector(_, x) | Payload::TimeSpanVector(_, x) => keyed(attribute, x.iter().map(|x| *x as i64)),
+        Payload::RealVector(_, x) => keyed(attribute, x.iter().map(|x| float_key(*x as f64))),
+        Payload::FloatVector(_, x) => keyed(attribute, x.iter().map(|x| float_key(*x))),
+        Payload::DateTimeVector(_, x) => keyed(attribute, x.iter().map(|x| float_key(f64::from_bits(*x)))),
+        Payload::CharVector(_, x) => keyed(attribute, x.as_bytes().iter().copied()),
+        Payload::SymbolVector(_, x) => keyed(attribute, x.iter().map(|x| x.as_bytes())),
+        Payload::InternedSymbolVector(_, t, x) => t.read(|symbols| keyed(attribute, x.iter().map(|x| symbols.name(*x)))),
+        _ => None,
+    };
+    message.map(String::from)
+}
+
+/// Checks the attribute against the items in q's order
+fn keyed<K: Ord + Hash + Clone, I: Iterator<Item = K>>(attribute: VectorAttribute, keys: I) -> Option<&'static str> {
+    let mut seen = HashSet::new();
+    let mut previous = None;
+    for key in keys {
+        let broken = match attribute {
+            VectorAttribute::Sorted => matches!(&previous, Some(x) if *x > key),
+            VectorAttribute::Unique => !seen.insert(key.clone()),
+            // A value seen before must continue the run it started
+            VectorAttribute::Parted => previous.as_ref() != Some(&key) && !seen.insert(key.clone()),
+            VectorAttribute::NoAttribute | VectorAttribute::Grouped => return None,
+        };
+        if broken {
+            return Some(match attribute {
+                VectorAttribute::Sorted => "Items with s# aren't ascending",
+                VectorAttribute::Unique => "Items with u# repeat",
+                _ => "Equal items with p# aren't contiguous",
+            });
+        }
+        previous = Some(key);
+    }
+    None
+}
+
+/// Floats in q's order, null first and -0 equal to 0
+fn float_key(x: f64) -> i64 {
+    if x.is_nan() {
+        return i64::MIN;
+    }
+    let bits = (x + 0.0).to_bits() as i64;
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{KdbString, Payload, VectorAttribute};
+    use crate::builder::table;
+    use crate::validate::Violation;
+
+    fn violation(path: &str, message: &str) -> Violation {
+        Violation { path: String::from(path), message: String::from(message) }
+    }
+
+    #[test]
+    pub fn test_validate() {
+        let trades = table(&["sym", "px", "px"], vec![
+            Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("a"), KdbString::from("b\0")]),
+            Payload::FloatVector(VectorAttribute::NoAttribute, vec![1.0]),
+            Payload::Long(1)]);
+        let dictionary = Payload::Dictionary(
+            Box::new(Payload::SymbolVector(VectorAttribute::NoAttribute, vec![KdbString::from("a")])),
+            Box::new(Payload::List(VectorAttribute::NoAttribute, vec![Payload::Char('€'), Payload::Nil])));
+        let payload = Payload::List(VectorAttribute::NoAttribute, vec![Payload::Long(1), trades, dictionary]);
+        assert_eq!(payload.validate(), Err(vec![
+            violation("x[1]", "Column px appears twice"),
+            violation("x[1].sym", "Symbol 1 holds a null byte"),
+            violation("x[1].px", "1 rows but the first column has 2"),
+            violation("x[1].px", "Type -7 isn't a vector"),
+            violation("x[2].value[0]", "Char '€' doesn't fit in a byte"),
+            violation("x[2]", "1 keys for 2 values"),
+        ]));
+        assert!(payload.check().unwrap_err().starts_with("x[1]: Column px appears twice; x[1].sym: "));
+        assert!(Payload::Symbol(KdbString::from("a\0")).validate().is_err());
+        assert_eq!(Payload::LongVector(VectorAttribute::NoAttribute, vec![1, 2]).validate(), Ok(()));
+    }
+
+    #[test]
+    pub fn test_attributes() {
+        let longs = |attribute, x: Vec<i64>| Payload::LongVector(attribute, x.into_iter().map(|x| x as u64).collect());
+        assert!(longs(VectorAttribute::Sorted, vec![i64::MIN, -1, 0, 0, 3]).validate().is_ok());
+        assert!(longs(VectorAttribute::Sorted, vec![0, -1]).validate().is_err());
+        assert!(longs(VectorAttribute::Unique, vec![3, 1, 2]).validate().is_ok());
+        assert!(longs(VectorAttribute::Unique, vec![3, 1, 3]).validate().is_err());
+        assert!(longs(VectorAttribute::Parted, vec![3, 3, 1, 2, 2]).validate().is_ok());
+        assert!(longs(VectorAttribute::Parted, vec![3, 1, 3]).validate().is_err());
+        assert!(longs(VectorAttribute::Grouped, vec![3, 1, 3]).validate().is_ok());
+        assert!(Payload::FloatVector(VectorAttribute::Sorted, vec![f64::NAN, f64::NEG_INFINITY, -1.5, -0.0, 0.0, 2.0]).validate().is_ok());
+        assert!(Payload::FloatVector(VectorAttribute::Sorted, vec![1.0, f64::NAN]).validate().is_err());
+        let symbols = Payload::SymbolVector(VectorAttribute::Sorted, vec![KdbString::from("ab"), KdbString::from("b")]);
+        assert!(symbols.validate().is_ok());
+    }
+}